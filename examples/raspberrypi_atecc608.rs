@@ -8,9 +8,7 @@
 // $ ssh pi@${PI_IP_ADDR} "RUST_LOG=info ./raspberrypi_atecc608"
 use at_cryptoauth::memory::{Size, Slot, Zone};
 use at_cryptoauth::tngtls::{AES_KEY, AUTH_PRIVATE_KEY, SIGN_PRIVATE_KEY, USER_PRIVATE_KEY1};
-use at_cryptoauth::{AtCaClient, Block};
-use core::fmt::Debug;
-use embedded_hal::delay::DelayNs embedded_hal::i2c::{Read, Write};
+use at_cryptoauth::{AtCaClient, Block, Transport};
 use linux_embedded_hal::Delay;
 use linux_embedded_hal::I2cdev;
 use log::info;
@@ -102,13 +100,13 @@ fn main() -> Result<(), Box<dyn Error>> {
 
     // SHA
     let digest_01 = atca
-        .sha()
+        .sha(None)
         .digest(&MESSAGE_TO_HASH)
         .map_err(|e| format!("{}", e))?;
     assert_eq!(digest_01.as_ref(), &DIGEST_OF_ANSWER);
 
     let digest_02 = atca
-        .sha()
+        .sha(None)
         .digest(SMALL_MESSAGE)
         .map_err(|e| format!("{}", e))?;
     assert_eq!(digest_02.as_ref(), &DIGEST_OF_SMALL_MESSAGE);
@@ -197,15 +195,12 @@ fn check_openssl_key_format() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
-fn check_privwrite_key_format<PHY, D>(
-    atca: &mut AtCaClient<PHY, D>,
+fn check_privwrite_key_format<T>(
+    atca: &mut AtCaClient<T>,
     key_id: Slot,
 ) -> Result<(), Box<dyn Error>>
 where
-    PHY: Read + Write,
-    <PHY as Read>::Error: Debug,
-    <PHY as Write>::Error: Debug,
-    D: DelayNs,
+    T: Transport,
 {
     let mut test_private_key = Block::default();
     test_private_key.as_mut().copy_from_slice(&TEST_PRIVATE_KEY);
@@ -216,15 +211,12 @@ where
     Ok(())
 }
 
-fn check_openssl_public_key_format<PHY, D>(
-    atca: &mut AtCaClient<PHY, D>,
+fn check_openssl_public_key_format<T>(
+    atca: &mut AtCaClient<T>,
     key_id: Slot,
 ) -> Result<(), Box<dyn Error>>
 where
-    PHY: Read + Write,
-    <PHY as Read>::Error: Debug,
-    <PHY as Write>::Error: Debug,
-    D: DelayNs,
+    T: Transport,
 {
     let result = atca
         .create_private_key(key_id)
@@ -242,15 +234,12 @@ where
     Ok(())
 }
 
-fn check_openssl_private_key_format<PHY, D>(
-    atca: &mut AtCaClient<PHY, D>,
+fn check_openssl_private_key_format<T>(
+    atca: &mut AtCaClient<T>,
     key_id: Slot,
 ) -> Result<(), Box<dyn Error>>
 where
-    PHY: Read + Write,
-    <PHY as Read>::Error: Debug,
-    <PHY as Write>::Error: Debug,
-    D: DelayNs,
+    T: Transport,
 {
     // Let OpenSSL create a key pair.
     let p256 = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).map_err(|e| format!("{}", e))?;
@@ -0,0 +1,631 @@
+use super::datalink::I2c;
+use crate::clock_divider::ClockDivider;
+use crate::command::{
+    self, Ecdh, GenKey, Info, Lock, NonceCtx, PrivWrite, PublicKey, Random, Serial, SharedSecret,
+    Word,
+};
+use crate::config::{ConfigZone, KeyConfig, SlotConfig};
+use crate::datalink::I2cConfig;
+use crate::error::{Error, ErrorKind};
+use crate::memory::{CertificateRepr, Size, Slot, Zone};
+use crate::packet::{Packet, PacketBuilder, Response};
+use crate::{Block, Digest, Signature};
+use core::convert::TryFrom;
+use core::convert::TryInto;
+use core::ops::{Deref, DerefMut};
+use embedded_hal_async::delay::DelayNs;
+use embedded_hal_async::i2c;
+use heapless::Vec;
+
+pub struct AtCaClient<PHY, D> {
+    i2c: I2c<PHY, D>,
+    buffer: Vec<u8, 192>,
+    clock_divider: ClockDivider,
+}
+
+impl<PHY, D> AtCaClient<PHY, D> {
+    /// Talks to a device at the default address, 0xC0 in datasheet notation.
+    pub fn new(phy: PHY, delay: D) -> Self {
+        Self::with_i2c_config(phy, delay, I2cConfig::default())
+    }
+
+    /// Talks to a device with the given bus parameters. Several clients can
+    /// share a bus via `embedded_hal_bus::i2c::RefCellDevice` or
+    /// `CriticalSectionDevice`, given each device has its own address.
+    pub fn with_i2c_config(phy: PHY, delay: D, config: I2cConfig) -> Self {
+        let i2c = I2c::new(phy, delay, config);
+        let buffer = Vec::new();
+        Self {
+            i2c,
+            buffer,
+            clock_divider: ClockDivider::Zero,
+        }
+    }
+
+    fn packet_builder(&mut self) -> PacketBuilder<'_> {
+        let capacity = self.buffer.capacity();
+        self.buffer.clear();
+        self.buffer
+            .resize(capacity, 0x00u8)
+            .unwrap_or_else(|()| unreachable!("Input length equals to the current capacity."));
+        PacketBuilder::new(&mut self.buffer)
+    }
+
+    pub fn memory(&mut self) -> Memory<'_, PHY, D> {
+        Memory { atca: self }
+    }
+
+    pub fn aes(&mut self, key_id: Slot) -> Aes<'_, PHY, D> {
+        Aes { atca: self, key_id }
+    }
+
+    // Keep the device awake until the returned guard is ended or dropped.
+    pub fn session(&mut self) -> Session<'_, PHY, D> {
+        let owner = !self.i2c.begin_session();
+        Session { atca: self, owner }
+    }
+
+    pub fn sha(&mut self, key_id: Option<Slot>) -> Sha<'_, PHY, D> {
+        let remaining_bytes = Vec::new();
+        Sha {
+            atca: self.session(),
+            remaining_bytes,
+            key_id,
+        }
+    }
+
+    pub fn sign(&mut self, key_id: Slot) -> Sign<'_, PHY, D> {
+        Sign { atca: self, key_id }
+    }
+
+    pub fn verify(&mut self, key_id: Slot) -> Verify<'_, PHY, D> {
+        Verify { atca: self, key_id }
+    }
+}
+
+impl<PHY, D> AtCaClient<PHY, D>
+where
+    PHY: i2c::I2c,
+    D: DelayNs,
+{
+    async fn execute(&mut self, packet: Packet) -> Result<Response<'_>, Error> {
+        let exec_time = self.clock_divider.execution_time(packet.opcode());
+        let response = self
+            .i2c
+            .execute(&mut self.buffer, packet.len(), exec_time)
+            .await?;
+        Response::new(response)
+    }
+
+    pub async fn sleep(&mut self) -> Result<(), Error> {
+        self.i2c.sleep().await
+    }
+
+    pub async fn info(&mut self) -> Result<Word, Error> {
+        let packet = Info::new(self.packet_builder()).revision()?;
+        self.execute(packet).await?.as_ref().try_into()
+    }
+
+    pub async fn random(&mut self) -> Result<Block, Error> {
+        let packet = Random::new(self.packet_builder()).random()?;
+        self.execute(packet).await?.as_ref().try_into()
+    }
+
+    // Write to device's digest message buffer.
+    pub async fn write_message_digest_buffer(&mut self, msg: &Digest) -> Result<(), Error> {
+        let packet = NonceCtx::new(self.packet_builder()).message_digest_buffer(msg)?;
+        self.execute(packet).await.map(drop)
+    }
+
+    // Create private key and output its public key.
+    pub async fn create_private_key(&mut self, key_id: Slot) -> Result<PublicKey, Error> {
+        let packet = GenKey::new(self.packet_builder()).private_key(key_id)?;
+        self.execute(packet).await?.as_ref().try_into()
+    }
+
+    // Write private key.
+    pub async fn write_private_key(
+        &mut self,
+        key_id: Slot,
+        private_key: &Block,
+    ) -> Result<(), Error> {
+        let packet =
+            PrivWrite::new(self.packet_builder()).write_private_key(key_id, private_key)?;
+        self.execute(packet).await.map(drop)
+    }
+
+    // Given a private key created and stored in advance, calculate its public key.
+    pub async fn generate_pubkey(&mut self, key_id: Slot) -> Result<PublicKey, Error> {
+        let packet = GenKey::new(self.packet_builder()).public_key(key_id)?;
+        self.execute(packet).await?.as_ref().try_into()
+    }
+
+    pub async fn diffie_hellman(
+        &mut self,
+        key_id: Slot,
+        public_key: PublicKey,
+    ) -> Result<SharedSecret, Error> {
+        let packet = Ecdh::new(self.packet_builder()).diffie_hellman(key_id, public_key)?;
+        self.execute(packet).await?.as_ref().try_into()
+    }
+}
+
+// A sequence of commands run without putting the device into the idle state
+// in between, as `crate::client::Session` does. Drop cannot wait on the bus,
+// so a dropped guard leaves the idle to the next transaction. End the session
+// with `idle` or `sleep` to put the device into either state right away.
+pub struct Session<'a, PHY, D> {
+    atca: &'a mut AtCaClient<PHY, D>,
+    // Sessions nest. Only the outermost one puts the device into idle state.
+    owner: bool,
+}
+
+impl<'a, PHY, D> Session<'a, PHY, D>
+where
+    PHY: i2c::I2c,
+    D: DelayNs,
+{
+    // End the session, putting the device into the idle state.
+    pub async fn idle(mut self) -> Result<(), Error> {
+        if !core::mem::replace(&mut self.owner, false) {
+            return Ok(());
+        }
+        self.atca.i2c.end_session().await
+    }
+
+    // End the session, putting the device to sleep. Volatile states are lost.
+    // A nested session leaves the device to the outer one, as `idle` does.
+    pub async fn sleep(self) -> Result<(), Error> {
+        if !self.owner {
+            return Ok(());
+        }
+        // The guard still ends the session on the transport when dropped.
+        self.atca.i2c.sleep().await
+    }
+}
+
+impl<'a, PHY, D> Deref for Session<'a, PHY, D> {
+    type Target = AtCaClient<PHY, D>;
+    fn deref(&self) -> &Self::Target {
+        self.atca
+    }
+}
+
+impl<'a, PHY, D> DerefMut for Session<'a, PHY, D> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.atca
+    }
+}
+
+impl<'a, PHY, D> Drop for Session<'a, PHY, D> {
+    fn drop(&mut self) {
+        if self.owner {
+            self.atca.i2c.defer_end_session();
+        }
+    }
+}
+
+// Memory zones consist of config, data and OTP.
+pub struct Memory<'a, PHY, D> {
+    atca: &'a mut AtCaClient<PHY, D>,
+}
+
+impl<'a, PHY, D> Memory<'a, PHY, D>
+where
+    PHY: i2c::I2c,
+    D: DelayNs,
+{
+    pub async fn serial_number(&mut self) -> Result<Serial, Error> {
+        let packet =
+            command::Read::new(self.atca.packet_builder()).read(Zone::Config, Size::Block, 0, 0)?;
+        self.atca.execute(packet).await?.as_ref().try_into()
+    }
+
+    pub async fn pubkey(&mut self, key_id: Slot) -> Result<PublicKey, Error> {
+        let mut pubkey = PublicKey::default();
+        let mut offset = 0;
+        let mut session = self.atca.session();
+        for (i, ranges) in CertificateRepr::new().enumerate() {
+            let packet = command::Read::new(session.packet_builder()).slot(key_id, i as u8)?;
+            let response = session.execute(packet).await?;
+            for range in ranges {
+                let dst = offset..offset + range.len();
+                pubkey.as_mut()[dst].copy_from_slice(&response.as_ref()[range.clone()]);
+                offset += range.len();
+            }
+        }
+        session.idle().await?;
+        Ok(pubkey)
+    }
+
+    pub async fn write_pubkey(
+        &mut self,
+        key_id: Slot,
+        pubkey: impl AsRef<[u8]>,
+    ) -> Result<(), Error> {
+        let mut data = Block::default();
+        let mut offset = 0;
+        let mut session = self.atca.session();
+        for (i, ranges) in CertificateRepr::new().enumerate() {
+            // Initialize block sized buffer
+            data.as_mut().iter_mut().for_each(|value| *value = 0);
+
+            for range in ranges {
+                let src = offset..offset + range.len();
+                data.as_mut()[range.clone()].copy_from_slice(&pubkey.as_ref()[src]);
+                offset += range.len();
+            }
+
+            let packet =
+                command::Write::new(session.packet_builder()).slot(key_id, i as u8, &data)?;
+            session.execute(packet).await?;
+        }
+        session.idle().await
+    }
+
+    pub async fn write_aes_key(
+        &mut self,
+        key_id: Slot,
+        aes_key: impl AsRef<[u8]>,
+    ) -> Result<(), Error> {
+        let mut data = Block::default();
+        data.as_mut()[..0x10].copy_from_slice(aes_key.as_ref());
+        let packet = command::Write::new(self.atca.packet_builder()).slot(key_id, 0, &data)?;
+        self.atca.execute(packet).await.map(drop)
+    }
+
+    pub async fn write_hmac_key(
+        &mut self,
+        key_id: Slot,
+        hmac_key: impl AsRef<[u8]>,
+    ) -> Result<(), Error> {
+        let mut data = Block::default();
+        data.as_mut()[..0x20].copy_from_slice(hmac_key.as_ref());
+        let packet = command::Write::new(self.atca.packet_builder()).slot(key_id, 0, &data)?;
+        self.atca.execute(packet).await.map(drop)
+    }
+
+    pub async fn is_slot_locked(&mut self, slot: Slot) -> Result<bool, Error> {
+        let zone = Zone::Config;
+        let size = Size::Word;
+        let block = 2;
+        let word_offset = 6;
+        let packet =
+            command::Read::new(self.atca.packet_builder()).read(zone, size, block, word_offset)?;
+        let response = self.atca.execute(packet).await?;
+        let word = Word::try_from(response.as_ref())?;
+        let slot_locked_bytes = word.as_ref()[..2]
+            .try_into()
+            .map(u16::from_le_bytes)
+            .unwrap_or_else(|_| unreachable!());
+        Ok(slot_locked_bytes & (0x01u16 << slot as u32) == 0x00)
+    }
+
+    pub async fn is_locked(&mut self, zone: Zone) -> Result<bool, Error> {
+        let size = Size::Word;
+        let block = 2;
+        let word_offset = 5;
+        let packet = command::Read::new(self.atca.packet_builder()).read(
+            Zone::Config,
+            size,
+            block,
+            word_offset,
+        )?;
+        let response = self.atca.execute(packet).await?;
+        let word = Word::try_from(response.as_ref())?;
+        match zone {
+            Zone::Config => Ok(word.as_ref()[3] != 0x55),
+            // OTP zone is locked along with data zone.
+            Zone::Data | Zone::Otp => Ok(word.as_ref()[2] != 0x55),
+        }
+    }
+
+    pub async fn lock_slot(&mut self, key_id: Slot) -> Result<(), Error> {
+        let packet = Lock::new(self.atca.packet_builder()).slot(key_id)?;
+        self.atca.execute(packet).await.map(drop)
+    }
+
+    pub async fn lock(&mut self, zone: Zone) -> Result<(), Error> {
+        let packet = Lock::new(self.atca.packet_builder()).zone(zone, None)?;
+        self.atca.execute(packet).await.map(drop)
+    }
+
+    pub async fn lock_crc(&mut self, zone: Zone, crc: u16) -> Result<(), Error> {
+        let packet = Lock::new(self.atca.packet_builder()).zone(zone, Some(crc))?;
+        self.atca.execute(packet).await.map(drop)
+    }
+
+    pub async fn chip_options(&mut self) -> Result<u16, Error> {
+        self.read_config_u16(ConfigZone::CHIP_OPTIONS_INDEX).await
+    }
+
+    pub async fn i2c_address(&mut self) -> Result<u8, Error> {
+        let (block, offset, pos) = Zone::locate_index(ConfigZone::I2C_ADDRESS_INDEX);
+        self.read_config(Size::Word, block, offset)
+            .await
+            .map(|resp| resp.as_ref()[pos as usize] >> 1)
+    }
+
+    pub async fn set_i2c_address(&mut self, address: u8) -> Result<(), Error> {
+        if address > 0x7f {
+            return Err(ErrorKind::BadParam.into());
+        }
+        let (block, offset, pos) = Zone::locate_index(ConfigZone::I2C_ADDRESS_INDEX);
+        let response = self.read_config(Size::Word, block, offset).await?;
+        let mut word = Word::try_from(response.as_ref())?;
+        word.as_mut()[pos as usize] = address << 1;
+        self.write_config(Size::Word, block, offset, word).await
+    }
+
+    pub async fn permission(&mut self, slot: Slot) -> Result<SlotConfig, Error> {
+        let index = ConfigZone::SLOT_CONFIG_INDEX + (slot as usize * 2);
+        self.read_config_u16(index).await.map(SlotConfig::from)
+    }
+
+    pub async fn key_type(&mut self, slot: Slot) -> Result<KeyConfig, Error> {
+        let index = ConfigZone::KEY_CONFIG_INDEX + (slot as usize * 2);
+        self.read_config_u16(index).await.map(KeyConfig::from)
+    }
+
+    async fn read_config_u16(&mut self, index: usize) -> Result<u16, Error> {
+        let (block, offset, pos) = Zone::locate_index(index);
+        let range = pos as usize..pos as usize + 2;
+        self.read_config(Size::Word, block, offset)
+            .await
+            .map(|resp| {
+                resp.as_ref()[range]
+                    .try_into()
+                    .map(u16::from_le_bytes)
+                    .unwrap_or_else(|_| unreachable!())
+            })
+    }
+
+    // TODO: Testing purpose only.
+    pub async fn read_config(
+        &mut self,
+        size: Size,
+        block: u8,
+        offset: u8,
+    ) -> Result<Response<'_>, Error> {
+        let packet = command::Read::new(self.atca.packet_builder()).read(
+            Zone::Config,
+            size,
+            block,
+            offset,
+        )?;
+        self.atca.execute(packet).await
+    }
+
+    pub async fn write_config(
+        &mut self,
+        size: Size,
+        block: u8,
+        offset: u8,
+        data: impl AsRef<[u8]>,
+    ) -> Result<(), Error> {
+        let packet = command::Write::new(self.atca.packet_builder()).write(
+            Zone::Config,
+            size,
+            block,
+            offset,
+            data,
+        )?;
+        self.atca.execute(packet).await.map(drop)
+    }
+}
+
+// AES
+pub struct Aes<'a, PHY, D> {
+    atca: &'a mut AtCaClient<PHY, D>,
+    key_id: Slot,
+}
+
+impl<'a, PHY, D> Aes<'a, PHY, D>
+where
+    PHY: i2c::I2c,
+    D: DelayNs,
+{
+    pub async fn encrypt(&mut self, plaintext: &[u8], ciphertext: &mut [u8]) -> Result<(), Error> {
+        use command::Aes as AesCmd;
+
+        if plaintext.len() != ciphertext.len() {
+            return Err(ErrorKind::BadParam.into());
+        }
+
+        for (plain, cipher) in plaintext
+            .chunks(AesCmd::DATA_SIZE)
+            .zip(ciphertext.chunks_mut(AesCmd::DATA_SIZE))
+        {
+            let packet = AesCmd::new(self.atca.packet_builder()).encrypt(self.key_id, plain)?;
+
+            // Encrypt plain bytes and write the result to cipher.
+            let response = self.atca.execute(packet).await?;
+            if response.as_ref().len() != AesCmd::DATA_SIZE {
+                return Err(ErrorKind::InvalidSize.into());
+            }
+            cipher.copy_from_slice(response.as_ref());
+        }
+        Ok(())
+    }
+
+    pub async fn decrypt(&mut self, ciphertext: &[u8], plaintext: &mut [u8]) -> Result<(), Error> {
+        use command::Aes as AesCmd;
+
+        if ciphertext.len() != plaintext.len() {
+            return Err(ErrorKind::BadParam.into());
+        }
+
+        for (cipher, plain) in ciphertext
+            .chunks(AesCmd::DATA_SIZE)
+            .zip(plaintext.chunks_mut(AesCmd::DATA_SIZE))
+        {
+            let packet = AesCmd::new(self.atca.packet_builder()).decrypt(self.key_id, cipher)?;
+
+            // Decrypt cipher bytes and write the result to plain.
+            let response = self.atca.execute(packet).await?;
+            if response.as_ref().len() != AesCmd::DATA_SIZE {
+                return Err(ErrorKind::InvalidSize.into());
+            }
+            plain.copy_from_slice(response.as_ref());
+        }
+        Ok(())
+    }
+}
+
+// SHA
+pub struct Sha<'a, PHY, D> {
+    atca: Session<'a, PHY, D>,
+    remaining_bytes: Vec<u8, 64>,
+    key_id: Option<Slot>,
+}
+
+impl<'a, PHY, D> Sha<'a, PHY, D>
+where
+    PHY: i2c::I2c,
+    D: DelayNs,
+{
+    // Start over, discarding bytes of a previous calculation.
+    pub async fn init(&mut self) -> Result<(), Error> {
+        self.remaining_bytes.clear();
+        let packet = command::Sha::new(self.atca.packet_builder()).start(self.key_id)?;
+        self.atca.execute(packet).await.map(drop)
+    }
+
+    // See digest::Update. Bytes short of a whole block are kept until the
+    // next call.
+    pub async fn update(&mut self, data: impl AsRef<[u8]>) -> Result<(), Error> {
+        let capacity = self.remaining_bytes.capacity();
+        let mut data = data.as_ref();
+
+        // Complete the block left over from previous calls first.
+        if !self.remaining_bytes.is_empty() {
+            let length = data.len().min(capacity - self.remaining_bytes.len());
+            let (head, tail) = data.split_at(length);
+            self.remaining_bytes
+                .extend_from_slice(head)
+                .unwrap_or_else(|()| unreachable!("Block never overflows."));
+            data = tail;
+            if self.remaining_bytes.len() < capacity {
+                return Ok(());
+            }
+            let packet =
+                command::Sha::new(self.atca.packet_builder()).update(&self.remaining_bytes)?;
+            self.atca.execute(packet).await?;
+            self.remaining_bytes.clear();
+        }
+
+        // Store remaining bytes for later processing
+        let (bytes, remainder) = data.split_at(data.len() - data.len() % capacity);
+        self.remaining_bytes
+            .extend_from_slice(remainder)
+            .unwrap_or_else(|()| unreachable!("Remainder is shorter than a block."));
+
+        // Execute update command
+        for chunk in bytes.chunks(capacity) {
+            let packet = command::Sha::new(self.atca.packet_builder()).update(chunk)?;
+            self.atca.execute(packet).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn finalize(&mut self) -> Result<Digest, Error> {
+        let packet = command::Sha::new(self.atca.packet_builder()).end(&self.remaining_bytes)?;
+        self.remaining_bytes.clear();
+        self.atca.execute(packet).await?.as_ref().try_into()
+    }
+
+    pub async fn digest(&mut self, data: &[u8]) -> Result<Digest, Error> {
+        self.init().await?;
+        self.update(data).await?;
+        self.finalize().await
+    }
+}
+
+// Sign
+pub struct Sign<'a, PHY, D> {
+    atca: &'a mut AtCaClient<PHY, D>,
+    key_id: Slot,
+}
+
+impl<'a, PHY, D> Sign<'a, PHY, D>
+where
+    PHY: i2c::I2c,
+    D: DelayNs,
+{
+    // Takes a 32-byte message to be signed, typically the SHA256 hash of the
+    // full message.
+    pub async fn sign_digest(&mut self, digest: &Digest) -> Result<Signature, Error> {
+        let mut session = self.atca.session();
+        // 1. Random value generation
+        session.random().await?;
+        // 2. Nonce load
+        session.write_message_digest_buffer(digest).await?;
+        // 3. Sign
+        let packet = command::Sign::new(session.packet_builder()).external(self.key_id)?;
+        let signature = session.execute(packet).await?.as_ref().try_into()?;
+        session.idle().await?;
+        Ok(signature)
+    }
+
+    // Hash the message on the device and sign its digest. Equivalent of
+    // `signature::Signer::try_sign`.
+    pub async fn sign(&mut self, msg: &[u8]) -> Result<Signature, Error> {
+        let key_id = self.key_id;
+        let mut session = self.atca.session();
+        let digest = session.sha(None).digest(msg).await?;
+        let signature = Sign {
+            atca: &mut session,
+            key_id,
+        }
+        .sign_digest(&digest)
+        .await?;
+        session.idle().await?;
+        Ok(signature)
+    }
+}
+
+pub struct Verify<'a, PHY, D> {
+    atca: &'a mut AtCaClient<PHY, D>,
+    key_id: Slot,
+}
+
+impl<'a, PHY, D> Verify<'a, PHY, D>
+where
+    PHY: i2c::I2c,
+    D: DelayNs,
+{
+    // Takes a 32-byte message to be signed, typically the SHA256 hash of the
+    // full message and signature.
+    pub async fn verify_digest(
+        &mut self,
+        digest: &Digest,
+        signature: &Signature,
+        public_key: &PublicKey,
+    ) -> Result<(), Error> {
+        let mut session = self.atca.session();
+        // 1. Nonce load
+        session.write_message_digest_buffer(digest).await?;
+        // 2. Verify
+        let packet =
+            command::Verify::new(session.packet_builder()).external(signature, public_key)?;
+        session.execute(packet).await?;
+        session.idle().await
+    }
+
+    // Hash the message on the device and verify the signature against the
+    // public key calculated from the private key in the slot. Equivalent of
+    // `signature::Verifier::verify`.
+    pub async fn verify(&mut self, msg: &[u8], signature: &Signature) -> Result<(), Error> {
+        let key_id = self.key_id;
+        let mut session = self.atca.session();
+        let digest = session.sha(None).digest(msg).await?;
+        let public_key = session.generate_pubkey(key_id).await?;
+        Verify {
+            atca: &mut session,
+            key_id,
+        }
+        .verify_digest(&digest, signature, &public_key)
+        .await?;
+        session.idle().await
+    }
+}
@@ -0,0 +1,228 @@
+// Asynchronous counterpart of `crate::datalink`. Packets are encoded and
+// responses are decoded by the very same routines, so that both flavours put
+// identical bytes on the bus.
+use crate::datalink::{check_wake_response, response_length, I2cConfig, Transaction, Watchdog};
+use crate::error::{Error, ErrorKind};
+use core::slice::from_ref;
+use embedded_hal_async::delay::DelayNs;
+use embedded_hal_async::i2c;
+
+pub(crate) struct I2c<PHY, D> {
+    phy: PHY,
+    delay: D,
+    config: I2cConfig,
+    watchdog: Watchdog,
+    // A session ended by drop, which cannot wait on the bus, leaves putting
+    // the device into the idle state to whatever comes next.
+    idle_pending: bool,
+}
+
+impl<PHY, D> I2c<PHY, D> {
+    pub(crate) fn new(phy: PHY, delay: D, config: I2cConfig) -> Self {
+        Self {
+            phy,
+            delay,
+            config,
+            watchdog: Watchdog::default(),
+            idle_pending: false,
+        }
+    }
+
+    /// Keeps the device awake in between commands. Returns whether a session
+    /// has been open already.
+    pub(crate) fn begin_session(&mut self) -> bool {
+        self.watchdog.begin_session()
+    }
+
+    /// Ends the session without touching the bus. The device is put into the
+    /// idle state before the next transaction.
+    pub(crate) fn defer_end_session(&mut self) {
+        if self.watchdog.end_session() {
+            self.idle_pending = true;
+        }
+    }
+}
+
+impl<PHY, D> I2c<PHY, D>
+where
+    PHY: i2c::I2c,
+    D: DelayNs,
+{
+    /// Sends the command packet in `buffer[1..length]` and receives the
+    /// response into `buffer`, as `Transport::execute` does.
+    pub(crate) async fn execute<'a>(
+        &mut self,
+        buffer: &'a mut [u8],
+        length: usize,
+        exec_time: u32,
+    ) -> Result<&'a [u8], Error> {
+        self.flush_idle().await;
+        match self.transmit(buffer, length, exec_time).await {
+            Ok(response) if self.watchdog.in_session() => Ok(response),
+            Ok(response) => self.idle().await.map(|()| response),
+            Err(error) => {
+                // Device state is unknown. Start over with the next command.
+                self.idle().await.ok();
+                Err(error)
+            }
+        }
+    }
+
+    /// Puts the device into the idle state if it is awake.
+    pub(crate) async fn end_session(&mut self) -> Result<(), Error> {
+        self.idle_pending = false;
+        match self.watchdog.end_session() {
+            true => self.idle().await,
+            false => Ok(()),
+        }
+    }
+
+    pub(crate) async fn sleep(&mut self) -> Result<(), Error> {
+        self.flush_idle().await;
+        let word_address = Transaction::Sleep as u8;
+        // The device only takes the word address while awake.
+        if !self.watchdog.is_awake() {
+            self.wake().await?;
+        }
+        self.watchdog.dormant();
+        // Wait for the I2C bus to be ready.
+        self.delay.delay_us(30).await;
+        self.phy
+            .write(self.config.address, from_ref(&word_address))
+            .await
+            .map_err(|_| ErrorKind::TxFail.into())
+    }
+
+    async fn transmit<'a>(
+        &mut self,
+        buffer: &'a mut [u8],
+        length: usize,
+        exec_time: u32,
+    ) -> Result<&'a [u8], Error> {
+        self.ensure_awake(exec_time).await?;
+        buffer[0] = Transaction::Command as u8;
+        self.send(&buffer[..length]).await?;
+        self.poll(exec_time).await?;
+        self.receive(buffer).await.map(|response| &*response)
+    }
+
+    /// Within a session, the device is woken up anew if the watchdog could
+    /// expire before the command completes.
+    async fn ensure_awake(&mut self, exec_time: u32) -> Result<(), Error> {
+        if self
+            .watchdog
+            .expires_within(self.config.timeout_us(exec_time))
+        {
+            self.idle().await?;
+        }
+        if !self.watchdog.is_awake() {
+            self.wake().await?;
+        }
+        Ok(())
+    }
+
+    /// Carries out the idle deferred by a dropped session. Errors are ignored
+    /// as they would have been on drop; the watchdog may have put the device
+    /// to sleep in the meantime.
+    async fn flush_idle(&mut self) {
+        if core::mem::replace(&mut self.idle_pending, false) {
+            self.idle().await.ok();
+        }
+    }
+
+    async fn send(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        self.phy
+            .write(self.config.address, bytes)
+            .await
+            .map_err(|_| ErrorKind::TxFail.into())
+    }
+
+    /// Waits for the device to finish its job. It does not acknowledge its
+    /// address while busy.
+    async fn poll(&mut self, exec_time: u32) -> Result<(), Error> {
+        // Reset indicates the beginning of transaction.
+        let word_address = Transaction::Reset as u8;
+        let interval = self.config.poll_interval_us;
+        let timeout = self.config.timeout_us(exec_time);
+        let mut elapsed: u32 = 0;
+        loop {
+            self.delay.delay_us(interval).await;
+            elapsed = elapsed.saturating_add(interval);
+            self.watchdog.elapse(interval);
+            if self
+                .phy
+                .write(self.config.address, from_ref(&word_address))
+                .await
+                .is_ok()
+            {
+                return Ok(());
+            }
+            if elapsed >= timeout {
+                return Err(ErrorKind::Timeout.into());
+            }
+        }
+    }
+
+    /// Returns response buffer for later processing.
+    async fn receive<'a>(&mut self, buffer: &'a mut [u8]) -> Result<&'a mut [u8], Error> {
+        self.phy
+            .read(self.config.address, &mut buffer[0..2])
+            .await
+            .map_err(|_| Error::from(ErrorKind::RxFail))?;
+
+        let length_to_read = match response_length(buffer)? {
+            // A single byte has already read.
+            None => return Ok(buffer[0..1].as_mut()),
+            Some(length) => length,
+        };
+
+        self.phy
+            .read(self.config.address, buffer[2..length_to_read].as_mut())
+            .await
+            .map(move |()| buffer[..length_to_read].as_mut())
+            .map_err(|_| ErrorKind::RxFail.into())
+    }
+
+    async fn wake(&mut self) -> Result<(), Error> {
+        // Send a single null byte to an absent address.
+        //
+        // Ignore errors as this will error if the device is not awake yet.
+        self.phy
+            .write(self.config.address, from_ref(&0x00))
+            .await
+            .ok();
+
+        // Wait for the device to wake up.
+        self.delay.delay_us(self.config.wake_delay_us).await;
+
+        let buffer = &mut [0x00, 0x00, 0x00, 0x00];
+        let mut received = false;
+        for _ in 0..self.config.retry {
+            if self
+                .phy
+                .read(self.config.address, buffer.as_mut())
+                .await
+                .is_ok()
+            {
+                received = true;
+                break;
+            }
+        }
+        if !received {
+            return Err(ErrorKind::RxFail.into());
+        }
+
+        check_wake_response(buffer)?;
+        self.watchdog.woken();
+        Ok(())
+    }
+
+    async fn idle(&mut self) -> Result<(), Error> {
+        let word_address = Transaction::Idle as u8;
+        self.watchdog.dormant();
+        self.phy
+            .write(self.config.address, from_ref(&word_address))
+            .await
+            .map_err(|_| ErrorKind::TxFail.into())
+    }
+}
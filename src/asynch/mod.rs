@@ -0,0 +1,6 @@
+// Asynchronous driver built on embedded-hal-async. APIs mirror those of the
+// blocking `AtCaClient` and share the same packet encoder and response decoder.
+mod client;
+mod datalink;
+
+pub use client::{Aes, AtCaClient, Memory, Session, Sha, Sign, Verify};
@@ -1,97 +1,116 @@
-use crate::command::{Ecdh, SharedSecret};
-
 use super::clock_divider::ClockDivider;
 use super::command::{
-    self, GenKey, Info, Lock, NonceCtx, PrivWrite, PublicKey, Random, Serial, Word,
+    self, CheckMac, CounterId, DeriveKey, EcdhSource, EcdhTarget, GenDig, GenKey, Info, KdfMessage,
+    KdfSource, KdfTarget, Lock, Mac, NonceCtx, NonceTarget, PrivWrite, PublicKey, Random, Serial,
+    SharedSecret, Word,
 };
-use super::datalink::I2c;
-use super::error::{Error, ErrorKind};
-use super::memory::{CertificateRepr, Size, Slot, Zone};
-use super::packet::{Packet, PacketBuilder, Response};
+use super::config::{self, ConfigZone, CountMatch, KeyConfig, SlotConfig};
+use super::datalink::{I2c, I2cConfig, Swi, SwiConfig, Transport};
+use super::error::{Error, ErrorKind, Status};
+use super::host::{self, Source, TempKey};
+use super::memory::{self, CertificateRepr, OtpMode, Size, Slot, Zone};
+use super::packet::{Packet, PacketBuilder, Response, CRC16};
 use super::tngtls::TrustAndGo;
 use super::{Block, Digest, Signature};
 use core::cell::RefCell;
 use core::convert::TryInto;
 use core::convert::{identity, TryFrom};
-use embedded_hal::delay::DelayNs;
-use embedded_hal::i2c;
+use core::ops::{Deref, DerefMut, Range};
 use heapless::Vec;
 
-pub struct Verifier<'a, PHY, D>(RefCell<Verify<'a, PHY, D>>);
+pub struct Verifier<'a, T>(RefCell<Verify<'a, T>>);
 
-impl<'a, PHY, D> From<Verify<'a, PHY, D>> for Verifier<'a, PHY, D> {
-    fn from(verify: Verify<'a, PHY, D>) -> Self {
+impl<'a, T> From<Verify<'a, T>> for Verifier<'a, T> {
+    fn from(verify: Verify<'a, T>) -> Self {
         Self(RefCell::new(verify))
     }
 }
 
-impl<'a, PHY, D> signature::Verifier<Signature> for Verifier<'a, PHY, D>
+impl<'a, T> signature::Verifier<Signature> for Verifier<'a, T>
 where
-    PHY: i2c::I2c,
-    D: DelayNs,
+    T: Transport,
 {
     fn verify(&self, msg: &[u8], signature: &Signature) -> Result<(), signature::Error> {
-        let digest = self
-            .0
-            .borrow_mut()
-            .atca
+        let mut verify = self.0.borrow_mut();
+        let key_id = verify.key_id;
+        let mut session = verify.atca.session();
+        let digest = session
             .sha(None)
             .digest(msg)
             .map_err(|_| signature::Error::new())?;
-        let key_id = self.0.borrow_mut().key_id.clone();
-        let public_key = self
-            .0
-            .borrow_mut()
-            .atca
+        let public_key = session
             .generate_pubkey(key_id)
             .map_err(|_| signature::Error::new())?;
-        self.0
-            .borrow_mut()
-            .verify_digest(&digest, signature, &public_key)
-            .map_err(|_| signature::Error::new())
+        Verify {
+            atca: &mut session,
+            key_id,
+        }
+        .verify_digest(&digest, signature, &public_key)
+        .map_err(|_| signature::Error::new())
     }
 }
 
-pub struct Signer<'a, PHY, D>(RefCell<Sign<'a, PHY, D>>);
+pub struct Signer<'a, T>(RefCell<Sign<'a, T>>);
 
-impl<'a, PHY, D> From<Sign<'a, PHY, D>> for Signer<'a, PHY, D> {
-    fn from(sign: Sign<'a, PHY, D>) -> Self {
+impl<'a, T> From<Sign<'a, T>> for Signer<'a, T> {
+    fn from(sign: Sign<'a, T>) -> Self {
         Self(RefCell::new(sign))
     }
 }
 
-impl<'a, PHY, D> signature::Signer<Signature> for Signer<'a, PHY, D>
+impl<'a, T> signature::Signer<Signature> for Signer<'a, T>
 where
-    PHY: i2c::I2c,
-    D: DelayNs,
+    T: Transport,
 {
     fn try_sign(&self, msg: &[u8]) -> Result<Signature, signature::Error> {
-        let digest = self
-            .0
-            .borrow_mut()
-            .atca
+        let mut sign = self.0.borrow_mut();
+        let key_id = sign.key_id;
+        let mut session = sign.atca.session();
+        let digest = session
             .sha(None)
             .digest(msg)
             .map_err(|_| signature::Error::new())?;
-        self.0
-            .borrow_mut()
-            .sign_digest(&digest)
-            .map_err(|_| signature::Error::new())
+        Sign {
+            atca: &mut session,
+            key_id,
+        }
+        .sign_digest(&digest)
+        .map_err(|_| signature::Error::new())
     }
 }
 
-pub struct AtCaClient<PHY, D> {
-    i2c: I2c<PHY, D>,
+pub struct AtCaClient<T> {
+    transport: T,
     buffer: Vec<u8, 192>,
     clock_divider: ClockDivider,
 }
 
-impl<PHY, D> AtCaClient<PHY, D> {
+impl<PHY, D> AtCaClient<I2c<PHY, D>> {
+    /// Talks to a device at the default address, 0xC0 in datasheet notation.
     pub fn new(phy: PHY, delay: D) -> Self {
-        let i2c = I2c::new(phy, delay);
+        Self::with_i2c_config(phy, delay, I2cConfig::default())
+    }
+
+    /// Talks to a device with the given bus parameters. Several clients can
+    /// share a bus via `embedded_hal_bus::i2c::RefCellDevice` or
+    /// `CriticalSectionDevice`, given each device has its own address.
+    pub fn with_i2c_config(phy: PHY, delay: D, config: I2cConfig) -> Self {
+        Self::with_transport(I2c::new(phy, delay, config))
+    }
+}
+
+impl<UART, D> AtCaClient<Swi<UART, D>> {
+    /// Talks to a device over the single-wire interface, driven by a UART.
+    pub fn swi(uart: UART, delay: D) -> Self {
+        Self::with_transport(Swi::new(uart, delay, SwiConfig::default()))
+    }
+}
+
+impl<T> AtCaClient<T> {
+    pub fn with_transport(transport: T) -> Self {
         let buffer = Vec::new();
         Self {
-            i2c,
+            transport,
             buffer,
             clock_divider: ClockDivider::Zero,
         }
@@ -106,56 +125,90 @@ impl<PHY, D> AtCaClient<PHY, D> {
         PacketBuilder::new(&mut self.buffer)
     }
 
-    pub fn memory(&mut self) -> Memory<'_, PHY, D> {
+    pub fn memory(&mut self) -> Memory<'_, T> {
         Memory { atca: self }
     }
 
-    pub fn aes(&mut self, key_id: Slot) -> Aes<'_, PHY, D> {
+    pub fn aes(&mut self, key_id: Slot) -> Aes<'_, T> {
         Aes { atca: self, key_id }
     }
 
-    pub fn sha(&mut self, key_id: Option<Slot>) -> Sha<'_, PHY, D> {
-        let remaining_bytes = Vec::new();
-        Sha {
+    pub fn nonce(&mut self) -> Nonce<'_, T> {
+        Nonce { atca: self }
+    }
+
+    pub fn counter(&mut self, counter_id: CounterId) -> Counter<'_, T> {
+        Counter {
             atca: self,
-            remaining_bytes,
-            key_id,
+            counter_id,
         }
     }
 
-    pub fn sign(&mut self, key_id: Slot) -> Sign<'_, PHY, D> {
+    pub fn ecdh(&mut self, source: EcdhSource) -> Ecdh<'_, T> {
+        Ecdh { atca: self, source }
+    }
+
+    pub fn kdf(&mut self) -> Kdf<'_, T> {
+        Kdf { atca: self }
+    }
+
+    pub fn sign(&mut self, key_id: Slot) -> Sign<'_, T> {
         Sign { atca: self, key_id }
     }
 
-    pub fn verify(&mut self, key_id: Slot) -> Verify<'_, PHY, D> {
+    pub fn verify(&mut self, key_id: Slot) -> Verify<'_, T> {
         Verify { atca: self, key_id }
     }
 }
 
-impl<PHY, D> AtCaClient<PHY, D>
+impl<T> AtCaClient<T>
 where
-    PHY: i2c::I2c,
-    D: DelayNs,
+    T: Transport,
 {
     fn execute(&mut self, packet: Packet) -> Result<Response<'_>, Error> {
         let exec_time = self.clock_divider.execution_time(packet.opcode());
-        self.i2c.execute(&mut self.buffer, packet, exec_time)
+        let response = self
+            .transport
+            .execute(&mut self.buffer, packet.len(), exec_time)?;
+        Response::new(response)
+    }
+
+    // Keep the device awake until the returned guard is dropped.
+    pub fn session(&mut self) -> Session<'_, T> {
+        let owner = !self.transport.begin_session();
+        Session { atca: self, owner }
+    }
+
+    pub fn sha(&mut self, key_id: Option<Slot>) -> Sha<'_, T> {
+        let remaining_bytes = Vec::new();
+        Sha {
+            atca: self.session(),
+            remaining_bytes,
+            key_id,
+        }
+    }
+
+    // HMAC/SHA-256 with the key in a slot.
+    pub fn hmac(&mut self, key_id: Slot) -> Hmac<'_, T> {
+        let sha = self.sha(Some(key_id));
+        let started = false;
+        Hmac { sha, started }
     }
 
-    pub fn signer(&mut self, key_id: Slot) -> Signer<'_, PHY, D> {
+    pub fn signer(&mut self, key_id: Slot) -> Signer<'_, T> {
         self.sign(key_id).into()
     }
 
-    pub fn verifier(&mut self, key_id: Slot) -> Verifier<'_, PHY, D> {
+    pub fn verifier(&mut self, key_id: Slot) -> Verifier<'_, T> {
         self.verify(key_id).into()
     }
 
-    pub fn tng(&mut self) -> Result<TrustAndGo<'_, PHY, D>, Error> {
+    pub fn tng(&mut self) -> Result<TrustAndGo<'_, T>, Error> {
         self.try_into()
     }
 
     pub fn sleep(&mut self) -> Result<(), Error> {
-        self.i2c.sleep()
+        self.transport.sleep()
     }
 
     pub fn info(&mut self) -> Result<Word, Error> {
@@ -168,6 +221,23 @@ where
         self.execute(packet)?.as_ref().try_into()
     }
 
+    // Load TempKey with a random nonce and combine it with a key in the data
+    // zone by GenDig. Returns the resulting TempKey as computed on the host.
+    fn gen_dig(
+        &mut self,
+        key_id: Slot,
+        key: &Block,
+        num_in: &[u8; 20],
+        serial: &Serial,
+    ) -> Result<TempKey, Error> {
+        let rand_out = self.nonce().random(num_in, true)?;
+        let mut temp_key = TempKey::random(&rand_out, num_in, true);
+        let packet = GenDig::new(self.packet_builder()).gendig(Zone::Data, key_id)?;
+        self.execute(packet)?;
+        temp_key.gen_dig(Zone::Data, key_id as u16, &(*key).into(), serial);
+        Ok(temp_key)
+    }
+
     // Write to device's digest message buffer.
     pub fn write_message_digest_buffer(&mut self, msg: &Digest) -> Result<(), Error> {
         let packet = NonceCtx::new(self.packet_builder()).message_digest_buffer(msg)?;
@@ -180,6 +250,20 @@ where
         self.execute(packet)?.as_ref().try_into()
     }
 
+    // Create a private key in TempKey for a single ECDH, as in TLS ECDHE. The
+    // guard keeps the device awake, so that no watchdog sleep or other command
+    // gets in between.
+    pub fn ephemeral_keypair(&mut self) -> Result<EphemeralKey<'_, T>, Error> {
+        let mut atca = self.session();
+        let packet = GenKey::new(atca.packet_builder()).ephemeral_key()?;
+        let public_key = atca.execute(packet)?.as_ref().try_into()?;
+        Ok(EphemeralKey {
+            atca,
+            public_key,
+            forgotten: false,
+        })
+    }
+
     // Write private key.
     pub fn write_private_key(&mut self, key_id: Slot, private_key: &Block) -> Result<(), Error> {
         let packet =
@@ -193,31 +277,446 @@ where
         self.execute(packet)?.as_ref().try_into()
     }
 
+    // Compute a MAC over a challenge and the key in a slot, as the mode
+    // tells. Pass no challenge if the mode takes TempKey in its place. See
+    // `host::mac` for the layout.
+    pub fn mac(
+        &mut self,
+        key_id: Slot,
+        challenge: Option<&Block>,
+        mode: u8,
+    ) -> Result<Digest, Error> {
+        let packet = Mac::new(self.packet_builder()).mac(key_id, challenge, mode)?;
+        self.execute(packet)?.as_ref().try_into()
+    }
+
+    // Check a response of another device to a challenge against the key in a
+    // slot. OtherData supplies the opcode, mode and key ID the other device
+    // used; see `host::check_mac`. Returns whether the response matched.
+    pub fn check_mac(
+        &mut self,
+        key_id: Slot,
+        challenge: &Block,
+        response: &Block,
+        other_data: &[u8; 13],
+    ) -> Result<bool, Error> {
+        let packet = CheckMac::new(self.packet_builder())
+            .check_mac(key_id, challenge, response, other_data)?;
+        match self.execute(packet) {
+            Ok(_) => Ok(true),
+            Err(error) if error.status() == Some(Status::CheckmacVerifyFailed) => Ok(false),
+            Err(error) => Err(error),
+        }
+    }
+
+    // Replace the key in `target` by a digest of its parent and TempKey; see
+    // `host::TempKey::derive_key`. The target's WriteConfig decides between
+    // roll mode, where the parent is the target itself, and create mode, where
+    // it is the target's WriteKey, and whether the authorizing MAC from
+    // `host::TempKey::derive_key_mac` is required. `source` has to match the
+    // SourceFlag of TempKey on the device.
+    pub fn derive_key(
+        &mut self,
+        target: Slot,
+        source: Source,
+        mac: Option<&Block>,
+    ) -> Result<(), Error> {
+        let packet = DeriveKey::new(self.packet_builder()).derive_key(
+            target,
+            source == Source::Input,
+            mac,
+        )?;
+        self.execute(packet).map(drop)
+    }
+
     pub fn diffie_hellman(
         &mut self,
         key_id: Slot,
         public_key: PublicKey,
     ) -> Result<SharedSecret, Error> {
-        let packet = Ecdh::new(self.packet_builder()).diffie_hellman(key_id, public_key)?;
+        let packet =
+            command::Ecdh::new(self.packet_builder()).diffie_hellman(key_id, public_key)?;
         self.execute(packet)?.as_ref().try_into()
     }
 }
 
-// Memory zones consist of config, data and OTP.
-pub struct Memory<'a, PHY, D> {
-    atca: &'a mut AtCaClient<PHY, D>,
+// A sequence of commands run without putting the device into the idle state
+// in between, so that TempKey and the other volatile states are kept intact.
+// The device is woken up once, and then again only when its watchdog could
+// expire before the next command completes. Time the caller spends in between
+// commands is not accounted for, so keep sessions short. Dropping the guard
+// idles the device.
+pub struct Session<'a, T>
+where
+    T: Transport,
+{
+    atca: &'a mut AtCaClient<T>,
+    // Sessions nest. Only the outermost one puts the device into idle state.
+    owner: bool,
+}
+
+impl<'a, T> Session<'a, T>
+where
+    T: Transport,
+{
+    // End the session, putting the device into the idle state.
+    pub fn idle(mut self) -> Result<(), Error> {
+        if !core::mem::replace(&mut self.owner, false) {
+            return Ok(());
+        }
+        self.atca.transport.end_session()
+    }
+
+    // End the session, putting the device to sleep. Volatile states are lost.
+    // A nested session leaves the device to the outer one, as `idle` does.
+    pub fn sleep(mut self) -> Result<(), Error> {
+        self.sleep_outermost().map(drop)
+    }
+
+    // Returns whether the device has been put to sleep.
+    fn sleep_outermost(&mut self) -> Result<bool, Error> {
+        if !self.owner {
+            return Ok(false);
+        }
+        // The guard still ends the session on the transport when dropped.
+        self.atca.transport.sleep().map(|()| true)
+    }
+}
+
+impl<'a, T> Deref for Session<'a, T>
+where
+    T: Transport,
+{
+    type Target = AtCaClient<T>;
+    fn deref(&self) -> &Self::Target {
+        self.atca
+    }
+}
+
+impl<'a, T> DerefMut for Session<'a, T>
+where
+    T: Transport,
+{
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.atca
+    }
+}
+
+impl<'a, T> Drop for Session<'a, T>
+where
+    T: Transport,
+{
+    fn drop(&mut self) {
+        if self.owner {
+            self.atca.transport.end_session().ok();
+        }
+    }
+}
+
+// Nonce loads TempKey and the other volatile buffers. They are lost once the
+// device goes idle, so run Nonce and the commands consuming it in a session.
+pub struct Nonce<'a, T> {
+    atca: &'a mut AtCaClient<T>,
+}
+
+impl<'a, T> Nonce<'a, T>
+where
+    T: Transport,
+{
+    // Combine a random number from the device with NumIn into TempKey.
+    // Returns RandOut, from which `host::TempKey::random` reproduces TempKey.
+    // Unless `update_seed` is set, the random number generator reuses its
+    // current seed.
+    pub fn random(&mut self, num_in: &[u8; 20], update_seed: bool) -> Result<Block, Error> {
+        let packet = NonceCtx::new(self.atca.packet_builder()).rand(num_in, update_seed)?;
+        self.atca.execute(packet)?.as_ref().try_into()
+    }
+
+    // Pass a fixed value of 32 or 64 bytes through to the target.
+    pub fn load(&mut self, target: NonceTarget, value: &[u8]) -> Result<(), Error> {
+        let packet = NonceCtx::new(self.atca.packet_builder()).load(target, value)?;
+        self.atca.execute(packet).map(drop)
+    }
+}
+
+// Monotonic counters. They only ever go up, and survive power cycles.
+pub struct Counter<'a, T> {
+    atca: &'a mut AtCaClient<T>,
+    counter_id: CounterId,
+}
+
+impl<'a, T> Counter<'a, T>
+where
+    T: Transport,
+{
+    pub fn read(&mut self) -> Result<u32, Error> {
+        let packet = command::Counter::new(self.atca.packet_builder()).read(self.counter_id)?;
+        self.execute(packet)
+    }
+
+    // Returns the incremented value. Fails once the counter saturates, or
+    // when Counter0 reaches its CountMatch value.
+    pub fn increment(&mut self) -> Result<u32, Error> {
+        let packet =
+            command::Counter::new(self.atca.packet_builder()).increment(self.counter_id)?;
+        self.execute(packet)
+    }
+
+    fn execute(&mut self, packet: Packet) -> Result<u32, Error> {
+        self.atca
+            .execute(packet)?
+            .as_ref()
+            .try_into()
+            .map(u32::from_le_bytes)
+            .map_err(|_| ErrorKind::InvalidSize.into())
+    }
+}
+
+// ECDH with a private key in a slot or TempKey. The shared secret is either
+// kept on the device for further key derivation, or returned.
+pub struct Ecdh<'a, T> {
+    atca: &'a mut AtCaClient<T>,
+    source: EcdhSource,
+}
+
+impl<'a, T> Ecdh<'a, T>
+where
+    T: Transport,
+{
+    // Return the shared secret in the clear.
+    pub fn diffie_hellman(&mut self, public_key: &PublicKey) -> Result<SharedSecret, Error> {
+        let packet = command::Ecdh::new(self.atca.packet_builder()).ecdh(
+            self.source,
+            EcdhTarget::Output,
+            public_key,
+        )?;
+        self.atca.execute(packet)?.as_ref().try_into()
+    }
+
+    // Return the shared secret encrypted under the IO protection key, see
+    // `tngtls::IO_PROTECTION_KEY`, and decrypt it with `io_key` on the host.
+    pub fn diffie_hellman_encrypted(
+        &mut self,
+        public_key: &PublicKey,
+        io_key: &Block,
+    ) -> Result<SharedSecret, Error> {
+        let packet = command::Ecdh::new(self.atca.packet_builder()).ecdh(
+            self.source,
+            EcdhTarget::OutputEncrypted,
+            public_key,
+        )?;
+        let response = self.atca.execute(packet)?;
+        let (secret, nonce) = response.as_ref().split_at(Size::Block.len());
+        let mut secret = SharedSecret::try_from(secret)?;
+        let nonce = Block::try_from(nonce)?;
+        host::io_decrypt(&(*io_key).into(), &nonce.into(), secret.as_mut());
+        Ok(secret)
+    }
+
+    pub fn into_temp_key(&mut self, public_key: &PublicKey) -> Result<(), Error> {
+        let packet = command::Ecdh::new(self.atca.packet_builder()).ecdh(
+            self.source,
+            EcdhTarget::TempKey,
+            public_key,
+        )?;
+        self.atca.execute(packet).map(drop)
+    }
+
+    // Write the shared secret into a slot. A private key in slot N can only
+    // write to slot N|1.
+    pub fn into_slot(&mut self, public_key: &PublicKey, target: Slot) -> Result<(), Error> {
+        if let EcdhSource::Slot(slot) = self.source {
+            if target as u8 != slot as u8 | 0x01 {
+                return Err(ErrorKind::BadParam.into());
+            }
+        }
+        let packet = command::Ecdh::new(self.atca.packet_builder()).ecdh(
+            self.source,
+            EcdhTarget::Slot(target),
+            public_key,
+        )?;
+        self.atca.execute(packet).map(drop)
+    }
 }
 
-impl<'a, PHY, D> Memory<'a, PHY, D> {
-    pub(crate) const SLOT_CONFIG_INDEX: usize = 20;
-    pub(crate) const CHIP_OPTIONS_INDEX: usize = 90;
-    pub(crate) const KEY_CONFIG_INDEX: usize = 96;
+// Private key in TempKey, created by `AtCaClient::ephemeral_keypair`. Idle
+// retains TempKey, so the key is wiped once consumed by a single ECDH, or
+// when the guard is dropped unused: the device is put to sleep, or TempKey is
+// overwritten if an outer session needs the device to stay awake.
+pub struct EphemeralKey<'a, T>
+where
+    T: Transport,
+{
+    atca: Session<'a, T>,
+    public_key: PublicKey,
+    forgotten: bool,
 }
 
-impl<'a, PHY, D> Memory<'a, PHY, D>
+impl<'a, T> EphemeralKey<'a, T>
 where
-    PHY: i2c::I2c,
-    D: DelayNs,
+    T: Transport,
+{
+    pub fn public_key(&self) -> &PublicKey {
+        &self.public_key
+    }
+
+    pub fn diffie_hellman(mut self, public_key: &PublicKey) -> Result<SharedSecret, Error> {
+        let shared_secret = self
+            .atca
+            .ecdh(EcdhSource::TempKey)
+            .diffie_hellman(public_key);
+        self.forget()?;
+        shared_secret
+    }
+
+    fn forget(&mut self) -> Result<(), Error> {
+        self.forgotten = true;
+        if !self.atca.sleep_outermost()? {
+            self.atca.nonce().load(NonceTarget::TempKey, &[0x00; 32])?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a, T> Drop for EphemeralKey<'a, T>
+where
+    T: Transport,
+{
+    fn drop(&mut self) {
+        if !self.forgotten {
+            self.forget().ok();
+        }
+    }
+}
+
+// Key derivation inside the device. Derived keys stay on the chip unless
+// the target is one of the outputs.
+pub struct Kdf<'a, T> {
+    atca: &'a mut AtCaClient<T>,
+}
+
+impl<'a, T> Kdf<'a, T>
+where
+    T: Transport,
+{
+    // TLS 1.2 PRF over `message`, i.e. the label followed by the seed, keyed
+    // by the first `key_length` bytes of the source. `output_length` is
+    // either 32 or 64.
+    pub fn prf(
+        &mut self,
+        source: KdfSource,
+        key_length: usize,
+        target: KdfTarget,
+        output_length: usize,
+        message: &[u8],
+    ) -> Result<KdfOutput, Error> {
+        let packet = command::Kdf::new(self.atca.packet_builder()).prf(
+            source,
+            key_length,
+            target,
+            output_length,
+            message,
+        )?;
+        self.execute(packet, target)
+    }
+
+    // PRK = HMAC-SHA256(salt, IKM). No salt stands for a salt of zeros.
+    pub fn hkdf_extract(
+        &mut self,
+        salt: Option<KdfSource>,
+        ikm: KdfMessage<'_>,
+        target: KdfTarget,
+    ) -> Result<KdfOutput, Error> {
+        let packet = command::Kdf::new(self.atca.packet_builder()).hkdf(salt, target, ikm)?;
+        self.execute(packet, target)
+    }
+
+    // First 32 bytes of OKM, HMAC-SHA256(PRK, info || 0x01).
+    pub fn hkdf_expand(
+        &mut self,
+        prk: KdfSource,
+        info: &[u8],
+        target: KdfTarget,
+    ) -> Result<KdfOutput, Error> {
+        let mut message = Vec::<u8, { command::Kdf::MESSAGE_MAX_SIZE }>::new();
+        message
+            .extend_from_slice(info)
+            .and_then(|()| message.push(0x01).map_err(drop))
+            .map_err(|()| Error::from(ErrorKind::InvalidSize))?;
+        let packet = command::Kdf::new(self.atca.packet_builder()).hkdf(
+            Some(prk),
+            target,
+            KdfMessage::Input(&message),
+        )?;
+        self.execute(packet, target)
+    }
+
+    // AES-128 encryption of `message` with the `key_index`th 16 bytes of the
+    // source. The result is 16 bytes long.
+    pub fn aes(
+        &mut self,
+        source: KdfSource,
+        key_index: u8,
+        target: KdfTarget,
+        message: &[u8; 16],
+    ) -> Result<KdfOutput, Error> {
+        let packet = command::Kdf::new(self.atca.packet_builder())
+            .aes(source, key_index, target, message)?;
+        self.execute(packet, target)
+    }
+
+    fn execute(&mut self, packet: Packet, target: KdfTarget) -> Result<KdfOutput, Error> {
+        let response = self.atca.execute(packet)?;
+        let mut output = KdfOutput::default();
+        let data = match target {
+            KdfTarget::Output => response.as_ref(),
+            KdfTarget::OutputEncrypted => {
+                let length = response.as_ref().len();
+                let (data, nonce) = response.as_ref().split_at(
+                    length
+                        .checked_sub(Size::Block.len())
+                        .ok_or(ErrorKind::InvalidSize)?,
+                );
+                output.nonce = Some(nonce.try_into()?);
+                data
+            }
+            _ => return Ok(output),
+        };
+        output
+            .data
+            .extend_from_slice(data)
+            .map_err(|()| ErrorKind::InvalidSize)?;
+        Ok(output)
+    }
+}
+
+// Key returned by KDF. Encrypted keys come with the nonce `host::io_decrypt`
+// needs.
+#[derive(Clone, Debug, Default)]
+pub struct KdfOutput {
+    data: Vec<u8, 64>,
+    nonce: Option<Block>,
+}
+
+impl KdfOutput {
+    // Empty unless the target is one of the outputs.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn nonce(&self) -> Option<Block> {
+        self.nonce
+    }
+}
+
+// Memory zones consist of config, data and OTP.
+pub struct Memory<'a, T> {
+    atca: &'a mut AtCaClient<T>,
+}
+
+impl<'a, T> Memory<'a, T>
+where
+    T: Transport,
 {
     pub fn serial_number(&mut self) -> Result<Serial, Error> {
         let packet =
@@ -227,13 +726,14 @@ where
 
     pub fn pubkey(&mut self, key_id: Slot) -> Result<PublicKey, Error> {
         let mut pubkey = PublicKey::default();
+        let mut session = self.atca.session();
         CertificateRepr::new()
             .enumerate()
             .scan(0, |offset, (i, ranges)| {
-                let result = command::Read::new(self.atca.packet_builder())
+                let result = command::Read::new(session.packet_builder())
                     .slot(key_id, i as u8)
                     .and_then(|packet| {
-                        let response = self.atca.execute(packet)?;
+                        let response = session.execute(packet)?;
                         for range in ranges {
                             let dst = *offset..*offset + range.len();
                             pubkey.as_mut()[dst].copy_from_slice(&response.as_ref()[range.clone()]);
@@ -247,8 +747,17 @@ where
             .map(|()| pubkey)
     }
 
+    // SHA-256 of the public key in a slot, computed on the device.
+    pub fn pubkey_digest(&mut self, key_id: Slot) -> Result<Digest, Error> {
+        let mut sha = self.atca.sha(None);
+        sha.init()?;
+        sha.public_key(key_id)?;
+        sha.finalize()
+    }
+
     pub fn write_pubkey(&mut self, key_id: Slot, pubkey: impl AsRef<[u8]>) -> Result<(), Error> {
         let mut data = Block::default();
+        let mut session = self.atca.session();
         CertificateRepr::new()
             .enumerate()
             .scan(0, |offset, (i, ranges)| {
@@ -261,15 +770,110 @@ where
                     *offset += range.len();
                 }
 
-                let result = command::Write::new(self.atca.packet_builder())
+                let result = command::Write::new(session.packet_builder())
                     .slot(key_id, i as u8, &data)
-                    .and_then(|packet| self.atca.execute(packet).map(drop));
+                    .and_then(|packet| session.execute(packet).map(drop));
 
                 Some(result)
             })
             .try_for_each(identity)
     }
 
+    // Read any byte range of a slot. Fails with `ErrorKind::InvalidSize` if
+    // the range runs past the end of the slot.
+    pub fn read_slot(&mut self, slot: Slot, offset: usize, buffer: &mut [u8]) -> Result<(), Error> {
+        let range = slot_range(slot, offset, buffer.len())?;
+        let mut session = self.atca.session();
+        memory::accesses(align(&range)).try_for_each(|(size, position)| {
+            let (block, word) = locate(position);
+            let packet =
+                command::Read::new(session.packet_builder()).data(slot, size, block, word)?;
+            let response = session.execute(packet)?;
+            let chunk = position..position + size.len();
+            let overlap = chunk.start.max(range.start)..chunk.end.min(range.end);
+            buffer[overlap.start - offset..overlap.end - offset].copy_from_slice(
+                &response.as_ref()[overlap.start - position..overlap.end - position],
+            );
+            Ok(())
+        })
+    }
+
+    // Write any byte range of a slot. Words partially covered by the range
+    // are read and merged first. Fails with `ErrorKind::InvalidSize` if the
+    // range runs past the end of the slot.
+    pub fn write_slot(&mut self, slot: Slot, offset: usize, data: &[u8]) -> Result<(), Error> {
+        let range = slot_range(slot, offset, data.len())?;
+        let mut session = self.atca.session();
+        memory::accesses(align(&range)).try_for_each(|(size, position)| {
+            let (block, word) = locate(position);
+            let chunk = position..position + size.len();
+            let overlap = chunk.start.max(range.start)..chunk.end.min(range.end);
+            let mut buffer = Block::default();
+            let buffer = &mut buffer.as_mut()[..size.len()];
+            if overlap != chunk {
+                let packet =
+                    command::Read::new(session.packet_builder()).data(slot, size, block, word)?;
+                buffer.copy_from_slice(session.execute(packet)?.as_ref());
+            }
+            buffer[overlap.start - position..overlap.end - position]
+                .copy_from_slice(&data[overlap.start - offset..overlap.end - offset]);
+            let packet = command::Write::new(session.packet_builder())
+                .data(slot, size, block, word, &buffer)?;
+            session.execute(packet).map(drop)
+        })
+    }
+
+    // Write a block of a slot whose WriteConfig demands encryption. The
+    // session key comes from a random nonce and GenDig over the write key,
+    // which the host mirrors to encrypt the data and compute the input MAC.
+    // Draw `num_in` from a host RNG for every call. Otherwise a device
+    // replaying RandOut gets the same session key twice.
+    pub fn write_slot_encrypted(
+        &mut self,
+        slot: Slot,
+        block: u8,
+        data: &Block,
+        write_key_slot: Slot,
+        write_key: &Block,
+        num_in: &[u8; 20],
+    ) -> Result<(), Error> {
+        let address = Zone::Data.get_slot_addr(slot, block)?;
+        let mut session = self.atca.session();
+        let serial = session.memory().serial_number()?;
+        let temp_key = session.gen_dig(write_key_slot, write_key, num_in, &serial)?;
+        let data = (*data).into();
+        let mac = temp_key.write_mac(Zone::Data.encode(Size::Block), address, &data, &serial);
+        let ciphertext = temp_key.xor(&data);
+        let packet = command::Write::new(session.packet_builder()).encrypted(
+            slot,
+            block,
+            &ciphertext,
+            &mac,
+        )?;
+        session.execute(packet).map(drop)
+    }
+
+    // Read a block of a secret slot with EncryptRead set. The device encrypts
+    // the contents with TempKey, set up by GenDig over the read key, and the
+    // host decrypts them with its own copy. `num_in` should be fresh from a
+    // host RNG, as for `write_slot_encrypted`.
+    pub fn read_slot_encrypted(
+        &mut self,
+        slot: Slot,
+        block: u8,
+        read_key_slot: Slot,
+        read_key: &Block,
+        num_in: &[u8; 20],
+    ) -> Result<Block, Error> {
+        let mut session = self.atca.session();
+        let serial = session.memory().serial_number()?;
+        let temp_key = session.gen_dig(read_key_slot, read_key, num_in, &serial)?;
+        let packet = command::Read::new(session.packet_builder()).slot(slot, block)?;
+        let response = session.execute(packet)?;
+        let ciphertext = Block::try_from(response.as_ref())?;
+        Ok(temp_key.xor(&ciphertext.into()).into())
+    }
+
     pub fn write_aes_key(&mut self, key_id: Slot, aes_key: impl AsRef<[u8]>) -> Result<(), Error> {
         let mut data = Block::default();
         data.as_mut()[..0x10].copy_from_slice(aes_key.as_ref());
@@ -293,13 +897,13 @@ where
     pub fn is_slot_locked(&mut self, slot: Slot) -> Result<bool, Error> {
         let zone = Zone::Config;
         let size = Size::Word;
-        let block = 2;
-        let word_offset = 6;
+        let (block, word_offset, pos) = Zone::locate_index(ConfigZone::SLOT_LOCKED_INDEX);
         let packet =
             command::Read::new(self.atca.packet_builder()).read(zone, size, block, word_offset)?;
         let response = self.atca.execute(packet)?;
         let word = Word::try_from(response.as_ref())?;
-        let slot_locked_bytes = word.as_ref()[..2]
+        let pos = pos as usize;
+        let slot_locked_bytes = word.as_ref()[pos..pos + 2]
             .try_into()
             .map(u16::from_le_bytes)
             .unwrap_or_else(|_| unreachable!());
@@ -308,8 +912,7 @@ where
 
     pub fn is_locked(&mut self, zone: Zone) -> Result<bool, Error> {
         let size = Size::Word;
-        let block = 2;
-        let word_offset = 5;
+        let (block, word_offset, _) = Zone::locate_index(ConfigZone::LOCK_VALUE_INDEX);
         let packet = command::Read::new(self.atca.packet_builder()).read(
             Zone::Config,
             size,
@@ -318,11 +921,12 @@ where
         )?;
         let response = self.atca.execute(packet)?;
         let word = Word::try_from(response.as_ref())?;
-        match zone {
-            Zone::Config => Ok(word.as_ref()[3] != 0x55),
-            Zone::Data => Ok(word.as_ref()[2] != 0x55),
-            Zone::Otp => Err(ErrorKind::BadParam.into()),
-        }
+        let index = match zone {
+            Zone::Config => ConfigZone::LOCK_CONFIG_INDEX,
+            // OTP zone is locked along with data zone.
+            Zone::Data | Zone::Otp => ConfigZone::LOCK_VALUE_INDEX,
+        };
+        Ok(word.as_ref()[index % Size::Word.len()] != 0x55)
     }
 
     pub fn lock_slot(&mut self, key_id: Slot) -> Result<(), Error> {
@@ -340,8 +944,35 @@ where
         self.atca.execute(packet).map(drop)
     }
 
+    // Lock the config zone provided it holds the expected image. The CRC
+    // over the whole zone makes the device refuse to lock if anything
+    // changed in the meantime.
+    pub fn lock_config_verified(&mut self, expected: &ConfigZone) -> Result<(), Error> {
+        let mut session = self.atca.session();
+        let current = session.memory().read_config_zone()?;
+        if current != *expected {
+            return Err(ErrorKind::ZoneMismatch.into());
+        }
+        let crc = CRC16.checksum(&current.to_bytes());
+        session.memory().lock_crc(Zone::Config, crc)
+    }
+
+    // Lock data and OTP zones provided they hold the expected images. Neither
+    // zone is readable before locking, so it is up to the device to compare
+    // the CRC over both zones with its contents. It refuses to lock on
+    // mismatch.
+    pub fn lock_data_verified(&mut self, data: &[u8], otp: &[u8]) -> Result<(), Error> {
+        if data.len() != Zone::Data.size() || otp.len() != Zone::Otp.size() {
+            return Err(ErrorKind::InvalidSize.into());
+        }
+        let mut digest = CRC16.digest();
+        digest.update(data);
+        digest.update(otp);
+        self.lock_crc(Zone::Data, digest.finalize())
+    }
+
     pub fn chip_options(&mut self) -> Result<u16, Error> {
-        let (block, offset, pos) = Zone::locate_index(Self::CHIP_OPTIONS_INDEX);
+        let (block, offset, pos) = Zone::locate_index(ConfigZone::CHIP_OPTIONS_INDEX);
         let range = pos as usize..pos as usize + 2;
         self.read_config(Size::Word, block, offset).map(|resp| {
             resp.as_ref()[range]
@@ -351,30 +982,152 @@ where
         })
     }
 
-    pub fn permission(&mut self, slot: Slot) -> Result<u16, Error> {
-        let index = Self::SLOT_CONFIG_INDEX + (slot as usize * 2);
+    // 7-bit I2C address the device answers on once the config zone is locked.
+    pub fn i2c_address(&mut self) -> Result<u8, Error> {
+        let (block, offset, pos) = Zone::locate_index(ConfigZone::I2C_ADDRESS_INDEX);
+        self.read_config(Size::Word, block, offset)
+            .map(|resp| resp.as_ref()[pos as usize] >> 1)
+    }
+
+    pub fn count_match(&mut self) -> Result<CountMatch, Error> {
+        let (block, offset, pos) = Zone::locate_index(ConfigZone::COUNT_MATCH_INDEX);
+        self.read_config(Size::Word, block, offset)
+            .map(|resp| resp.as_ref()[pos as usize].into())
+    }
+
+    // Value Counter0 is not allowed to pass, if CountMatch is enabled. It is
+    // read from the CountMatch key slot, which therefore has to be readable.
+    pub fn count_match_value(&mut self) -> Result<Option<u32>, Error> {
+        let count_match = self.count_match()?;
+        if !count_match.enabled() {
+            return Ok(None);
+        }
+        let mut value = [0x00; 8];
+        self.read_slot(count_match.key(), 0, &mut value)?;
+        CountMatch::decode_value(&value)
+            .map(Some)
+            .ok_or_else(|| ErrorKind::FuncFail.into())
+    }
+
+    // Reprogram the I2C address before locking the config zone. The device
+    // keeps answering on its current address until it wakes up after locking.
+    pub fn set_i2c_address(&mut self, address: u8) -> Result<(), Error> {
+        if address > 0x7f {
+            return Err(ErrorKind::BadParam.into());
+        }
+        let (block, offset, pos) = Zone::locate_index(ConfigZone::I2C_ADDRESS_INDEX);
+        let mut word = Word::try_from(self.read_config(Size::Word, block, offset)?.as_ref())?;
+        word.as_mut()[pos as usize] = address << 1;
+        self.write_config(Size::Word, block, offset, word)
+    }
+
+    pub fn permission(&mut self, slot: Slot) -> Result<SlotConfig, Error> {
+        let index = ConfigZone::SLOT_CONFIG_INDEX + (slot as usize * 2);
         let (block, offset, pos) = Zone::locate_index(index);
         let range = pos as usize..pos as usize + 2;
         self.read_config(Size::Word, block, offset).map(|resp| {
             resp.as_ref()[range]
                 .try_into()
-                .map(u16::from_le_bytes)
+                .map(SlotConfig::from_le_bytes)
                 .unwrap_or_else(|_| unreachable!())
         })
     }
 
-    pub fn key_type(&mut self, slot: Slot) -> Result<u16, Error> {
-        let index = Self::KEY_CONFIG_INDEX + (slot as usize * 2);
+    pub fn key_type(&mut self, slot: Slot) -> Result<KeyConfig, Error> {
+        let index = ConfigZone::KEY_CONFIG_INDEX + (slot as usize * 2);
         let (block, offset, pos) = Zone::locate_index(index);
         let range = pos as usize..pos as usize + 2;
         self.read_config(Size::Word, block, offset).map(|resp| {
             resp.as_ref()[range]
                 .try_into()
-                .map(u16::from_le_bytes)
+                .map(KeyConfig::from_le_bytes)
                 .unwrap_or_else(|_| unreachable!())
         })
     }
 
+    // Read the whole config zone.
+    pub fn read_config_zone(&mut self) -> Result<ConfigZone, Error> {
+        let mut bytes = [0x00; ConfigZone::SIZE];
+        let mut session = self.atca.session();
+        let mut memory = session.memory();
+        bytes
+            .chunks_mut(Size::Block.len())
+            .enumerate()
+            .try_for_each(|(block, chunk)| {
+                let response = memory.read_config(Size::Block, block as u8, 0)?;
+                chunk.copy_from_slice(response.as_ref());
+                Ok::<_, Error>(())
+            })?;
+        ConfigZone::try_from(&bytes[..])
+    }
+
+    // Write the config zone but its read-only bytes, i.e. serial number,
+    // revision, UserExtra, UserExtraAdd and lock bytes.
+    pub fn write_config_zone(&mut self, config: &ConfigZone) -> Result<(), Error> {
+        let bytes = config.to_bytes();
+        let mut session = self.atca.session();
+        let mut memory = session.memory();
+        config::writable_words().try_for_each(|(size, block, offset)| {
+            let start = block as usize * Size::Block.len() + offset as usize * Size::Word.len();
+            memory.write_config(size, block, offset, &bytes[start..start + size.len()])
+        })
+    }
+
+    // OTP mode as decoded by `ConfigZone::otp_mode`, reading only the word of
+    // LockValue.
+    pub fn otp_mode(&mut self) -> Result<OtpMode, Error> {
+        self.is_locked(Zone::Otp).map(|locked| {
+            if locked {
+                OtpMode::ReadOnly
+            } else {
+                OtpMode::Writable
+            }
+        })
+    }
+
+    // Read a word or a block of the 64-byte OTP zone. Fails with
+    // `ErrorKind::NotLocked` until the data zone is locked. `mode` comes from
+    // `otp_mode` or from a `ConfigZone` at hand, so that accesses in a row need
+    // not read it again.
+    pub fn read_otp(
+        &mut self,
+        mode: OtpMode,
+        size: Size,
+        block: u8,
+        offset: u8,
+    ) -> Result<Response<'_>, Error> {
+        if mode == OtpMode::Writable {
+            return Err(ErrorKind::NotLocked.into());
+        }
+        let packet =
+            command::Read::new(self.atca.packet_builder()).read(Zone::Otp, size, block, offset)?;
+        self.atca.execute(packet)
+    }
+
+    // Write a word or a block of the OTP zone. Fails with
+    // `ErrorKind::DataZoneLocked` once the data zone is locked. See `read_otp`
+    // for `mode`.
+    pub fn write_otp(
+        &mut self,
+        mode: OtpMode,
+        size: Size,
+        block: u8,
+        offset: u8,
+        data: impl AsRef<[u8]>,
+    ) -> Result<(), Error> {
+        if mode == OtpMode::ReadOnly {
+            return Err(ErrorKind::DataZoneLocked.into());
+        }
+        let packet = command::Write::new(self.atca.packet_builder()).write(
+            Zone::Otp,
+            size,
+            block,
+            offset,
+            data,
+        )?;
+        self.atca.execute(packet).map(drop)
+    }
+
     // TODO: Testing purpose only.
     pub fn read_config(
         &mut self,
@@ -409,17 +1162,36 @@ where
     }
 }
 
+// Byte range within a slot, provided it fits in.
+fn slot_range(slot: Slot, offset: usize, len: usize) -> Result<Range<usize>, Error> {
+    match offset.checked_add(len) {
+        Some(end) if end <= slot.size() => Ok(offset..end),
+        _ => Err(ErrorKind::InvalidSize.into()),
+    }
+}
+
+// Smallest word aligned range covering the given one.
+fn align(range: &Range<usize>) -> Range<usize> {
+    let word = Size::Word.len();
+    range.start / word * word..range.end.div_ceil(word) * word
+}
+
+// Block and word offset of a byte position within a slot.
+fn locate(position: usize) -> (u8, u8) {
+    let (block, offset, _) = Zone::locate_index(position);
+    (block, offset)
+}
+
 // Method signature is taken from cipher::block::BlockCipher.
 // AES
-pub struct Aes<'a, PHY, D> {
-    atca: &'a mut AtCaClient<PHY, D>,
+pub struct Aes<'a, T> {
+    atca: &'a mut AtCaClient<T>,
     key_id: Slot,
 }
 
-impl<'a, PHY, D> Aes<'a, PHY, D>
+impl<'a, T> Aes<'a, T>
 where
-    PHY: i2c::I2c,
-    D: DelayNs,
+    T: Transport,
 {
     pub fn encrypt(&mut self, plaintext: &[u8], ciphertext: &mut [u8]) -> Result<(), Error> {
         use command::Aes as AesCmd;
@@ -474,32 +1246,168 @@ where
     }
 }
 
-// SHA
-pub struct Sha<'a, PHY, D> {
-    atca: &'a mut AtCaClient<PHY, D>,
+// HMAC/SHA-256 keyed with a slot. The calculation starts with the first
+// update and starts over after finalization. The digest is returned, or left
+// in TempKey for commands that follow in the same session.
+pub struct Hmac<'a, T>
+where
+    T: Transport,
+{
+    sha: Sha<'a, T>,
+    started: bool,
+}
+
+impl<'a, T> Hmac<'a, T>
+where
+    T: Transport,
+{
+    pub fn update(&mut self, data: impl AsRef<[u8]>) -> Result<(), Error> {
+        self.start()?;
+        self.sha.update(data)
+    }
+
+    pub fn chain(&mut self, data: impl AsRef<[u8]>) -> Result<&mut Self, Error> {
+        self.update(data)?;
+        Ok(self)
+    }
+
+    pub fn finalize(&mut self) -> Result<Digest, Error> {
+        self.end(false)?.as_ref().try_into()
+    }
+
+    // Leave the digest in TempKey instead of returning it.
+    pub fn finalize_into_temp_key(&mut self) -> Result<(), Error> {
+        self.end(true).map(drop)
+    }
+
+    pub fn digest(&mut self, data: &[u8]) -> Result<Digest, Error> {
+        self.reset();
+        self.update(data)?;
+        self.finalize()
+    }
+
+    // Discard the ongoing calculation.
+    pub fn reset(&mut self) {
+        self.sha.remaining_bytes.clear();
+        self.started = false;
+    }
+
+    fn start(&mut self) -> Result<(), Error> {
+        if !self.started {
+            self.sha.init()?;
+            self.started = true;
+        }
+        Ok(())
+    }
+
+    fn end(&mut self, temp_key: bool) -> Result<Response<'_>, Error> {
+        self.start()?;
+        let sha = &mut self.sha;
+        let packet = command::Sha::new(sha.atca.packet_builder())
+            .hmac_end(&sha.remaining_bytes, temp_key)?;
+        sha.remaining_bytes.clear();
+        self.started = false;
+        sha.atca.execute(packet)
+    }
+}
+
+impl<'a, T> mac::Update for Hmac<'a, T>
+where
+    T: Transport,
+{
+    fn update(&mut self, data: &[u8]) {
+        Hmac::update(self, data).expect("update operation failed");
+    }
+}
+
+impl<'a, T> mac::OutputSizeUser for Hmac<'a, T>
+where
+    T: Transport,
+{
+    type OutputSize = mac::consts::U32;
+}
+
+impl<'a, T> mac::FixedOutput for Hmac<'a, T>
+where
+    T: Transport,
+{
+    fn finalize_into(mut self, out: &mut mac::Output<Self>) {
+        let digest = Hmac::finalize(&mut self).expect("finalize operation failed");
+        out.copy_from_slice(digest.as_ref());
+    }
+}
+
+impl<'a, T> mac::FixedOutputReset for Hmac<'a, T>
+where
+    T: Transport,
+{
+    fn finalize_into_reset(&mut self, out: &mut mac::Output<Self>) {
+        let digest = Hmac::finalize(self).expect("finalize operation failed");
+        out.copy_from_slice(digest.as_ref());
+    }
+}
+
+impl<'a, T> mac::Reset for Hmac<'a, T>
+where
+    T: Transport,
+{
+    fn reset(&mut self) {
+        Hmac::reset(self);
+    }
+}
+
+impl<'a, T> mac::MacMarker for Hmac<'a, T> where T: Transport {}
+
+// SHA. The device is kept awake until the hasher is dropped, because the
+// SHA context does not survive sleep.
+pub struct Sha<'a, T>
+where
+    T: Transport,
+{
+    atca: Session<'a, T>,
     remaining_bytes: Vec<u8, 64>,
     key_id: Option<Slot>,
 }
 
-impl<'a, PHY, D> Sha<'a, PHY, D>
+impl<'a, T> Sha<'a, T>
 where
-    PHY: i2c::I2c,
-    D: DelayNs,
+    T: Transport,
 {
+    // Start over, discarding bytes of a previous calculation.
     pub fn init(&mut self) -> Result<(), Error> {
+        self.remaining_bytes.clear();
         let packet = command::Sha::new(self.atca.packet_builder()).start(self.key_id)?;
         self.atca.execute(packet).map(drop)
     }
 
-    // See digest::Update
+    // See digest::Update. Bytes short of a whole block are kept until the
+    // next call.
     pub fn update(&mut self, data: impl AsRef<[u8]>) -> Result<(), Error> {
-        let capacity = 0x40;
-        let length = data.as_ref().len();
+        let capacity = self.remaining_bytes.capacity();
+        let mut data = data.as_ref();
+
+        // Complete the block left over from previous calls first.
+        if !self.remaining_bytes.is_empty() {
+            let length = data.len().min(capacity - self.remaining_bytes.len());
+            let (head, tail) = data.split_at(length);
+            self.remaining_bytes
+                .extend_from_slice(head)
+                .unwrap_or_else(|()| unreachable!("Block never overflows."));
+            data = tail;
+            if self.remaining_bytes.len() < capacity {
+                return Ok(());
+            }
+            let packet =
+                command::Sha::new(self.atca.packet_builder()).update(&self.remaining_bytes)?;
+            self.atca.execute(packet)?;
+            self.remaining_bytes.clear();
+        }
 
-        // Store remainging bytes for later processing
-        let remainder_length = data.as_ref().len() % capacity;
-        let (bytes, remainder) = data.as_ref().split_at(length - remainder_length);
-        self.remaining_bytes.extend_from_slice(remainder).ok();
+        // Store remaining bytes for later processing
+        let (bytes, remainder) = data.split_at(data.len() - data.len() % capacity);
+        self.remaining_bytes
+            .extend_from_slice(remainder)
+            .unwrap_or_else(|()| unreachable!("Remainder is shorter than a block."));
 
         // Execute update command
         bytes.chunks(capacity).try_for_each(|chunk| {
@@ -509,16 +1417,13 @@ where
     }
 
     pub fn chain(&mut self, data: impl AsRef<[u8]>) -> Result<&mut Self, Error> {
-        if self.remaining_bytes.len() != 0 {
-            // TODO: Concatinate remaining bytes and input data.
-        }
-
         self.update(data)?;
         Ok(self)
     }
 
     pub fn finalize(&mut self) -> Result<Digest, Error> {
         let packet = command::Sha::new(self.atca.packet_builder()).end(&self.remaining_bytes)?;
+        self.remaining_bytes.clear();
         self.atca.execute(packet)?.as_ref().try_into()
     }
 
@@ -527,42 +1432,86 @@ where
         self.update(data)?;
         self.finalize()
     }
+
+    // Feed the 64-byte public key stored in a slot. The device hashes
+    // whole blocks only, so bytes hashed so far must add up to a multiple
+    // of 64, or this fails with `ErrorKind::FuncFail`.
+    pub fn public_key(&mut self, slot: Slot) -> Result<(), Error> {
+        if !self.remaining_bytes.is_empty() {
+            return Err(ErrorKind::FuncFail.into());
+        }
+        let packet = command::Sha::new(self.atca.packet_builder()).public_key(slot)?;
+        self.atca.execute(packet).map(drop)
+    }
+
+    // Read out the ongoing calculation, along with bytes not sent to the
+    // device yet. Other commands may run in between, as long as the device
+    // does not go to sleep, and the calculation is resumed with
+    // `restore_context`. HMAC contexts cannot be saved.
+    pub fn save_context(&mut self) -> Result<ShaContext, Error> {
+        let packet = command::Sha::new(self.atca.packet_builder()).read_context()?;
+        let response = self.atca.execute(packet)?;
+        let mut context = Vec::new();
+        context
+            .extend_from_slice(response.as_ref())
+            .map_err(|()| Error::from(ErrorKind::InvalidSize))?;
+        let remaining_bytes = self.remaining_bytes.clone();
+        Ok(ShaContext {
+            context,
+            remaining_bytes,
+        })
+    }
+
+    // Resume a calculation saved earlier, in place of `init`.
+    pub fn restore_context(&mut self, context: &ShaContext) -> Result<(), Error> {
+        let packet =
+            command::Sha::new(self.atca.packet_builder()).write_context(&context.context)?;
+        self.atca.execute(packet)?;
+        self.remaining_bytes = context.remaining_bytes.clone();
+        Ok(())
+    }
+}
+
+// A snapshot of a SHA calculation. Its contents are opaque.
+#[derive(Clone, Debug)]
+pub struct ShaContext {
+    context: Vec<u8, { command::Sha::CONTEXT_MAX_SIZE }>,
+    remaining_bytes: Vec<u8, 64>,
 }
 
 // Method signatures are taken from signature::DigestSigner.
 // Sign
-pub struct Sign<'a, PHY, D> {
-    atca: &'a mut AtCaClient<PHY, D>,
+pub struct Sign<'a, T> {
+    atca: &'a mut AtCaClient<T>,
     key_id: Slot,
 }
 
-impl<'a, PHY, D> Sign<'a, PHY, D>
+impl<'a, T> Sign<'a, T>
 where
-    PHY: i2c::I2c,
-    D: DelayNs,
+    T: Transport,
 {
     // Takes a 32-byte message to be signed, typically the SHA256 hash of the
     // full message.
     pub fn sign_digest(&mut self, digest: &Digest) -> Result<Signature, Error> {
+        let mut session = self.atca.session();
         // 1. Random value generation
-        self.atca.random()?;
+        session.random()?;
         // 2. Nonce load
-        self.atca.write_message_digest_buffer(digest)?;
+        session.write_message_digest_buffer(digest)?;
         // 3. Sign
-        let packet = command::Sign::new(self.atca.packet_builder()).external(self.key_id)?;
-        self.atca.execute(packet)?.as_ref().try_into()
+        let packet = command::Sign::new(session.packet_builder()).external(self.key_id)?;
+        session.execute(packet)?.as_ref().try_into()
     }
 }
 
-pub struct Verify<'a, PHY, D> {
-    atca: &'a mut AtCaClient<PHY, D>,
+pub struct Verify<'a, T> {
+    atca: &'a mut AtCaClient<T>,
     key_id: Slot,
 }
 
-impl<'a, PHY, D> Verify<'a, PHY, D>
+impl<'a, T> Verify<'a, T>
 where
-    PHY: i2c::I2c,
-    D: DelayNs,
+    T: Transport,
 {
     // Takes a 32-byte message to be signed, typically the SHA256 hash of the
     // full message and signature.
@@ -572,11 +1521,12 @@ where
         signature: &Signature,
         public_key: &PublicKey,
     ) -> Result<(), Error> {
+        let mut session = self.atca.session();
         // 1. Nonce load
-        self.atca.write_message_digest_buffer(digest)?;
+        session.write_message_digest_buffer(digest)?;
         // 2. Verify
         let packet =
-            command::Verify::new(self.atca.packet_builder()).external(signature, public_key)?;
-        self.atca.execute(packet).map(drop)
+            command::Verify::new(session.packet_builder()).external(signature, public_key)?;
+        session.execute(packet).map(drop)
     }
 }
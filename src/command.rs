@@ -58,6 +58,19 @@ impl TryFrom<&[u8]> for Block {
     }
 }
 
+impl From<[u8; 32]> for Block {
+    fn from(value: [u8; 32]) -> Self {
+        let value = value.into();
+        Self { value }
+    }
+}
+
+impl From<Block> for [u8; 32] {
+    fn from(block: Block) -> Self {
+        block.value.into()
+    }
+}
+
 impl AsRef<[u8]> for Block {
     fn as_ref(&self) -> &[u8] {
         self.value.as_ref()
@@ -235,13 +248,80 @@ impl AsMut<[u8]> for Digest {
     }
 }
 
+/// Destination of a nonce loaded in pass-through mode.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NonceTarget {
+    /// TempKey, the input of most cryptographic commands.
+    TempKey,
+    /// Message digest buffer, which Sign and Verify read messages from.
+    MessageDigestBuffer,
+    /// Alternate key buffer. It holds 32 bytes only.
+    AlternateKeyBuffer,
+}
+
+/// One of the two 21-bit monotonic counters.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CounterId {
+    /// Counter0, which can also limit key uses and be bound to CountMatch.
+    Counter0 = 0,
+    Counter1 = 1,
+}
+
+/// Key KDF derives from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KdfSource {
+    TempKey,
+    /// Upper 32 bytes of TempKey.
+    TempKeyUpper,
+    Slot(Slot),
+    AlternateKeyBuffer,
+}
+
+/// Destination of the key KDF derives.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KdfTarget {
+    TempKey,
+    /// Upper 32 bytes of TempKey.
+    TempKeyUpper,
+    Slot(Slot),
+    AlternateKeyBuffer,
+    /// Return the key in the clear.
+    Output,
+    /// Return the key encrypted with the IO protection key, followed by the
+    /// nonce to decrypt it with.
+    OutputEncrypted,
+}
+
+/// Private key ECDH runs with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EcdhSource {
+    Slot(Slot),
+    /// Ephemeral key GenKey left in TempKey.
+    TempKey,
+}
+
+/// Destination of the shared secret.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum EcdhTarget {
+    /// Slot N|1 of a private key in slot N, or any slot for a key in TempKey.
+    Slot(Slot),
+    TempKey,
+    Output,
+    OutputEncrypted,
+}
+
+/// Input keying material of HKDF.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KdfMessage<'a> {
+    Input(&'a [u8]),
+    TempKey,
+}
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub(crate) enum OpCode {
     /// CheckMac command op-code
-    #[allow(dead_code)]
     CheckMac = 0x28,
     /// DeriveKey command op-code
-    #[allow(dead_code)]
     DeriveKey = 0x1C,
     /// Info command op-code
     Info = 0x30,
@@ -255,7 +335,6 @@ pub(crate) enum OpCode {
     /// Lock command op-code
     Lock = 0x17,
     /// MAC command op-code
-    #[allow(dead_code)]
     Mac = 0x08,
     /// Nonce command op-code
     Nonce = 0x16,
@@ -278,17 +357,14 @@ pub(crate) enum OpCode {
     /// Write command op-code
     Write = 0x12,
     /// ECDH command op-code
-    #[allow(dead_code)]
     Ecdh = 0x43,
     /// Counter command op-code
-    #[allow(dead_code)]
     Counter = 0x24,
     /// SHA command op-code
     Sha = 0x47,
     /// AES command op-code
     Aes = 0x51,
     /// KDF command op-code
-    #[allow(dead_code)]
     Kdf = 0x56,
     /// Secure Boot command op-code
     #[allow(dead_code)]
@@ -298,11 +374,8 @@ pub(crate) enum OpCode {
     SelfTest = 0x77,
 }
 
-#[allow(dead_code)]
 pub(crate) struct CheckMac<'a>(PacketBuilder<'a>);
-#[allow(dead_code)]
 pub(crate) struct Counter<'a>(PacketBuilder<'a>);
-#[allow(dead_code)]
 pub(crate) struct DeriveKey<'a>(PacketBuilder<'a>);
 #[allow(dead_code)]
 pub(crate) struct Ecdh<'a>(PacketBuilder<'a>);
@@ -312,7 +385,6 @@ pub(crate) struct GenKey<'a>(PacketBuilder<'a>);
 pub(crate) struct HMac<'a>(PacketBuilder<'a>);
 pub(crate) struct Info<'a>(PacketBuilder<'a>);
 pub(crate) struct Lock<'a>(PacketBuilder<'a>);
-#[allow(dead_code)]
 pub(crate) struct Mac<'a>(PacketBuilder<'a>);
 pub(crate) struct NonceCtx<'a> {
     builder: PacketBuilder<'a>,
@@ -335,19 +407,89 @@ pub(crate) struct Verify<'a>(PacketBuilder<'a>);
 pub(crate) struct Write<'a>(PacketBuilder<'a>);
 pub(crate) struct Sha<'a>(PacketBuilder<'a>);
 pub(crate) struct Aes<'a>(PacketBuilder<'a>);
-#[allow(dead_code)]
 pub(crate) struct Kdf<'a>(PacketBuilder<'a>);
 #[allow(dead_code)]
 pub(crate) struct SecureBoot<'a>(PacketBuilder<'a>);
 #[allow(dead_code)]
 pub(crate) struct SelfTest<'a>(PacketBuilder<'a>);
 
-#[allow(dead_code)]
+/// Counter
+impl<'a> Counter<'a> {
+    const MODE_READ: u8 = 0x00;
+    const MODE_INCREMENT: u8 = 0x01;
+    /// Counters saturate at 2^21 - 1.
+    pub(crate) const MAX_VALUE: u32 = 0x1f_ffff;
+
+    pub(crate) fn new(builder: PacketBuilder<'a>) -> Self {
+        Self(builder)
+    }
+
+    pub(crate) fn read(&mut self, counter_id: CounterId) -> Result<Packet, Error> {
+        let packet = self
+            .0
+            .opcode(OpCode::Counter)
+            .mode(Self::MODE_READ)
+            .param2(counter_id as u16)
+            .build()?;
+        Ok(packet)
+    }
+
+    pub(crate) fn increment(&mut self, counter_id: CounterId) -> Result<Packet, Error> {
+        let packet = self
+            .0
+            .opcode(OpCode::Counter)
+            .mode(Self::MODE_INCREMENT)
+            .param2(counter_id as u16)
+            .build()?;
+        Ok(packet)
+    }
+}
+
+/// CheckMac
+impl<'a> CheckMac<'a> {
+    pub(crate) fn new(builder: PacketBuilder<'a>) -> Self {
+        Self(builder)
+    }
+
+    // Compare a response to a challenge, computed elsewhere, against the
+    // MAC over the key in the given slot.
+    pub(crate) fn check_mac(
+        &mut self,
+        key_id: Slot,
+        challenge: &Block,
+        response: &Block,
+        other_data: &[u8; 13],
+    ) -> Result<Packet, Error> {
+        let buffer = self.0.pdu_buffer();
+        let (challenge_buffer, buffer) = buffer.split_at_mut(Size::Block.len());
+        let (response_buffer, buffer) = buffer.split_at_mut(Size::Block.len());
+        challenge_buffer.copy_from_slice(challenge.as_ref());
+        response_buffer.copy_from_slice(response.as_ref());
+        buffer[..other_data.len()].copy_from_slice(other_data);
+        let packet = self
+            .0
+            .opcode(OpCode::CheckMac)
+            .mode(0x00)
+            .param2(key_id as u16)
+            .pdu_length(Size::Block.len() * 2 + other_data.len())
+            .build()?;
+        Ok(packet)
+    }
+}
+
 impl<'a> Ecdh<'a> {
+    const MODE_SOURCE_TEMPKEY: u8 = 0x01;
+    const MODE_OUTPUT_ENCRYPTED: u8 = 0x02;
+    const MODE_COPY_SLOT: u8 = 0x04;
+    const MODE_COPY_TEMPKEY: u8 = 0x08;
+    const MODE_COPY_OUTPUT: u8 = 0x0c;
+
     pub(crate) fn new(builder: PacketBuilder<'a>) -> Self {
         Self(builder)
     }
 
+    /// Legacy mode, where ReadKey of the slot decides whether the secret is
+    /// returned or written to slot N|1.
     pub(crate) fn diffie_hellman(
         &mut self,
         private_key_id: Slot,
@@ -362,27 +504,93 @@ impl<'a> Ecdh<'a> {
             .build()?;
         Ok(packet)
     }
+
+    pub(crate) fn ecdh(
+        &mut self,
+        source: EcdhSource,
+        target: EcdhTarget,
+        public_key: &PublicKey,
+    ) -> Result<Packet, Error> {
+        let (source_mode, key_id) = match (source, target) {
+            (EcdhSource::Slot(slot), _) => (0x00, slot as u16),
+            (EcdhSource::TempKey, EcdhTarget::Slot(slot)) => {
+                (Self::MODE_SOURCE_TEMPKEY, slot as u16)
+            }
+            (EcdhSource::TempKey, _) => (Self::MODE_SOURCE_TEMPKEY, 0x0000),
+        };
+        let target_mode = match target {
+            EcdhTarget::Slot(_) => Self::MODE_COPY_SLOT,
+            EcdhTarget::TempKey => Self::MODE_COPY_TEMPKEY,
+            EcdhTarget::Output => Self::MODE_COPY_OUTPUT,
+            EcdhTarget::OutputEncrypted => Self::MODE_COPY_OUTPUT | Self::MODE_OUTPUT_ENCRYPTED,
+        };
+        let packet = self
+            .0
+            .opcode(OpCode::Ecdh)
+            .mode(source_mode | target_mode)
+            .param2(key_id)
+            .pdu_data(public_key)
+            .build()?;
+        Ok(packet)
+    }
 }
 
 // Used when signing an internally stored digest. The GenDig command uses
 // SHA-256 to combine a stored value with the contents of TempKey, which must
 // have been valid prior to the execution of this command.
-#[allow(dead_code)]
 impl<'a> GenDig<'a> {
     pub(crate) fn new(builder: PacketBuilder<'a>) -> Self {
         Self(builder)
     }
 
-    pub(crate) fn gendig(&mut self, key_id: Slot) -> Result<Packet, Error> {
+    pub(crate) fn gendig(&mut self, zone: Zone, key_id: Slot) -> Result<Packet, Error> {
         let packet = self
             .0
             .opcode(OpCode::GenDig)
+            .mode(zone as u8)
             .param2(key_id as u16)
             .build()?;
         Ok(packet)
     }
 }
 
+/// DeriveKey
+impl<'a> DeriveKey<'a> {
+    /// TempKey was loaded from an input rather than a random nonce.
+    const MODE_SOURCE_INPUT: u8 = 0x04;
+
+    pub(crate) fn new(builder: PacketBuilder<'a>) -> Self {
+        Self(builder)
+    }
+
+    /// Mode matching the source flag of TempKey, as the device requires.
+    pub(crate) fn mode(source_input: bool) -> u8 {
+        if source_input {
+            Self::MODE_SOURCE_INPUT
+        } else {
+            0x00
+        }
+    }
+
+    pub(crate) fn derive_key(
+        &mut self,
+        target: Slot,
+        source_input: bool,
+        mac: Option<&Block>,
+    ) -> Result<Packet, Error> {
+        let packet = self
+            .0
+            .opcode(OpCode::DeriveKey)
+            .mode(Self::mode(source_input))
+            .param2(target as u16);
+        if let Some(mac) = mac {
+            packet.pdu_data(mac);
+        }
+        let packet = packet.build()?;
+        Ok(packet)
+    }
+}
+
 /// GenKey
 impl<'a> GenKey<'a> {
     // Config zone should be locked, otherwise GenKey always fails regardless of
@@ -393,6 +601,7 @@ impl<'a> GenKey<'a> {
     const MODE_DIGEST: u8 = 0x08; // PubKey digest will be created after the public key is calculated
     #[allow(dead_code)]
     const MODE_PUBKEY_DIGEST: u8 = 0x10; // Calculate a digest on the public key
+    const KEY_ID_TEMP_KEY: u16 = 0xffff; // Private key goes into TempKey
 
     pub(crate) fn new(builder: PacketBuilder<'a>) -> Self {
         Self(builder)
@@ -408,6 +617,18 @@ impl<'a> GenKey<'a> {
         Ok(packet)
     }
 
+    /// Private key is generated into TempKey and lost once the device goes
+    /// idle. Only the public key leaves the device.
+    pub(crate) fn ephemeral_key(&mut self) -> Result<Packet, Error> {
+        let packet = self
+            .0
+            .opcode(OpCode::GenKey)
+            .mode(Self::MODE_PRIVATE)
+            .param2(Self::KEY_ID_TEMP_KEY)
+            .build()?;
+        Ok(packet)
+    }
+
     pub(crate) fn public_key(&mut self, key_id: Slot) -> Result<Packet, Error> {
         let packet = self
             .0
@@ -440,28 +661,28 @@ impl<'a> Info<'a> {
 
 impl<'a> Lock<'a> {
     const LOCK_ZONE_NO_CRC: u8 = 0x80;
+    const LOCK_ZONE_CONFIG: u8 = 0x00;
+    // Data and OTP zones are locked at once.
+    const LOCK_ZONE_DATA: u8 = 0x01;
 
     pub(crate) fn new(builder: PacketBuilder<'a>) -> Self {
         Self(builder)
     }
 
     pub(crate) fn zone(&mut self, zone: Zone, crc: Option<u16>) -> Result<Packet, Error> {
-        if matches!(zone, Zone::Otp) {
-            return Err(ErrorKind::BadParam.into());
-        }
+        let mode = match zone {
+            Zone::Config => Self::LOCK_ZONE_CONFIG,
+            Zone::Data => Self::LOCK_ZONE_DATA,
+            Zone::Otp => return Err(ErrorKind::BadParam.into()),
+        };
 
         let packet = match crc {
             None => self
                 .0
                 .opcode(OpCode::Lock)
-                .mode(Self::LOCK_ZONE_NO_CRC | zone as u8)
-                .build()?,
-            Some(crc) => self
-                .0
-                .opcode(OpCode::Lock)
-                .mode(zone as u8)
-                .param2(crc)
+                .mode(Self::LOCK_ZONE_NO_CRC | mode)
                 .build()?,
+            Some(crc) => self.0.opcode(OpCode::Lock).mode(mode).param2(crc).build()?,
         };
 
         Ok(packet)
@@ -474,67 +695,112 @@ impl<'a> Lock<'a> {
     }
 }
 
+/// MAC
+impl<'a> Mac<'a> {
+    const MODE_BLOCK2_TEMPKEY: u8 = 0x01; // Second 32 bytes are TempKey
+    const MODE_MASK: u8 = 0x77; // Bits 3 and 7 are 0.
+
+    pub(crate) fn new(builder: PacketBuilder<'a>) -> Self {
+        Self(builder)
+    }
+
+    // A challenge is sent unless the mode takes TempKey in its place.
+    pub(crate) fn mac(
+        &mut self,
+        key_id: Slot,
+        challenge: Option<&Block>,
+        mode: u8,
+    ) -> Result<Packet, Error> {
+        if mode & !Self::MODE_MASK != 0x00 {
+            return Err(ErrorKind::BadParam.into());
+        }
+        let builder = self.0.opcode(OpCode::Mac).mode(mode).param2(key_id as u16);
+        let packet = match (challenge, mode & Self::MODE_BLOCK2_TEMPKEY) {
+            (Some(challenge), 0x00) => builder.pdu_data(challenge).build()?,
+            (None, Self::MODE_BLOCK2_TEMPKEY) => builder.build()?,
+            _ => return Err(ErrorKind::BadParam.into()),
+        };
+        Ok(packet)
+    }
+}
+
 /// Nonce
 impl<'a> NonceCtx<'a> {
     #[allow(dead_code)]
     const MODE_MASK: u8 = 0x03; // Nonce mode bits 2 to 7 are 0.
-    #[allow(dead_code)]
     const MODE_SEED_UPDATE: u8 = 0x00; // Nonce mode: update seed
-    #[allow(dead_code)]
     const MODE_NO_SEED_UPDATE: u8 = 0x01; // Nonce mode: do not update seed
     #[allow(dead_code)]
     const MODE_INVALID: u8 = 0x02; // Nonce mode 2 is invalid.
     const MODE_PASSTHROUGH: u8 = 0x03; // Nonce mode: pass-through
     #[allow(dead_code)]
     const MODE_INPUT_LEN_MASK: u8 = 0x20; // Nonce mode: input size mask
-    #[allow(dead_code)]
     const MODE_INPUT_LEN_32: u8 = 0x00; // Nonce mode: input size is 32 bytes
-    #[allow(dead_code)]
     const MODE_INPUT_LEN_64: u8 = 0x20; // Nonce mode: input size is 64 bytes
     const MODE_TARGET_MASK: u8 = 0xc0; // Nonce mode: target mask
-    #[allow(dead_code)]
     const MODE_TARGET_TEMPKEY: u8 = 0x00; // Nonce mode: target is TempKey
     const MODE_TARGET_MSGDIGBUF: u8 = 0x40; // Nonce mode: target is Message Digest Buffer
-    #[allow(dead_code)]
     const MODE_TARGET_ALTKEYBUF: u8 = 0x80; // Nonce mode: target is Alternate Key Buffer
+    pub(crate) const NUM_IN_SIZE: usize = 20;
 
     pub(crate) fn new(builder: PacketBuilder<'a>) -> Self {
         let counter = 0x4432;
         Self { builder, counter }
     }
 
+    // Mode byte of random nonces, which TempKey is also calculated over.
+    pub(crate) fn random_mode(update_seed: bool) -> u8 {
+        match update_seed {
+            true => Self::MODE_SEED_UPDATE,
+            false => Self::MODE_NO_SEED_UPDATE,
+        }
+    }
+
     // TODO: Usage of Nonce, especially its correct timing is not clear. In
     // `test/api_atcab/atca_tests_aes.c`, AES encryption/decryption assumes
     // nonce value is loaded to TempKey in advance.
     pub(crate) fn message_digest_buffer(&mut self, msg: &Digest) -> Result<Packet, Error> {
-        let mode = Self::MODE_PASSTHROUGH | (Self::MODE_TARGET_MSGDIGBUF & Self::MODE_TARGET_MASK);
+        self.load(NonceTarget::MessageDigestBuffer, msg.as_ref())
+    }
+
+    // Pass 32 or 64 bytes through to the target. The alternate key buffer
+    // takes 32 bytes only.
+    pub(crate) fn load(&mut self, target: NonceTarget, value: &[u8]) -> Result<Packet, Error> {
+        let length = match (value.len(), target) {
+            (32, _) => Self::MODE_INPUT_LEN_32,
+            (64, NonceTarget::TempKey) | (64, NonceTarget::MessageDigestBuffer) => {
+                Self::MODE_INPUT_LEN_64
+            }
+            _ => return Err(ErrorKind::BadParam.into()),
+        };
+        let target = match target {
+            NonceTarget::TempKey => Self::MODE_TARGET_TEMPKEY,
+            NonceTarget::MessageDigestBuffer => Self::MODE_TARGET_MSGDIGBUF,
+            NonceTarget::AlternateKeyBuffer => Self::MODE_TARGET_ALTKEYBUF,
+        };
+        let mode = Self::MODE_PASSTHROUGH | length | (target & Self::MODE_TARGET_MASK);
         let packet = self
             .builder
             .opcode(OpCode::Nonce)
             .mode(mode)
-            .pdu_data(msg)
+            .pdu_data(value)
             .build()?;
         Ok(packet)
     }
 
-    #[allow(dead_code)]
-    fn load(&mut self) -> Self {
-        unimplemented!()
-    }
-
-    #[allow(dead_code)]
-    fn rand(&mut self) -> Self {
-        unimplemented!()
-    }
-
-    #[allow(dead_code)]
-    fn challenge(&mut self) -> Self {
-        unimplemented!()
-    }
-
-    #[allow(dead_code)]
-    fn challenge_seed_update(&mut self) -> Self {
-        unimplemented!()
+    // Combine a random number from the device with 20 bytes of NumIn into
+    // TempKey. Returns RandOut.
+    pub(crate) fn rand(&mut self, num_in: &[u8], update_seed: bool) -> Result<Packet, Error> {
+        if num_in.len() != Self::NUM_IN_SIZE {
+            return Err(ErrorKind::BadParam.into());
+        }
+        let packet = self
+            .builder
+            .opcode(OpCode::Nonce)
+            .mode(Self::random_mode(update_seed))
+            .pdu_data(num_in)
+            .build()?;
+        Ok(packet)
     }
 }
 
@@ -577,10 +843,22 @@ impl<'a> Sha<'a> {
     /// Complete the calculation and return the digest
     const MODE_SHA256_END: u8 = 0x02;
     /// Add 64 byte ECC public key in the slot to the SHA context
-    #[allow(dead_code)]
     const MODE_SHA256_PUBLIC: u8 = 0x03;
 
+    /// Read out the current SHA context
+    const MODE_READ_CONTEXT: u8 = 0x06;
+    /// Restore a SHA context read out earlier
+    const MODE_WRITE_CONTEXT: u8 = 0x07;
+    /// Largest context the device returns
+    pub(crate) const CONTEXT_MAX_SIZE: usize = 109;
+
     const MODE_HMAC_START: u8 = 0x04;
+    /// HMAC end on ATECC608. ATECC508 used 0x05 instead.
+    const MODE_HMAC_END: u8 = 0x02;
+    /// Leave the digest in TempKey, besides the output.
+    const MODE_TARGET_TEMPKEY: u8 = 0x00;
+    /// Return the digest only.
+    const MODE_TARGET_OUT_ONLY: u8 = 0xc0;
 
     pub(crate) fn new(builder: PacketBuilder<'a>) -> Self {
         Self(builder)
@@ -618,6 +896,67 @@ impl<'a> Sha<'a> {
         Ok(packet)
     }
 
+    /// Hash the public key stored in a slot, without it leaving the device.
+    pub(crate) fn public_key(&mut self, key_id: Slot) -> Result<Packet, Error> {
+        let packet = self
+            .0
+            .opcode(OpCode::Sha)
+            .mode(Self::MODE_SHA256_PUBLIC)
+            .param2(key_id as u16)
+            .build()?;
+        Ok(packet)
+    }
+
+    pub(crate) fn read_context(&mut self) -> Result<Packet, Error> {
+        let packet = self
+            .0
+            .opcode(OpCode::Sha)
+            .mode(Self::MODE_READ_CONTEXT)
+            .build()?;
+        Ok(packet)
+    }
+
+    pub(crate) fn write_context(&mut self, context: impl AsRef<[u8]>) -> Result<Packet, Error> {
+        let length = context.as_ref().len();
+        if length > Self::CONTEXT_MAX_SIZE {
+            return Err(ErrorKind::BadParam.into());
+        }
+
+        let packet = self
+            .0
+            .opcode(OpCode::Sha)
+            .mode(Self::MODE_WRITE_CONTEXT)
+            .param2(length as u16)
+            .pdu_data(context)
+            .build()?;
+        Ok(packet)
+    }
+
+    /// Complete an HMAC over the remaining data, up to 64 bytes.
+    pub(crate) fn hmac_end(
+        &mut self,
+        data: impl AsRef<[u8]>,
+        temp_key: bool,
+    ) -> Result<Packet, Error> {
+        let length = data.as_ref().len();
+        if length > 64 {
+            return Err(ErrorKind::BadParam.into());
+        }
+
+        let target = match temp_key {
+            true => Self::MODE_TARGET_TEMPKEY,
+            false => Self::MODE_TARGET_OUT_ONLY,
+        };
+        let packet = self
+            .0
+            .opcode(OpCode::Sha)
+            .mode(Self::MODE_HMAC_END | target)
+            .param2(length as u16)
+            .pdu_data(data)
+            .build()?;
+        Ok(packet)
+    }
+
     /// Command execution will return a digest of Block size.
     pub(crate) fn end(&mut self, data: impl AsRef<[u8]>) -> Result<Packet, Error> {
         let length = data.as_ref().len();
@@ -693,6 +1032,133 @@ impl<'a> Aes<'a> {
     }
 }
 
+impl<'a> Kdf<'a> {
+    const MODE_ALG_PRF: u8 = 0x00;
+    const MODE_ALG_AES: u8 = 0x20;
+    const MODE_ALG_HKDF: u8 = 0x40;
+    /// PRF yields 64 bytes instead of 32.
+    const DETAILS_PRF_TARGET_64: u32 = 0x0100;
+    const DETAILS_HKDF_MESSAGE_TEMPKEY: u32 = 0x01;
+    const DETAILS_HKDF_MESSAGE_INPUT: u32 = 0x02;
+    /// HMAC key of HKDF is 32 zero bytes rather than the source.
+    const DETAILS_HKDF_ZERO_KEY: u32 = 0x04;
+    pub(crate) const MESSAGE_MAX_SIZE: usize = 128;
+
+    pub(crate) fn new(builder: PacketBuilder<'a>) -> Self {
+        Self(builder)
+    }
+
+    /// TLS 1.2 PRF keyed by 16, 32, 48 or 64 bytes of the source. Output is
+    /// either 32 or 64 bytes long.
+    pub(crate) fn prf(
+        &mut self,
+        source: KdfSource,
+        key_length: usize,
+        target: KdfTarget,
+        output_length: usize,
+        message: &[u8],
+    ) -> Result<Packet, Error> {
+        let key_length = match key_length {
+            16 | 32 | 48 | 64 => (key_length / 16 - 1) as u32,
+            _ => return Err(ErrorKind::BadParam.into()),
+        };
+        let output_length = match output_length {
+            32 => 0x00,
+            64 => Self::DETAILS_PRF_TARGET_64,
+            _ => return Err(ErrorKind::BadParam.into()),
+        };
+        let details = key_length | output_length;
+        self.kdf(Self::MODE_ALG_PRF, source, target, details, message)
+    }
+
+    /// HMAC/SHA-256 over the message, keyed by the source or, if there is
+    /// none, by zeros.
+    pub(crate) fn hkdf(
+        &mut self,
+        source: Option<KdfSource>,
+        target: KdfTarget,
+        message: KdfMessage<'_>,
+    ) -> Result<Packet, Error> {
+        let (mut details, message) = match message {
+            KdfMessage::Input(message) => (Self::DETAILS_HKDF_MESSAGE_INPUT, message),
+            KdfMessage::TempKey => (Self::DETAILS_HKDF_MESSAGE_TEMPKEY, &[][..]),
+        };
+        if source.is_none() {
+            details |= Self::DETAILS_HKDF_ZERO_KEY;
+        }
+        let source = source.unwrap_or(KdfSource::TempKey);
+        self.kdf(Self::MODE_ALG_HKDF, source, target, details, message)
+    }
+
+    /// AES-128 encryption of a block, keyed by the `key_index`th 16 bytes of
+    /// the source.
+    pub(crate) fn aes(
+        &mut self,
+        source: KdfSource,
+        key_index: u8,
+        target: KdfTarget,
+        message: &[u8; 16],
+    ) -> Result<Packet, Error> {
+        if key_index > 0x03 {
+            return Err(ErrorKind::BadParam.into());
+        }
+        self.kdf(
+            Self::MODE_ALG_AES,
+            source,
+            target,
+            key_index.into(),
+            message,
+        )
+    }
+
+    // Details are followed by the message, whose length goes to the most
+    // significant byte of details.
+    fn kdf(
+        &mut self,
+        algorithm: u8,
+        source: KdfSource,
+        target: KdfTarget,
+        details: u32,
+        message: &[u8],
+    ) -> Result<Packet, Error> {
+        if message.len() > Self::MESSAGE_MAX_SIZE {
+            return Err(ErrorKind::InvalidSize.into());
+        }
+
+        let (source_mode, source_slot) = match source {
+            KdfSource::TempKey => (0x00, 0x00),
+            KdfSource::TempKeyUpper => (0x01, 0x00),
+            KdfSource::Slot(slot) => (0x02, slot as u8),
+            KdfSource::AlternateKeyBuffer => (0x03, 0x00),
+        };
+        let (target_mode, target_slot) = match target {
+            KdfTarget::TempKey => (0x00, 0x00),
+            KdfTarget::TempKeyUpper => (0x04, 0x00),
+            KdfTarget::Slot(slot) => (0x08, slot as u8),
+            KdfTarget::AlternateKeyBuffer => (0x0c, 0x00),
+            KdfTarget::Output => (0x10, 0x00),
+            KdfTarget::OutputEncrypted => (0x14, 0x00),
+        };
+        let details = if algorithm == Self::MODE_ALG_AES {
+            details
+        } else {
+            details | (message.len() as u32) << 24
+        };
+
+        let buffer = self.0.pdu_buffer();
+        buffer[..4].copy_from_slice(&details.to_le_bytes());
+        buffer[4..4 + message.len()].copy_from_slice(message);
+        let packet = self
+            .0
+            .opcode(OpCode::Kdf)
+            .mode(algorithm | target_mode | source_mode)
+            .param2(u16::from_le_bytes([source_slot, target_slot]))
+            .pdu_length(4 + message.len())
+            .build()?;
+        Ok(packet)
+    }
+}
+
 /// Random
 impl<'a> Random<'a> {
     const MODE_SEED_UPDATE: u8 = 0x00;
@@ -729,6 +1195,24 @@ impl<'a> Read<'a> {
         Ok(packet)
     }
 
+    pub(crate) fn data(
+        &mut self,
+        slot: Slot,
+        size: Size,
+        block: u8,
+        offset: u8,
+    ) -> Result<Packet, Error> {
+        let addr = Zone::Data.get_data_addr(slot, block, offset, size)?;
+        let mode = Zone::Data.encode(size);
+        let packet = self
+            .0
+            .opcode(OpCode::Read)
+            .mode(mode)
+            .param2(addr)
+            .build()?;
+        Ok(packet)
+    }
+
     pub(crate) fn read(
         &mut self,
         zone: Zone,
@@ -811,6 +1295,8 @@ impl<'a> Verify<'a> {
 
 /// Write
 impl<'a> Write<'a> {
+    pub(crate) const MODE_ENCRYPTED: u8 = 0x40;
+
     pub(crate) fn new(builder: PacketBuilder<'a>) -> Self {
         Self(builder)
     }
@@ -828,6 +1314,53 @@ impl<'a> Write<'a> {
         Ok(packet)
     }
 
+    pub(crate) fn data(
+        &mut self,
+        slot: Slot,
+        size: Size,
+        block: u8,
+        offset: u8,
+        data: impl AsRef<[u8]>,
+    ) -> Result<Packet, Error> {
+        if size.len() != data.as_ref().len() {
+            return Err(ErrorKind::BadParam.into());
+        }
+
+        let addr = Zone::Data.get_data_addr(slot, block, offset, size)?;
+        let mode = Zone::Data.encode(size);
+        let packet = self
+            .0
+            .opcode(OpCode::Write)
+            .mode(mode)
+            .param2(addr)
+            .pdu_data(data)
+            .build()?;
+        Ok(packet)
+    }
+
+    // Data encrypted with TempKey followed by the input MAC.
+    pub(crate) fn encrypted(
+        &mut self,
+        slot: Slot,
+        block: u8,
+        data: &[u8; 32],
+        mac: &[u8; 32],
+    ) -> Result<Packet, Error> {
+        let addr = Zone::Data.get_slot_addr(slot, block)?;
+        let mode = Zone::Data.encode(Size::Block) | Self::MODE_ENCRYPTED;
+        let (data_buffer, mac_buffer) = self.0.pdu_buffer().split_at_mut(data.len());
+        data_buffer.copy_from_slice(data);
+        mac_buffer[..mac.len()].copy_from_slice(mac);
+        let packet = self
+            .0
+            .opcode(OpCode::Write)
+            .mode(mode)
+            .param2(addr)
+            .pdu_length(data.len() + mac.len())
+            .build()?;
+        Ok(packet)
+    }
+
     pub(crate) fn write(
         &mut self,
         zone: Zone,
@@ -883,6 +1416,17 @@ mod tests {
         assert_eq!(packet[0x04..0x06], [0x00, 0x00]);
     }
 
+    #[test]
+    fn lock_data() {
+        let buf = &mut [0x00u8; 0xff];
+        let packet = Lock::new(PacketBuilder::new(buf.as_mut()))
+            .zone(Zone::Data, None)
+            .unwrap()
+            .buffer(buf.as_ref());
+        assert_eq!(packet[0x02], OpCode::Lock as u8);
+        assert_eq!(packet[0x03], 0x81);
+    }
+
     #[test]
     fn lock_crc() {
         let buf = &mut [0x00u8; 0xff];
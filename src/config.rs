@@ -0,0 +1,837 @@
+// Typed view of the 128 bytes of configuration zone. Layout follows the
+// ATECC608 datasheet; multi-byte integers are little endian.
+use super::command::{Counter, CounterId};
+use super::error::{Error, ErrorKind};
+use super::memory::{OtpMode, Size, Slot};
+use core::convert::{TryFrom, TryInto};
+use core::iter::once;
+use core::ops::Range;
+
+/// Configuration zone image, as read from or written to the device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ConfigZone {
+    /// SN[0:3] followed by SN[4:8].
+    pub serial_number: [u8; 9],
+    pub revision: [u8; 4],
+    pub aes_enable: u8,
+    pub i2c_enable: u8,
+    /// 7-bit address shifted left by one.
+    pub i2c_address: u8,
+    pub count_match: u8,
+    pub chip_mode: u8,
+    pub slot_config: [SlotConfig; 16],
+    pub counter0: [u8; 8],
+    pub counter1: [u8; 8],
+    pub use_lock: u8,
+    pub volatile_key_permission: u8,
+    pub secure_boot: u16,
+    pub kdf_iv_loc: u8,
+    pub kdf_iv_str: [u8; 2],
+    pub user_extra: u8,
+    pub user_extra_add: u8,
+    pub lock_value: u8,
+    pub lock_config: u8,
+    pub slot_locked: u16,
+    pub chip_options: u16,
+    pub x509_format: [u8; 4],
+    pub key_config: [KeyConfig; 16],
+    // Bytes 15, 17 and 75 to 83 are kept so that images round-trip.
+    reserved: [u8; 11],
+}
+
+/// Fields of the configuration zone, in the order of their addresses.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Field {
+    SerialNumber,
+    Revision,
+    AesEnable,
+    I2cEnable,
+    I2cAddress,
+    CountMatch,
+    ChipMode,
+    SlotConfig(Slot),
+    Counter0,
+    Counter1,
+    UseLock,
+    VolatileKeyPermission,
+    SecureBoot,
+    KdfIvLoc,
+    KdfIvStr,
+    UserExtra,
+    UserExtraAdd,
+    LockValue,
+    LockConfig,
+    SlotLocked,
+    ChipOptions,
+    X509Format,
+    KeyConfig(Slot),
+}
+
+impl Field {
+    /// Every field, slot configurations included.
+    pub fn iter() -> impl Iterator<Item = Field> {
+        use Field::*;
+        let head = [
+            SerialNumber,
+            Revision,
+            AesEnable,
+            I2cEnable,
+            I2cAddress,
+            CountMatch,
+            ChipMode,
+        ];
+        let middle = [
+            Counter0,
+            Counter1,
+            UseLock,
+            VolatileKeyPermission,
+            SecureBoot,
+            KdfIvLoc,
+            KdfIvStr,
+            UserExtra,
+            UserExtraAdd,
+            LockValue,
+            LockConfig,
+            SlotLocked,
+            ChipOptions,
+            X509Format,
+        ];
+        IntoIterator::into_iter(head)
+            .chain(Slot::keys().map(SlotConfig))
+            .chain(middle)
+            .chain(Slot::keys().map(KeyConfig))
+    }
+}
+
+impl ConfigZone {
+    pub const SIZE: usize = 128;
+
+    // Byte indices of fields the client reads or writes on their own.
+    pub(crate) const I2C_ADDRESS_INDEX: usize = 16;
+    pub(crate) const COUNT_MATCH_INDEX: usize = 18;
+    pub(crate) const SLOT_CONFIG_INDEX: usize = 20;
+    pub(crate) const LOCK_VALUE_INDEX: usize = 86;
+    pub(crate) const LOCK_CONFIG_INDEX: usize = 87;
+    pub(crate) const SLOT_LOCKED_INDEX: usize = 88;
+    pub(crate) const CHIP_OPTIONS_INDEX: usize = 90;
+    pub(crate) const KEY_CONFIG_INDEX: usize = 96;
+
+    // Byte ranges the Write command refuses to touch. Serial number and
+    // revision are factory programmed. UserExtra and UserExtraAdd are set by
+    // UpdateExtra, LockValue and LockConfig by Lock.
+    const READ_ONLY: [Range<usize>; 2] = [0..16, 84..88];
+
+    /// Counter0 or Counter1 as shipped, i.e. never incremented.
+    pub const COUNTER_RESET: [u8; 8] = [0xff, 0xff, 0xff, 0xff, 0x00, 0x00, 0x00, 0x00];
+
+    /// Whether the Write command accepts the given byte range.
+    pub(crate) fn is_writable(range: Range<usize>) -> bool {
+        Self::READ_ONLY
+            .iter()
+            .all(|read_only| range.end <= read_only.start || read_only.end <= range.start)
+    }
+
+    /// Access to the OTP zone. ATECC508A took a read-only or consumption mode
+    /// from byte 18, which ATECC608 repurposes as CountMatch, and neither
+    /// ChipMode nor ChipOptions has a replacement. What remains is LockValue:
+    /// the zone is writable until the data zone is locked.
+    pub fn otp_mode(&self) -> OtpMode {
+        match self.lock_value {
+            0x55 => OtpMode::Writable,
+            _ => OtpMode::ReadOnly,
+        }
+    }
+
+    /// Initial value of a counter, or `None` if its bytes are not a valid
+    /// encoding.
+    pub fn counter(&self, counter_id: CounterId) -> Option<u32> {
+        match counter_id {
+            CounterId::Counter0 => Self::decode_counter(&self.counter0),
+            CounterId::Counter1 => Self::decode_counter(&self.counter1),
+        }
+    }
+
+    /// Set the initial value of a counter, which takes effect when the
+    /// configuration zone is locked.
+    pub fn set_counter(&mut self, counter_id: CounterId, value: u32) -> Result<(), Error> {
+        let bytes = Self::encode_counter(value)?;
+        match counter_id {
+            CounterId::Counter0 => self.counter0 = bytes,
+            CounterId::Counter1 => self.counter1 = bytes,
+        }
+        Ok(())
+    }
+
+    /// Encode a counter value into LinA, LinB, BinA and BinB, 16 bits each
+    /// in little endian. The binary part counts blocks of 32, and the linear
+    /// part has one bit cleared per count within a block, in LinA first and
+    /// then in LinB. BinB runs one ahead while LinB is in use, so the last 16
+    /// values cannot be encoded.
+    pub fn encode_counter(value: u32) -> Result<[u8; 8], Error> {
+        if value > Counter::MAX_VALUE {
+            return Err(ErrorKind::BadParam.into());
+        }
+        let count = value % 32;
+        let bin_a = (value / 32) as u16;
+        let (lin_a, lin_b, bin_b) = if count < 16 {
+            (0xffff_u16 >> count, 0xffff_u16, bin_a)
+        } else {
+            let bin_b = bin_a.checked_add(1).ok_or(ErrorKind::BadParam)?;
+            (0x0000, 0xffff_u16 >> (count - 16), bin_b)
+        };
+        let mut bytes = [0x00; 8];
+        bytes[0..2].copy_from_slice(&lin_a.to_le_bytes());
+        bytes[2..4].copy_from_slice(&lin_b.to_le_bytes());
+        bytes[4..6].copy_from_slice(&bin_a.to_le_bytes());
+        bytes[6..8].copy_from_slice(&bin_b.to_le_bytes());
+        Ok(bytes)
+    }
+
+    /// Inverse of `encode_counter`.
+    pub fn decode_counter(bytes: &[u8; 8]) -> Option<u32> {
+        let word = |i: usize| u16::from_le_bytes([bytes[i], bytes[i + 1]]);
+        let (lin_a, lin_b, bin_a, bin_b) = (word(0), word(2), word(4), word(6));
+        // Number of bits cleared from the top of a linear part.
+        let cleared = |lin: u16| {
+            let cleared = lin.leading_zeros();
+            (lin == 0xffff_u16.checked_shr(cleared).unwrap_or(0)).then_some(cleared)
+        };
+        let count = match (cleared(lin_a)?, cleared(lin_b)?) {
+            (count, 0) if count < 16 && bin_b == bin_a => count,
+            (16, count) if count < 16 && u32::from(bin_b) == u32::from(bin_a) + 1 => 16 + count,
+            _ => return None,
+        };
+        Some(u32::from(bin_a) * 32 + count)
+    }
+
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let mut bytes = [0x00; Self::SIZE];
+        bytes[0..4].copy_from_slice(&self.serial_number[0..4]);
+        bytes[4..8].copy_from_slice(&self.revision);
+        bytes[8..13].copy_from_slice(&self.serial_number[4..9]);
+        bytes[13] = self.aes_enable;
+        bytes[14] = self.i2c_enable;
+        bytes[15] = self.reserved[0];
+        bytes[16] = self.i2c_address;
+        bytes[17] = self.reserved[1];
+        bytes[18] = self.count_match;
+        bytes[19] = self.chip_mode;
+        words_to_bytes(&self.slot_config, &mut bytes[20..52]);
+        bytes[52..60].copy_from_slice(&self.counter0);
+        bytes[60..68].copy_from_slice(&self.counter1);
+        bytes[68] = self.use_lock;
+        bytes[69] = self.volatile_key_permission;
+        bytes[70..72].copy_from_slice(&self.secure_boot.to_le_bytes());
+        bytes[72] = self.kdf_iv_loc;
+        bytes[73..75].copy_from_slice(&self.kdf_iv_str);
+        bytes[75..84].copy_from_slice(&self.reserved[2..]);
+        bytes[84] = self.user_extra;
+        bytes[85] = self.user_extra_add;
+        bytes[86] = self.lock_value;
+        bytes[87] = self.lock_config;
+        bytes[88..90].copy_from_slice(&self.slot_locked.to_le_bytes());
+        bytes[90..92].copy_from_slice(&self.chip_options.to_le_bytes());
+        bytes[92..96].copy_from_slice(&self.x509_format);
+        words_to_bytes(&self.key_config, &mut bytes[96..128]);
+        bytes
+    }
+
+    /// Fields whose values differ between two images.
+    pub fn diff<'a>(&'a self, other: &'a Self) -> impl Iterator<Item = Field> + 'a {
+        Field::iter().filter(move |field| !self.field_eq(other, *field))
+    }
+
+    fn field_eq(&self, other: &Self, field: Field) -> bool {
+        use Field::*;
+        match field {
+            SerialNumber => self.serial_number == other.serial_number,
+            Revision => self.revision == other.revision,
+            AesEnable => self.aes_enable == other.aes_enable,
+            I2cEnable => self.i2c_enable == other.i2c_enable,
+            I2cAddress => self.i2c_address == other.i2c_address,
+            CountMatch => self.count_match == other.count_match,
+            ChipMode => self.chip_mode == other.chip_mode,
+            SlotConfig(slot) => self.slot_config[slot as usize] == other.slot_config[slot as usize],
+            Counter0 => self.counter0 == other.counter0,
+            Counter1 => self.counter1 == other.counter1,
+            UseLock => self.use_lock == other.use_lock,
+            VolatileKeyPermission => self.volatile_key_permission == other.volatile_key_permission,
+            SecureBoot => self.secure_boot == other.secure_boot,
+            KdfIvLoc => self.kdf_iv_loc == other.kdf_iv_loc,
+            KdfIvStr => self.kdf_iv_str == other.kdf_iv_str,
+            UserExtra => self.user_extra == other.user_extra,
+            UserExtraAdd => self.user_extra_add == other.user_extra_add,
+            LockValue => self.lock_value == other.lock_value,
+            LockConfig => self.lock_config == other.lock_config,
+            SlotLocked => self.slot_locked == other.slot_locked,
+            ChipOptions => self.chip_options == other.chip_options,
+            X509Format => self.x509_format == other.x509_format,
+            KeyConfig(slot) => self.key_config[slot as usize] == other.key_config[slot as usize],
+        }
+    }
+}
+
+impl TryFrom<&[u8]> for ConfigZone {
+    type Error = Error;
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        if bytes.len() != Self::SIZE {
+            return Err(ErrorKind::BadParam.into());
+        }
+
+        let mut serial_number = [0x00; 9];
+        serial_number[0..4].copy_from_slice(&bytes[0..4]);
+        serial_number[4..9].copy_from_slice(&bytes[8..13]);
+        let mut reserved = [0x00; 11];
+        once(bytes[15])
+            .chain(once(bytes[17]))
+            .chain(bytes[75..84].iter().copied())
+            .zip(reserved.iter_mut())
+            .for_each(|(src, dst)| *dst = src);
+
+        Ok(Self {
+            serial_number,
+            revision: array(&bytes[4..8]),
+            aes_enable: bytes[13],
+            i2c_enable: bytes[14],
+            i2c_address: bytes[16],
+            count_match: bytes[18],
+            chip_mode: bytes[19],
+            slot_config: words_from_bytes(&bytes[20..52]),
+            counter0: array(&bytes[52..60]),
+            counter1: array(&bytes[60..68]),
+            use_lock: bytes[68],
+            volatile_key_permission: bytes[69],
+            secure_boot: u16::from_le_bytes(array(&bytes[70..72])),
+            kdf_iv_loc: bytes[72],
+            kdf_iv_str: array(&bytes[73..75]),
+            user_extra: bytes[84],
+            user_extra_add: bytes[85],
+            lock_value: bytes[86],
+            lock_config: bytes[87],
+            slot_locked: u16::from_le_bytes(array(&bytes[88..90])),
+            chip_options: u16::from_le_bytes(array(&bytes[90..92])),
+            x509_format: array(&bytes[92..96]),
+            key_config: words_from_bytes(&bytes[96..128]),
+            reserved,
+        })
+    }
+}
+
+// Word addresses of the zone, grouped by block. Blocks partially read-only
+// have to be written a word at a time.
+pub(crate) fn writable_words() -> impl Iterator<Item = (Size, u8, u8)> {
+    let block_len = Size::Block.len();
+    let word_len = Size::Word.len();
+    (0..ConfigZone::SIZE / block_len).flat_map(move |block| {
+        let start = block * block_len;
+        let whole = ConfigZone::is_writable(start..start + block_len);
+        (0..block_len / word_len).filter_map(move |offset| {
+            let start = start + offset * word_len;
+            match (whole, offset) {
+                (true, 0) => Some((Size::Block, block as u8, 0)),
+                (true, _) => None,
+                (false, _) if ConfigZone::is_writable(start..start + word_len) => {
+                    Some((Size::Word, block as u8, offset as u8))
+                }
+                (false, _) => None,
+            }
+        })
+    })
+}
+
+fn array<const N: usize>(bytes: &[u8]) -> [u8; N] {
+    bytes.try_into().unwrap_or_else(|_| unreachable!())
+}
+
+fn words_from_bytes<T: From<u16> + Default + Copy>(bytes: &[u8]) -> [T; 16] {
+    let mut words = [T::default(); 16];
+    bytes
+        .chunks(2)
+        .zip(words.iter_mut())
+        .for_each(|(src, dst)| *dst = u16::from_le_bytes(array(src)).into());
+    words
+}
+
+fn words_to_bytes<T: Into<u16> + Copy>(words: &[T; 16], bytes: &mut [u8]) {
+    bytes
+        .chunks_mut(2)
+        .zip(words.iter())
+        .for_each(|(dst, src)| dst.copy_from_slice(&(*src).into().to_le_bytes()));
+}
+
+// Extract and replace bit fields of a 16-bit word.
+fn bits(word: u16, shift: u32, width: u32) -> u16 {
+    word >> shift & ((1 << width) - 1)
+}
+
+fn with_bits(word: u16, shift: u32, width: u32, value: u16) -> u16 {
+    let mask = ((1 << width) - 1) << shift;
+    word & !mask | value << shift & mask
+}
+
+/// Access policy of a slot, two bytes per slot starting at byte 20.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SlotConfig(u16);
+
+impl SlotConfig {
+    /// Key to encrypt reads with. For private keys, bit 0 enables external
+    /// signatures, bit 1 internal signatures and bit 2 ECDH, whose shared
+    /// secret is written into slot N|1 if bit 3 is set.
+    pub fn read_key(&self) -> u8 {
+        bits(self.0, 0, 4) as u8
+    }
+
+    /// The key cannot be used by the MAC command.
+    pub fn no_mac(&self) -> bool {
+        bits(self.0, 4, 1) != 0
+    }
+
+    /// Uses of the key are limited by Counter0.
+    pub fn limited_use(&self) -> bool {
+        bits(self.0, 5, 1) != 0
+    }
+
+    /// Reads are encrypted with ReadKey. Requires IsSecret.
+    pub fn encrypt_read(&self) -> bool {
+        bits(self.0, 6, 1) != 0
+    }
+
+    /// Clear text reads as well as 4-byte reads and writes are prohibited.
+    pub fn is_secret(&self) -> bool {
+        bits(self.0, 7, 1) != 0
+    }
+
+    /// Key to validate and encrypt writes with.
+    pub fn write_key(&self) -> u8 {
+        bits(self.0, 8, 4) as u8
+    }
+
+    /// Commands allowed to modify the slot, and under which conditions.
+    pub fn write_config(&self) -> u8 {
+        bits(self.0, 12, 4) as u8
+    }
+
+    pub fn with_read_key(self, read_key: u8) -> Self {
+        Self(with_bits(self.0, 0, 4, read_key.into()))
+    }
+
+    pub fn with_no_mac(self, no_mac: bool) -> Self {
+        Self(with_bits(self.0, 4, 1, no_mac.into()))
+    }
+
+    pub fn with_limited_use(self, limited_use: bool) -> Self {
+        Self(with_bits(self.0, 5, 1, limited_use.into()))
+    }
+
+    pub fn with_encrypt_read(self, encrypt_read: bool) -> Self {
+        Self(with_bits(self.0, 6, 1, encrypt_read.into()))
+    }
+
+    pub fn with_is_secret(self, is_secret: bool) -> Self {
+        Self(with_bits(self.0, 7, 1, is_secret.into()))
+    }
+
+    pub fn with_write_key(self, write_key: u8) -> Self {
+        Self(with_bits(self.0, 8, 4, write_key.into()))
+    }
+
+    pub fn with_write_config(self, write_config: u8) -> Self {
+        Self(with_bits(self.0, 12, 4, write_config.into()))
+    }
+
+    pub fn from_le_bytes(bytes: [u8; 2]) -> Self {
+        Self(u16::from_le_bytes(bytes))
+    }
+
+    pub fn to_le_bytes(self) -> [u8; 2] {
+        self.0.to_le_bytes()
+    }
+}
+
+impl From<u16> for SlotConfig {
+    fn from(word: u16) -> Self {
+        Self(word)
+    }
+}
+
+impl From<SlotConfig> for u16 {
+    fn from(slot_config: SlotConfig) -> Self {
+        slot_config.0
+    }
+}
+
+/// Binding of Counter0 to a limit, stored in byte 18. Counter0 cannot be
+/// incremented past the match value, held in the first 8 bytes of the slot
+/// `key`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CountMatch(u8);
+
+impl CountMatch {
+    pub fn enabled(&self) -> bool {
+        self.0 & 0x01 != 0
+    }
+
+    /// Slot holding the match value.
+    pub fn key(&self) -> Slot {
+        Slot::keys()
+            .nth((self.0 >> 4).into())
+            .unwrap_or_else(|| unreachable!("Slots are numbered by 4 bits."))
+    }
+
+    pub fn with_enabled(self, enabled: bool) -> Self {
+        Self(self.0 & !0x01 | u8::from(enabled))
+    }
+
+    pub fn with_key(self, key: Slot) -> Self {
+        Self(self.0 & 0x0f | (key as u8) << 4)
+    }
+
+    /// Encode a match value for the slot. The value, a multiple of 32, is
+    /// stored twice in little endian.
+    pub fn encode_value(value: u32) -> Result<[u8; 8], Error> {
+        if value & 0x1f != 0 || value > Counter::MAX_VALUE {
+            return Err(ErrorKind::BadParam.into());
+        }
+        let mut bytes = [0x00; 8];
+        bytes[..4].copy_from_slice(&value.to_le_bytes());
+        bytes[4..].copy_from_slice(&value.to_le_bytes());
+        Ok(bytes)
+    }
+
+    pub fn decode_value(bytes: &[u8; 8]) -> Option<u32> {
+        let (value, copy) = bytes.split_at(4);
+        (value == copy).then(|| u32::from_le_bytes(array(value)))
+    }
+}
+
+impl From<u8> for CountMatch {
+    fn from(byte: u8) -> Self {
+        Self(byte)
+    }
+}
+
+impl From<CountMatch> for u8 {
+    fn from(count_match: CountMatch) -> Self {
+        count_match.0
+    }
+}
+
+/// Kind of key held in a slot.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyType {
+    /// P256 NIST ECC key.
+    P256,
+    /// AES-128 key.
+    Aes,
+    /// SHA key or other data.
+    Sha,
+    /// Values the datasheet reserves for future use.
+    Reserved(u8),
+}
+
+impl From<u8> for KeyType {
+    fn from(value: u8) -> Self {
+        match value & 0x07 {
+            0x04 => Self::P256,
+            0x06 => Self::Aes,
+            0x07 => Self::Sha,
+            value => Self::Reserved(value),
+        }
+    }
+}
+
+impl From<KeyType> for u8 {
+    fn from(key_type: KeyType) -> Self {
+        match key_type {
+            KeyType::P256 => 0x04,
+            KeyType::Aes => 0x06,
+            KeyType::Sha => 0x07,
+            KeyType::Reserved(value) => value & 0x07,
+        }
+    }
+}
+
+/// Usage policy of a slot, two bytes per slot starting at byte 96.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct KeyConfig(u16);
+
+impl KeyConfig {
+    /// The slot holds an ECC private key.
+    pub fn private(&self) -> bool {
+        bits(self.0, 0, 1) != 0
+    }
+
+    /// For private keys, the public key can be generated. For public keys,
+    /// the key can be used only once validated.
+    pub fn pub_info(&self) -> bool {
+        bits(self.0, 1, 1) != 0
+    }
+
+    pub fn key_type(&self) -> KeyType {
+        KeyType::from(bits(self.0, 2, 3) as u8)
+    }
+
+    /// The slot can be locked individually by the Lock command.
+    pub fn lockable(&self) -> bool {
+        bits(self.0, 5, 1) != 0
+    }
+
+    /// Using the key requires a random nonce.
+    pub fn req_random(&self) -> bool {
+        bits(self.0, 6, 1) != 0
+    }
+
+    /// Using the key requires a prior authorization with AuthKey.
+    pub fn req_auth(&self) -> bool {
+        bits(self.0, 7, 1) != 0
+    }
+
+    pub fn auth_key(&self) -> u8 {
+        bits(self.0, 8, 4) as u8
+    }
+
+    /// The key is unusable unless the persistent latch is set.
+    pub fn persistent_disable(&self) -> bool {
+        bits(self.0, 12, 1) != 0
+    }
+
+    /// Index of X509format to validate the public key against.
+    pub fn x509_id(&self) -> u8 {
+        bits(self.0, 14, 2) as u8
+    }
+
+    pub fn with_private(self, private: bool) -> Self {
+        Self(with_bits(self.0, 0, 1, private.into()))
+    }
+
+    pub fn with_pub_info(self, pub_info: bool) -> Self {
+        Self(with_bits(self.0, 1, 1, pub_info.into()))
+    }
+
+    pub fn with_key_type(self, key_type: KeyType) -> Self {
+        Self(with_bits(self.0, 2, 3, u8::from(key_type).into()))
+    }
+
+    pub fn with_lockable(self, lockable: bool) -> Self {
+        Self(with_bits(self.0, 5, 1, lockable.into()))
+    }
+
+    pub fn with_req_random(self, req_random: bool) -> Self {
+        Self(with_bits(self.0, 6, 1, req_random.into()))
+    }
+
+    pub fn with_req_auth(self, req_auth: bool) -> Self {
+        Self(with_bits(self.0, 7, 1, req_auth.into()))
+    }
+
+    pub fn with_auth_key(self, auth_key: u8) -> Self {
+        Self(with_bits(self.0, 8, 4, auth_key.into()))
+    }
+
+    pub fn with_persistent_disable(self, persistent_disable: bool) -> Self {
+        Self(with_bits(self.0, 12, 1, persistent_disable.into()))
+    }
+
+    pub fn with_x509_id(self, x509_id: u8) -> Self {
+        Self(with_bits(self.0, 14, 2, x509_id.into()))
+    }
+
+    pub fn from_le_bytes(bytes: [u8; 2]) -> Self {
+        Self(u16::from_le_bytes(bytes))
+    }
+
+    pub fn to_le_bytes(self) -> [u8; 2] {
+        self.0.to_le_bytes()
+    }
+}
+
+impl From<u16> for KeyConfig {
+    fn from(word: u16) -> Self {
+        Self(word)
+    }
+}
+
+impl From<KeyConfig> for u16 {
+    fn from(key_config: KeyConfig) -> Self {
+        key_config.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image() -> [u8; ConfigZone::SIZE] {
+        let mut bytes = [0x00; ConfigZone::SIZE];
+        bytes
+            .iter_mut()
+            .enumerate()
+            .for_each(|(i, byte)| *byte = i as u8);
+        bytes
+    }
+
+    #[test]
+    fn round_trip() {
+        let bytes = image();
+        let config = ConfigZone::try_from(&bytes[..]).unwrap();
+        assert_eq!(config.serial_number, [0, 1, 2, 3, 8, 9, 10, 11, 12]);
+        assert_eq!(config.i2c_address, 16);
+        assert_eq!(config.slot_config[1], SlotConfig::from(0x1716));
+        assert_eq!(config.secure_boot, 0x4746);
+        assert_eq!(config.chip_options, 0x5b5a);
+        assert_eq!(config.key_config[15], KeyConfig::from(0x7f7e));
+        assert_eq!(config.to_bytes(), bytes);
+        assert!(ConfigZone::try_from(&bytes[..64]).is_err());
+        assert_eq!(config.otp_mode(), OtpMode::ReadOnly);
+        let config = ConfigZone {
+            lock_value: 0x55,
+            ..config
+        };
+        assert_eq!(config.otp_mode(), OtpMode::Writable);
+    }
+
+    #[test]
+    fn diff() {
+        let bytes = image();
+        let config = ConfigZone::try_from(&bytes[..]).unwrap();
+        assert_eq!(config.diff(&config).count(), 0);
+        let mut other = config;
+        other.slot_config[Slot::PrivateKey05 as usize] = SlotConfig::default();
+        other.lock_config = 0x00;
+        other.key_config[Slot::Certificate0f as usize] = KeyConfig::default();
+        let mut diff = config.diff(&other);
+        assert_eq!(diff.next(), Some(Field::SlotConfig(Slot::PrivateKey05)));
+        assert_eq!(diff.next(), Some(Field::LockConfig));
+        assert_eq!(diff.next(), Some(Field::KeyConfig(Slot::Certificate0f)));
+        assert_eq!(diff.next(), None);
+        assert_eq!(Field::iter().count(), 21 + 16 * 2);
+    }
+
+    #[test]
+    fn writable_words() {
+        let mut words = super::writable_words();
+        assert_eq!(words.next(), Some((Size::Word, 0, 4)));
+        let words = super::writable_words().collect::<heapless::Vec<_, 32>>();
+        // 4 words of block 0, whole block 1, 7 words of block 2 and block 3.
+        assert_eq!(words.len(), 4 + 1 + 7 + 1);
+        assert!(words
+            .iter()
+            .all(|(size, block, offset)| !matches!((size, block, offset), (Size::Word, 2, 5))));
+    }
+
+    #[test]
+    fn count_match() {
+        let count_match = CountMatch::from(0xa1);
+        assert!(count_match.enabled());
+        assert_eq!(count_match.key(), Slot::Certificate0a);
+        assert_eq!(
+            CountMatch::default()
+                .with_key(Slot::Certificate0a)
+                .with_enabled(true),
+            count_match
+        );
+        let value = CountMatch::encode_value(0x0400).unwrap();
+        assert_eq!(value, [0x00, 0x04, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00]);
+        assert_eq!(CountMatch::decode_value(&value), Some(0x0400));
+        assert_eq!(
+            CountMatch::decode_value(&[0x00, 0x04, 0, 0, 0, 0, 0, 0]),
+            None
+        );
+        assert!(CountMatch::encode_value(0x0401).is_err());
+        assert!(CountMatch::encode_value(0x0020_0000).is_err());
+
+        let mut config = ConfigZone::try_from(&image()[..]).unwrap();
+        assert_eq!(config.counter(CounterId::Counter0), None);
+        config.counter0 = ConfigZone::COUNTER_RESET;
+        assert_eq!(config.counter(CounterId::Counter0), Some(0));
+        config.set_counter(CounterId::Counter1, 0x41).unwrap();
+        assert_eq!(
+            config.counter1,
+            [0xff, 0x7f, 0xff, 0xff, 0x02, 0x00, 0x02, 0x00]
+        );
+        config.set_counter(CounterId::Counter1, 0x53).unwrap();
+        assert_eq!(
+            config.counter1,
+            [0x00, 0x00, 0xff, 0x1f, 0x02, 0x00, 0x03, 0x00]
+        );
+        assert_eq!(config.counter(CounterId::Counter1), Some(0x53));
+        for value in [0, 15, 16, 31, 32, 0x1234, 0x1f_ffef] {
+            let bytes = ConfigZone::encode_counter(value).unwrap();
+            assert_eq!(ConfigZone::decode_counter(&bytes), Some(value));
+        }
+        assert!(ConfigZone::encode_counter(0x1f_fff0).is_err());
+        assert!(ConfigZone::encode_counter(0x20_0000).is_err());
+        // Bits are cleared from the top of LinA only, one after another.
+        let mut bytes = ConfigZone::encode_counter(3).unwrap();
+        bytes[0] = 0xfe;
+        assert_eq!(ConfigZone::decode_counter(&bytes), None);
+        // BinB has to run one ahead while LinB is in use.
+        let mut bytes = ConfigZone::encode_counter(20).unwrap();
+        bytes[6] = 0x00;
+        assert_eq!(ConfigZone::decode_counter(&bytes), None);
+    }
+
+    #[test]
+    fn slot_config() {
+        // Secondary private key of TNG-TLS.
+        let slot_config = SlotConfig::from_le_bytes([0x85, 0x20]);
+        assert_eq!(slot_config.read_key(), 0x05);
+        assert!(!slot_config.no_mac());
+        assert!(!slot_config.limited_use());
+        assert!(!slot_config.encrypt_read());
+        assert!(slot_config.is_secret());
+        assert_eq!(slot_config.write_key(), 0x00);
+        assert_eq!(slot_config.write_config(), 0x02);
+        let built = SlotConfig::default()
+            .with_read_key(0x05)
+            .with_is_secret(true)
+            .with_write_config(0x02);
+        assert_eq!(built, slot_config);
+        assert_eq!(built.to_le_bytes(), [0x85, 0x20]);
+
+        let slot_config = SlotConfig::default()
+            .with_read_key(0xff)
+            .with_no_mac(true)
+            .with_limited_use(true)
+            .with_encrypt_read(true)
+            .with_write_key(0x0a)
+            .with_write_config(0x04);
+        assert_eq!(u16::from(slot_config), 0x4a7f);
+        assert_eq!(u16::from(slot_config.with_encrypt_read(false)), 0x4a3f);
+    }
+
+    #[test]
+    fn key_config() {
+        // Primary private key of TNG-TLS.
+        let key_config = KeyConfig::from_le_bytes([0x53, 0x00]);
+        assert!(key_config.private());
+        assert!(key_config.pub_info());
+        assert_eq!(key_config.key_type(), KeyType::P256);
+        assert!(!key_config.lockable());
+        assert!(key_config.req_random());
+        assert!(!key_config.req_auth());
+        assert_eq!(key_config.auth_key(), 0x00);
+        assert!(!key_config.persistent_disable());
+        assert_eq!(key_config.x509_id(), 0x00);
+        let built = KeyConfig::default()
+            .with_private(true)
+            .with_pub_info(true)
+            .with_key_type(KeyType::P256)
+            .with_req_random(true);
+        assert_eq!(built, key_config);
+
+        let key_config = KeyConfig::default()
+            .with_key_type(KeyType::Aes)
+            .with_lockable(true)
+            .with_req_auth(true)
+            .with_auth_key(0x03)
+            .with_persistent_disable(true)
+            .with_x509_id(0x02);
+        assert_eq!(key_config.to_le_bytes(), [0xb8, 0x93]);
+        assert_eq!(key_config.key_type(), KeyType::Aes);
+        assert_eq!(
+            key_config.with_key_type(KeyType::Reserved(0x01)).key_type(),
+            KeyType::Reserved(0x01)
+        );
+    }
+}
@@ -2,29 +2,32 @@
 // have ATCAPacket format Devices such as ATECCx08A require a word address value
 // pre-pended to the packet txdata[0] is using _reserved byte of the ATCAPacket
 use super::error::{Error, ErrorKind};
-use super::packet::{Packet, Response};
 use core::fmt::Debug;
 use core::iter::from_fn;
 use core::slice::from_ref;
 use embedded_hal::delay::DelayNs;
 use embedded_hal::i2c;
+
+pub(crate) mod swi;
+pub use swi::{BaudRate, Swi, SwiConfig};
+
 const WAKE_RESPONSE_EXPECTED: &[u8] = &[0x04, 0x11, 0x33, 0x43];
 const WAKE_SELFTEST_FAILED: &[u8] = &[0x04, 0x07, 0xC4, 0x40];
 
 /// Default I2C address of ATECC608
-const ADDRESS: u8 = 0xc0 >> 1;
+pub(crate) const ADDRESS: u8 = 0xc0 >> 1;
 /// Default time in us that takes for ATECC608 device to wake up.
-const DELAY_US: u32 = 1500;
-
-// By default, wake up sequence is repeated up to 20 times until it succeeds.
-// Multiply by 2, otherwise you see RxFail on wake up. It happens when you try
-// to write a word to the config zone on Raspberry Pi's I2C. This behavior might
-// be specific to linux HAL. What's worse, GenKey command from Raspberry Pi
-// needs to retry 20 * 15 times until it succeeds.
-#[cfg(target_os = "none")]
-const RETRY: usize = 20;
-#[cfg(not(target_os = "none"))]
-const RETRY: usize = 20 * 15;
+pub(crate) const DELAY_US: u32 = 1500;
+/// Minimum size of a response, length (1 byte), status (1 byte) and crc (2 bytes).
+const MIN_RESP_SIZE: u8 = 4;
+
+// Wake up sequence is repeated up to 20 times until it succeeds.
+pub(crate) const RETRY: usize = 20;
+/// Default interval in us between polls for a response.
+pub(crate) const POLL_INTERVAL_US: u32 = 1000;
+/// Time in us after wake-up, at which the watchdog puts the device to sleep
+/// unless it has been put into the idle state.
+const WATCHDOG_US: u32 = 1_300_000;
 
 /// So-called "word address".
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -37,14 +40,217 @@ pub(crate) enum Transaction {
     Reserved = 0xff,
 }
 
-pub(crate) struct I2c<PHY, D> {
+/// Bus parameters of a device, built up by chaining setters on the default.
+///
+/// Addresses are 7-bit, as taken by `embedded_hal::i2c`. Datasheets give them
+/// in their 8-bit form, e.g. 0xC0 for a blank ATECC608, 0x6A for Trust&GO and
+/// 0x6C for TrustFLEX parts, which correspond to 0x60, 0x35 and 0x36 here.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct I2cConfig {
+    pub(crate) address: u8,
+    pub(crate) retry: usize,
+    pub(crate) wake_delay_us: u32,
+    pub(crate) poll_interval_us: u32,
+    pub(crate) timeout_ms: Option<u32>,
+}
+
+impl Default for I2cConfig {
+    fn default() -> Self {
+        Self {
+            address: ADDRESS,
+            retry: RETRY,
+            wake_delay_us: DELAY_US,
+            poll_interval_us: POLL_INTERVAL_US,
+            timeout_ms: None,
+        }
+    }
+}
+
+impl I2cConfig {
+    /// 7-bit I2C address of the device.
+    pub fn address(mut self, address: u8) -> Self {
+        self.address = address;
+        self
+    }
+
+    /// How many times a wake up is attempted until it succeeds. Zero is
+    /// treated as one.
+    pub fn retry(mut self, retry: usize) -> Self {
+        self.retry = retry.max(1);
+        self
+    }
+
+    /// Time in us to wait for the device to wake up.
+    pub fn wake_delay_us(mut self, wake_delay_us: u32) -> Self {
+        self.wake_delay_us = wake_delay_us;
+        self
+    }
+
+    /// Time in us between polls while the device is busy executing a
+    /// command. Zero is treated as one.
+    pub fn poll_interval_us(mut self, poll_interval_us: u32) -> Self {
+        self.poll_interval_us = poll_interval_us.max(1);
+        self
+    }
+
+    /// Give up polling after the given time in ms and fail with
+    /// `ErrorKind::Timeout`. By default, the device is polled up to twice
+    /// the typical execution time of each command.
+    pub fn timeout_ms(mut self, timeout_ms: u32) -> Self {
+        self.timeout_ms = Some(timeout_ms);
+        self
+    }
+
+    /// Time in us to poll for a response to a command.
+    pub(crate) fn timeout_us(&self, exec_time_ms: u32) -> u32 {
+        self.timeout_ms.unwrap_or(exec_time_ms).saturating_mul(1000)
+    }
+}
+
+/// Carries command packets to the device and responses back, and manages its
+/// power states.
+pub trait Transport {
+    /// Sends the command packet in `buffer[1..length]` and receives the
+    /// response into `buffer`. `buffer[0]` is reserved for the word address or
+    /// the flag preceding the packet on the wire. `exec_time` is the time in
+    /// ms the command is allowed to execute for.
+    ///
+    /// The device is woken up beforehand if needed. Unless a session is open,
+    /// it is put into the idle state afterwards.
+    fn execute<'a>(
+        &mut self,
+        buffer: &'a mut [u8],
+        length: usize,
+        exec_time: u32,
+    ) -> Result<&'a [u8], Error>;
+
+    /// Keeps the device awake in between commands. Returns whether a session
+    /// has been open already.
+    fn begin_session(&mut self) -> bool;
+
+    /// Puts the device into the idle state if it is awake.
+    fn end_session(&mut self) -> Result<(), Error>;
+
+    /// Puts the device to sleep. Volatile states are lost.
+    fn sleep(&mut self) -> Result<(), Error>;
+}
+
+/// Tracks how long the device has been awake, so that commands do not run
+/// into the watchdog.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct Watchdog {
+    // Time in us spent since the device woke up, as long as it is awake.
+    awake_us: Option<u32>,
+    // Keep the device awake in between commands.
+    session: bool,
+}
+
+impl Watchdog {
+    pub(crate) fn begin_session(&mut self) -> bool {
+        core::mem::replace(&mut self.session, true)
+    }
+
+    /// Returns whether the device is awake and has to be put into idle.
+    pub(crate) fn end_session(&mut self) -> bool {
+        self.session = false;
+        self.is_awake()
+    }
+
+    pub(crate) fn in_session(&self) -> bool {
+        self.session
+    }
+
+    pub(crate) fn is_awake(&self) -> bool {
+        self.awake_us.is_some()
+    }
+
+    /// Whether the watchdog could expire before a command of the given
+    /// timeout completes. A device just woken up runs any command.
+    pub(crate) fn expires_within(&self, timeout_us: u32) -> bool {
+        match self.awake_us {
+            None | Some(0) => false,
+            Some(elapsed) => elapsed.saturating_add(timeout_us) >= WATCHDOG_US,
+        }
+    }
+
+    pub(crate) fn woken(&mut self) {
+        self.awake_us = Some(0);
+    }
+
+    pub(crate) fn elapse(&mut self, us: u32) {
+        self.awake_us = self.awake_us.map(|awake| awake.saturating_add(us));
+    }
+
+    /// Either idle or asleep.
+    pub(crate) fn dormant(&mut self) {
+        self.awake_us = None;
+    }
+}
+
+/// Datalink over an I2C bus, one device per address.
+pub struct I2c<PHY, D> {
     phy: PHY,
     delay: D,
+    config: I2cConfig,
+    watchdog: Watchdog,
 }
 
 impl<PHY, D> I2c<PHY, D> {
-    pub(crate) fn new(phy: PHY, delay: D) -> Self {
-        Self { phy, delay }
+    pub fn new(phy: PHY, delay: D, config: I2cConfig) -> Self {
+        Self {
+            phy,
+            delay,
+            config,
+            watchdog: Watchdog::default(),
+        }
+    }
+}
+
+impl<PHY, D> Transport for I2c<PHY, D>
+where
+    PHY: i2c::I2c,
+    D: DelayNs,
+{
+    fn execute<'a>(
+        &mut self,
+        buffer: &'a mut [u8],
+        length: usize,
+        exec_time: u32,
+    ) -> Result<&'a [u8], Error> {
+        match self.transmit(buffer, length, exec_time) {
+            Ok(response) if self.watchdog.in_session() => Ok(response),
+            Ok(response) => self.idle().map(|()| response),
+            Err(error) => {
+                // Device state is unknown. Start over with the next command.
+                self.idle().ok();
+                Err(error)
+            }
+        }
+    }
+
+    fn begin_session(&mut self) -> bool {
+        self.watchdog.begin_session()
+    }
+
+    fn end_session(&mut self) -> Result<(), Error> {
+        match self.watchdog.end_session() {
+            true => self.idle(),
+            false => Ok(()),
+        }
+    }
+
+    fn sleep(&mut self) -> Result<(), Error> {
+        let word_address = Transaction::Sleep as u8;
+        // The device only takes the word address while awake.
+        if !self.watchdog.is_awake() {
+            self.wake()?;
+        }
+        self.watchdog.dormant();
+        // Wait for the I2C bus to be ready.
+        self.delay.delay_us(30);
+        self.phy
+            .write(self.config.address, from_ref(&word_address))
+            .map_err(|_| ErrorKind::TxFail.into())
     }
 }
 
@@ -53,58 +259,80 @@ where
     PHY: i2c::I2c,
     D: DelayNs,
 {
-    /// Wakes up device, sends the packet, waits for command completion,
-    /// receives response, and puts the device into the idle state.
-    pub(crate) fn execute<'a>(
+    fn transmit<'a>(
         &mut self,
         buffer: &'a mut [u8],
-        packet: Packet,
-        exec_time: Option<u32>,
-    ) -> Result<Response<'a>, Error> {
-        self.wake()?;
-        self.send(&packet.buffer(buffer))?;
-        // Wait for the device to finish its job.
-        self.delay.delay_us(exec_time.unwrap_or(1) * 1000);
-        let response_buffer = self.receive(buffer)?;
-        self.idle()?;
-        Response::new(response_buffer)
-    }
-
-    fn send<T>(&mut self, bytes: &T) -> Result<(), Error>
-    where
-        T: AsRef<[u8]>,
-    {
+        length: usize,
+        exec_time: u32,
+    ) -> Result<&'a [u8], Error> {
+        self.ensure_awake(exec_time)?;
+        buffer[0] = Transaction::Command as u8;
+        self.send(&buffer[..length])?;
+        self.poll(exec_time)?;
+        self.receive(buffer).map(|response| &*response)
+    }
+
+    /// Within a session, the device is woken up anew if the watchdog could
+    /// expire before the command completes. Going through the idle state
+    /// retains TempKey and the other volatile states.
+    fn ensure_awake(&mut self, exec_time: u32) -> Result<(), Error> {
+        if self
+            .watchdog
+            .expires_within(self.config.timeout_us(exec_time))
+        {
+            self.idle()?;
+        }
+        if !self.watchdog.is_awake() {
+            self.wake()?;
+        }
+        Ok(())
+    }
+
+    fn send(&mut self, bytes: &[u8]) -> Result<(), Error> {
         self.phy
-            .write(ADDRESS, bytes.as_ref())
+            .write(self.config.address, bytes)
             .map_err(|_| ErrorKind::TxFail.into())
     }
 
-    /// Returns response buffer for later processing.
-    fn receive<'a>(&mut self, buffer: &'a mut [u8]) -> Result<&'a mut [u8], Error> {
+    /// Waits for the device to finish its job. It does not acknowledge its
+    /// address while busy.
+    fn poll(&mut self, exec_time: u32) -> Result<(), Error> {
         // Reset indicates the beginning of transaction.
         let word_address = Transaction::Reset as u8;
-        from_fn(|| self.phy.write(ADDRESS, from_ref(&word_address)).into())
-            .take(RETRY)
-            .find_map(Result::<_, _>::ok)
-            .ok_or_else(|| Error::from(ErrorKind::TxFail))?;
+        let interval = self.config.poll_interval_us;
+        let timeout = self.config.timeout_us(exec_time);
+        let mut elapsed: u32 = 0;
+        loop {
+            self.delay.delay_us(interval);
+            elapsed = elapsed.saturating_add(interval);
+            self.watchdog.elapse(interval);
+            if self
+                .phy
+                .write(self.config.address, from_ref(&word_address))
+                .is_ok()
+            {
+                return Ok(());
+            }
+            if elapsed >= timeout {
+                return Err(ErrorKind::Timeout.into());
+            }
+        }
+    }
 
-        let min_resp_size = 4;
+    /// Returns response buffer for later processing.
+    fn receive<'a>(&mut self, buffer: &'a mut [u8]) -> Result<&'a mut [u8], Error> {
         self.phy
-            .read(ADDRESS, &mut buffer[0..2])
+            .read(self.config.address, &mut buffer[0..2])
             .map_err(|_| Error::from(ErrorKind::RxFail))?;
 
-        let length_to_read = match buffer[0] {
+        let length_to_read = match response_length(buffer)? {
             // A single byte has already read.
-            length if length == 1 => return Ok(buffer[0..1].as_mut()),
-            // Buffer cannot contain the response to come. Abort.
-            length if buffer.len() < length as usize => return Err(ErrorKind::CommFail.into()),
-            // The coming response is malformed. Abort.
-            length if length < min_resp_size => return Err(ErrorKind::CommFail.into()),
-            length => length as usize,
+            None => return Ok(buffer[0..1].as_mut()),
+            Some(length) => length,
         };
 
         self.phy
-            .read(ADDRESS, buffer[2..length_to_read].as_mut())
+            .read(self.config.address, buffer[2..length_to_read].as_mut())
             .map(move |()| buffer[..length_to_read].as_mut())
             .map_err(|_| ErrorKind::RxFail.into())
     }
@@ -113,37 +341,49 @@ where
         // Send a single null byte to an absent address.
         //
         // Ignore errors as this will error if the device is not awake yet.
-        self.phy.write(ADDRESS, from_ref(&0x00)).ok();
+        self.phy.write(self.config.address, from_ref(&0x00)).ok();
 
         // Wait for the device to wake up.
-        self.delay.delay_us(DELAY_US);
+        self.delay.delay_us(self.config.wake_delay_us);
 
         let buffer = &mut [0x00, 0x00, 0x00, 0x00];
-        from_fn(|| self.phy.read(ADDRESS, buffer.as_mut()).into())
-            .take(RETRY)
+        let I2cConfig { address, retry, .. } = self.config;
+        from_fn(|| self.phy.read(address, buffer.as_mut()).into())
+            .take(retry)
             .find_map(Result::<_, _>::ok)
             .ok_or_else(|| Error::from(ErrorKind::RxFail))?;
 
-        match buffer.as_ref() {
-            WAKE_RESPONSE_EXPECTED => Ok(()),
-            WAKE_SELFTEST_FAILED => Err(ErrorKind::WakeFailed.into()),
-            _ => Err(ErrorKind::WakeFailed.into()),
-        }
+        check_wake_response(buffer)?;
+        self.watchdog.woken();
+        Ok(())
     }
 
     fn idle(&mut self) -> Result<(), Error> {
         let word_address = Transaction::Idle as u8;
+        self.watchdog.dormant();
         self.phy
-            .write(ADDRESS, from_ref(&word_address))
+            .write(self.config.address, from_ref(&word_address))
             .map_err(|_| ErrorKind::TxFail.into())
     }
+}
 
-    pub(crate) fn sleep(&mut self) -> Result<(), Error> {
-        let word_address = Transaction::Sleep as u8;
-        // Wait for the I2C bus to be ready.
-        self.delay.delay_us(30);
-        self.phy
-            .write(ADDRESS, from_ref(&word_address))
-            .map_err(|_| ErrorKind::TxFail.into())
+/// Interprets the length byte at the head of a response. `None` means the
+/// response consists of a single byte which has already been read.
+pub(crate) fn response_length(buffer: &[u8]) -> Result<Option<usize>, Error> {
+    match buffer[0] {
+        1 => Ok(None),
+        // Buffer cannot contain the response to come. Abort.
+        length if buffer.len() < length as usize => Err(ErrorKind::CommFail.into()),
+        // The coming response is malformed. Abort.
+        length if length < MIN_RESP_SIZE => Err(ErrorKind::CommFail.into()),
+        length => Ok(Some(length as usize)),
+    }
+}
+
+pub(crate) fn check_wake_response(buffer: &[u8]) -> Result<(), Error> {
+    match buffer {
+        WAKE_RESPONSE_EXPECTED => Ok(()),
+        WAKE_SELFTEST_FAILED => Err(ErrorKind::WakeFailed.into()),
+        _ => Err(ErrorKind::WakeFailed.into()),
     }
 }
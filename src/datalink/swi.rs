@@ -0,0 +1,324 @@
+// Single-wire interface over a UART. Every bit on the wire is a UART frame of 7
+// data bits at 230400 baud, 0x7F for one and 0x7D for zero, and bytes are sent
+// LSB first. Each transfer from the host starts with a flag byte. The device
+// responds only to the transmit flag, so polling for completion means sending
+// transmit flags until the line becomes busy.
+use super::{check_wake_response, response_length, Transport, Watchdog};
+use super::{DELAY_US, POLL_INTERVAL_US};
+use crate::error::{Error, ErrorKind};
+use core::iter::once;
+use embedded_hal::delay::DelayNs;
+use embedded_io::{ErrorType, Read, ReadReady, Write};
+
+/// Baud rate of regular transfers.
+pub(crate) const BAUD_RATE: u32 = 230_400;
+/// Baud rate of the wake token. A null byte then holds the line low for long
+/// enough.
+pub(crate) const WAKE_BAUD_RATE: u32 = 115_200;
+const BIT_ONE: u8 = 0x7f;
+const BIT_ZERO: u8 = 0x7d;
+// Time in us the device takes to start transmitting after a transmit flag.
+const TURNAROUND_US: u32 = 150;
+
+/// Flags heading transfers from the host.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Flag {
+    Command = 0x77,
+    Transmit = 0x88,
+    Idle = 0xbb,
+    Sleep = 0xcc,
+}
+
+/// A UART whose baud rate can be switched, as sending the wake token requires.
+pub trait BaudRate: ErrorType {
+    fn set_baud_rate(&mut self, baud_rate: u32) -> Result<(), Self::Error>;
+}
+
+impl<T: BaudRate + ?Sized> BaudRate for &mut T {
+    fn set_baud_rate(&mut self, baud_rate: u32) -> Result<(), Self::Error> {
+        T::set_baud_rate(self, baud_rate)
+    }
+}
+
+/// Line parameters, built up by chaining setters on the default.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SwiConfig {
+    pub(crate) wake_delay_us: u32,
+    pub(crate) poll_interval_us: u32,
+    pub(crate) timeout_ms: Option<u32>,
+    pub(crate) echo: bool,
+}
+
+impl Default for SwiConfig {
+    fn default() -> Self {
+        Self {
+            wake_delay_us: DELAY_US,
+            poll_interval_us: POLL_INTERVAL_US,
+            timeout_ms: None,
+            echo: false,
+        }
+    }
+}
+
+impl SwiConfig {
+    /// Time in us to wait for the device to wake up.
+    pub fn wake_delay_us(mut self, wake_delay_us: u32) -> Self {
+        self.wake_delay_us = wake_delay_us;
+        self
+    }
+
+    /// Time in us between polls while the device is busy executing a
+    /// command. Zero is treated as one.
+    pub fn poll_interval_us(mut self, poll_interval_us: u32) -> Self {
+        self.poll_interval_us = poll_interval_us.max(1);
+        self
+    }
+
+    /// Give up polling after the given time in ms and fail with
+    /// `ErrorKind::Timeout`. By default, the device is polled up to twice
+    /// the typical execution time of each command.
+    pub fn timeout_ms(mut self, timeout_ms: u32) -> Self {
+        self.timeout_ms = Some(timeout_ms);
+        self
+    }
+
+    /// Whether the receiver sees the transmitted frames too, which is the
+    /// case when TX and RX are simply tied together. Echoed frames are then
+    /// read back and discarded.
+    pub fn echo(mut self, echo: bool) -> Self {
+        self.echo = echo;
+        self
+    }
+
+    fn timeout_us(&self, exec_time_ms: u32) -> u32 {
+        self.timeout_ms.unwrap_or(exec_time_ms).saturating_mul(1000)
+    }
+}
+
+/// Datalink over the single-wire interface, driven by a UART.
+pub struct Swi<UART, D> {
+    uart: UART,
+    delay: D,
+    config: SwiConfig,
+    watchdog: Watchdog,
+}
+
+impl<UART, D> Swi<UART, D> {
+    /// The UART is expected to run at 230400 baud with 7 data bits, no parity
+    /// and 1 stop bit.
+    pub fn new(uart: UART, delay: D, config: SwiConfig) -> Self {
+        Self {
+            uart,
+            delay,
+            config,
+            watchdog: Watchdog::default(),
+        }
+    }
+}
+
+impl<UART, D> Transport for Swi<UART, D>
+where
+    UART: Read + Write + ReadReady + BaudRate,
+    D: DelayNs,
+{
+    fn execute<'a>(
+        &mut self,
+        buffer: &'a mut [u8],
+        length: usize,
+        exec_time: u32,
+    ) -> Result<&'a [u8], Error> {
+        match self.transmit(buffer, length, exec_time) {
+            Ok(response) if self.watchdog.in_session() => Ok(response),
+            Ok(response) => self.idle().map(|()| response),
+            Err(error) => {
+                // Device state is unknown. Start over with the next command.
+                self.idle().ok();
+                Err(error)
+            }
+        }
+    }
+
+    fn begin_session(&mut self) -> bool {
+        self.watchdog.begin_session()
+    }
+
+    fn end_session(&mut self) -> Result<(), Error> {
+        match self.watchdog.end_session() {
+            true => self.idle(),
+            false => Ok(()),
+        }
+    }
+
+    fn sleep(&mut self) -> Result<(), Error> {
+        // The device only takes flags while awake.
+        if !self.watchdog.is_awake() {
+            self.wake()?;
+        }
+        self.watchdog.dormant();
+        self.send(Flag::Sleep, &[])
+    }
+}
+
+impl<UART, D> Swi<UART, D>
+where
+    UART: Read + Write + ReadReady + BaudRate,
+    D: DelayNs,
+{
+    fn transmit<'a>(
+        &mut self,
+        buffer: &'a mut [u8],
+        length: usize,
+        exec_time: u32,
+    ) -> Result<&'a [u8], Error> {
+        if self
+            .watchdog
+            .expires_within(self.config.timeout_us(exec_time))
+        {
+            self.idle()?;
+        }
+        if !self.watchdog.is_awake() {
+            self.wake()?;
+        }
+        self.send(Flag::Command, &buffer[1..length])?;
+        self.poll(exec_time)?;
+        self.receive(buffer)
+    }
+
+    fn send(&mut self, flag: Flag, bytes: &[u8]) -> Result<(), Error> {
+        once(flag as u8)
+            .chain(bytes.iter().copied())
+            .try_for_each(|byte| self.write_frames(&encode(byte)))?;
+        self.uart.flush().map_err(|_| ErrorKind::TxFail.into())
+    }
+
+    fn write_frames(&mut self, frames: &[u8]) -> Result<(), Error> {
+        self.uart
+            .write_all(frames)
+            .map_err(|_| Error::from(ErrorKind::TxFail))?;
+        if self.config.echo {
+            let mut echo = [0x00; 8];
+            self.uart
+                .read_exact(&mut echo[..frames.len()])
+                .map_err(|_| Error::from(ErrorKind::TxFail))?;
+        }
+        Ok(())
+    }
+
+    /// Waits for the device to finish its job. It does not answer a transmit
+    /// flag while busy.
+    fn poll(&mut self, exec_time: u32) -> Result<(), Error> {
+        let interval = self.config.poll_interval_us;
+        let timeout = self.config.timeout_us(exec_time);
+        let mut elapsed: u32 = 0;
+        loop {
+            self.delay.delay_us(interval);
+            elapsed = elapsed.saturating_add(interval);
+            self.watchdog.elapse(interval);
+            if self.request_transmission()? {
+                return Ok(());
+            }
+            if elapsed >= timeout {
+                return Err(ErrorKind::Timeout.into());
+            }
+        }
+    }
+
+    /// Sends a transmit flag and tells whether the device started responding.
+    fn request_transmission(&mut self) -> Result<bool, Error> {
+        self.send(Flag::Transmit, &[])?;
+        self.delay.delay_us(TURNAROUND_US);
+        self.uart.read_ready().map_err(|_| ErrorKind::RxFail.into())
+    }
+
+    fn read_byte(&mut self) -> Result<u8, Error> {
+        let mut frames = [0x00; 8];
+        self.uart
+            .read_exact(&mut frames)
+            .map_err(|_| Error::from(ErrorKind::RxFail))?;
+        Ok(decode(&frames))
+    }
+
+    /// Returns response buffer for later processing.
+    fn receive<'a>(&mut self, buffer: &'a mut [u8]) -> Result<&'a [u8], Error> {
+        buffer[0] = self.read_byte()?;
+        let length = match response_length(buffer)? {
+            None => return Ok(&buffer[..1]),
+            Some(length) => length,
+        };
+        for byte in buffer[1..length].iter_mut() {
+            *byte = self.read_byte()?;
+        }
+        Ok(&buffer[..length])
+    }
+
+    fn wake(&mut self) -> Result<(), Error> {
+        // Hold the line low by sending a null byte at a lower baud rate.
+        self.uart
+            .set_baud_rate(WAKE_BAUD_RATE)
+            .map_err(|_| Error::from(ErrorKind::CommFail))?;
+        let result = self.write_frames(&[0x00]);
+        self.uart.flush().ok();
+        self.uart
+            .set_baud_rate(BAUD_RATE)
+            .map_err(|_| Error::from(ErrorKind::CommFail))?;
+        result?;
+
+        // Wait for the device to wake up.
+        self.delay.delay_us(self.config.wake_delay_us);
+
+        if !self.request_transmission()? {
+            return Err(ErrorKind::WakeFailed.into());
+        }
+        let mut buffer = [0x00; 4];
+        for byte in buffer.iter_mut() {
+            *byte = self.read_byte()?;
+        }
+        check_wake_response(&buffer)?;
+        self.watchdog.woken();
+        Ok(())
+    }
+
+    fn idle(&mut self) -> Result<(), Error> {
+        self.watchdog.dormant();
+        self.send(Flag::Idle, &[])
+    }
+}
+
+/// One UART frame per bit, LSB first.
+pub(crate) fn encode(byte: u8) -> [u8; 8] {
+    let mut frames = [BIT_ZERO; 8];
+    frames
+        .iter_mut()
+        .enumerate()
+        .filter(|(i, _)| byte >> i & 0x01 == 0x01)
+        .for_each(|(_, frame)| *frame = BIT_ONE);
+    frames
+}
+
+/// The device may stretch bits. A one is read as 0x7E or 0x7F, and a zero as
+/// 0x7A, 0x7B or 0x7D.
+pub(crate) fn decode(frames: &[u8; 8]) -> u8 {
+    frames
+        .iter()
+        .enumerate()
+        .filter(|(_, frame)| *frame ^ BIT_ONE < 2)
+        .fold(0x00, |byte, (i, _)| byte | 0x01 << i)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bit_encoding() {
+        assert_eq!(
+            encode(Flag::Command as u8),
+            [0x7f, 0x7f, 0x7f, 0x7d, 0x7f, 0x7f, 0x7f, 0x7d]
+        );
+        assert_eq!(decode(&encode(0xa5)), 0xa5);
+        assert_eq!(
+            decode(&[0x7e, 0x7a, 0x7b, 0x7d, 0x7f, 0x7d, 0x7d, 0x7e]),
+            0x91
+        );
+    }
+}
@@ -12,6 +12,24 @@ enum Repr {
     Simple(ErrorKind),
 }
 
+impl Error {
+    /// Cause of an error detected by the host, if any.
+    pub fn kind(&self) -> Option<ErrorKind> {
+        match self.repr {
+            Repr::Simple(kind) => Some(kind),
+            Repr::Device(_) => None,
+        }
+    }
+
+    /// Status reported by the device, if any.
+    pub fn status(&self) -> Option<Status> {
+        match self.repr {
+            Repr::Device(status) => Some(status),
+            Repr::Simple(_) => None,
+        }
+    }
+}
+
 impl From<ErrorKind> for Error {
     fn from(kind: ErrorKind) -> Error {
         Error {
@@ -37,7 +55,7 @@ impl core::fmt::Display for Error {
     }
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum Status {
     /// response status byte indicates CheckMac failure (status byte = 0x01)
     CheckmacVerifyFailed = 0x01,
@@ -103,7 +121,7 @@ impl core::fmt::Display for Status {
 }
 
 /// A list of specific error causes. Each kind is converted into `Error` type.
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum ErrorKind {
     /// Code failed run-time consistency check
     AssertFailure = 0xF6,
@@ -144,6 +162,8 @@ pub enum ErrorKind {
     UseFlagsConsumed = 0xFC,
     /// Device did not respond to wake call as expected
     WakeFailed = 0xD0,
+    /// Zone contents differ from the expected image
+    ZoneMismatch = 0xD8,
 }
 
 impl core::fmt::Display for ErrorKind {
@@ -191,6 +211,7 @@ impl core::fmt::Display for ErrorKind {
                 write!(fmt, "use flags on the device indicates its consumed fully")
             }
             Self::WakeFailed => write!(fmt, "device did not respond to wake call as expected"),
+            Self::ZoneMismatch => write!(fmt, "zone contents differ from the expected image"),
         }
     }
 }
@@ -0,0 +1,416 @@
+//! Host side counterparts of the digests the device computes. Knowing the
+//! keys involved, the host follows TempKey through a command sequence and
+//! derives the same session keys and MACs off-chip.
+//!
+//! Digests mix in bytes of the serial number as returned by
+//! `Memory::serial_number`, along with the opcode and parameters of the
+//! command. Only the data of the calculation is modelled here. Whether the
+//! device accepts the command in the first place is up to its configuration.
+use super::command::{Block, DeriveKey, NonceCtx, OpCode, Serial};
+use super::memory::Zone;
+use core::convert::TryInto;
+use sha2::{Digest as _, Sha256};
+
+/// MAC mode: the second 32 bytes are TempKey instead of the challenge.
+pub const MAC_MODE_BLOCK2_TEMPKEY: u8 = 0x01;
+/// MAC mode: the first 32 bytes are TempKey instead of the slot key.
+pub const MAC_MODE_BLOCK1_TEMPKEY: u8 = 0x02;
+/// MAC mode: SourceFlag of TempKey must match bit 0 of the mode.
+pub const MAC_MODE_SOURCE_FLAG_MATCH: u8 = 0x04;
+/// MAC mode: include 88 bits of the OTP zone. Bit 5 is ignored while this
+/// bit is set.
+pub const MAC_MODE_INCLUDE_OTP_88: u8 = 0x10;
+/// MAC mode: include 64 bits of the OTP zone.
+pub const MAC_MODE_INCLUDE_OTP_64: u8 = 0x20;
+/// MAC mode: include the whole serial number.
+pub const MAC_MODE_INCLUDE_SN: u8 = 0x40;
+
+/// Where the value of TempKey came from, as the device keeps track of in its
+/// SourceFlag.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Source {
+    /// Nonce in random mode, mixing in the internal random number generator.
+    Random,
+    /// Nonce in pass-through mode, taking a value from the host.
+    Input,
+}
+
+/// A copy of TempKey held on the host. Each method mirrors the effect of the
+/// corresponding command on TempKey, or computes a digest the device would
+/// calculate over it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TempKey {
+    // Most commands only take the lower 32 bytes.
+    value: [u8; 64],
+    source: Source,
+}
+
+impl TempKey {
+    /// TempKey after Nonce in random mode, combining RandOut from the device
+    /// with NumIn from the host. `update_seed` must match the Nonce command.
+    pub fn random(rand_out: &Block, num_in: &[u8; 20], update_seed: bool) -> Self {
+        let mode = NonceCtx::random_mode(update_seed);
+        let value = Sha256::new()
+            .chain(rand_out)
+            .chain(num_in)
+            .chain([OpCode::Nonce as u8, mode, 0x00])
+            .finalize();
+        Self::new(&value, Source::Random)
+    }
+
+    /// TempKey after Nonce in pass-through mode.
+    pub fn fixed(value: &[u8; 32]) -> Self {
+        Self::new(value, Source::Input)
+    }
+
+    /// TempKey holding up to 64 bytes, zero padded. Commands writing their
+    /// result into TempKey, such as ECDH or KDF, keep the source flag.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `value` is longer than 64 bytes.
+    pub fn new(value: &[u8], source: Source) -> Self {
+        let mut bytes = [0x00; 64];
+        bytes[..value.len()].copy_from_slice(value);
+        Self {
+            value: bytes,
+            source,
+        }
+    }
+
+    pub fn value(&self) -> &[u8; 32] {
+        self.value[..32]
+            .try_into()
+            .unwrap_or_else(|_| unreachable!("TempKey holds more than 32 bytes."))
+    }
+
+    /// All 64 bytes, the upper half of which Nonce in 64-byte pass-through
+    /// mode and KDF make use of.
+    pub fn bytes(&self) -> &[u8; 64] {
+        &self.value
+    }
+
+    pub fn source(&self) -> Source {
+        self.source
+    }
+
+    /// GenDig combining a 32 byte value in a zone into TempKey. It is a key
+    /// in a data slot, or a block of the config or OTP zone. `key_id` is the
+    /// slot or block number accordingly.
+    pub fn gen_dig(&mut self, zone: Zone, key_id: u16, value: &[u8; 32], serial: &Serial) {
+        let [lsb, msb] = key_id.to_le_bytes();
+        let sn = serial.as_ref();
+        let digest = Sha256::new()
+            .chain(value)
+            .chain([OpCode::GenDig as u8, zone as u8, lsb, msb])
+            .chain([sn[8], sn[0], sn[1]])
+            .chain([0x00; 25])
+            .chain(self.value())
+            .finalize();
+        *self = Self::new(&digest, self.source);
+    }
+
+    /// Encrypted reads and writes XOR data with TempKey both ways.
+    pub fn xor(&self, data: &[u8; 32]) -> [u8; 32] {
+        let mut output = [0x00; 32];
+        output
+            .iter_mut()
+            .zip(self.value().iter().zip(data))
+            .for_each(|(dst, (key, src))| *dst = key ^ src);
+        output
+    }
+
+    /// Input MAC of an encrypted Write, computed over the plain text.
+    /// `zone` is the zone parameter of Write without the encryption bit.
+    pub fn write_mac(&self, zone: u8, address: u16, data: &[u8; 32], serial: &Serial) -> [u8; 32] {
+        let [lsb, msb] = address.to_le_bytes();
+        let sn = serial.as_ref();
+        Sha256::new()
+            .chain(self.value())
+            .chain([OpCode::Write as u8, zone, lsb, msb])
+            .chain([sn[8], sn[0], sn[1]])
+            .chain([0x00; 25])
+            .chain(data)
+            .finalize()
+            .into()
+    }
+
+    /// Key DeriveKey writes into the target slot. `parent` is the current
+    /// key of the target in roll mode, or the key of its WriteKey slot in
+    /// create mode.
+    pub fn derive_key(&self, target: u16, parent: &[u8; 32], serial: &Serial) -> [u8; 32] {
+        let [lsb, msb] = target.to_le_bytes();
+        let sn = serial.as_ref();
+        Sha256::new()
+            .chain(parent)
+            .chain([OpCode::DeriveKey as u8, self.derive_key_mode(), lsb, msb])
+            .chain([sn[8], sn[0], sn[1]])
+            .chain([0x00; 25])
+            .chain(self.value())
+            .finalize()
+            .into()
+    }
+
+    /// Authorizing MAC of DeriveKey, for targets whose WriteConfig requires
+    /// one. It is keyed by the same parent as `derive_key`.
+    pub fn derive_key_mac(&self, target: u16, parent: &[u8; 32], serial: &Serial) -> [u8; 32] {
+        let [lsb, msb] = target.to_le_bytes();
+        let sn = serial.as_ref();
+        Sha256::new()
+            .chain(parent)
+            .chain([OpCode::DeriveKey as u8, self.derive_key_mode(), lsb, msb])
+            .chain([sn[8], sn[0], sn[1]])
+            .finalize()
+            .into()
+    }
+
+    // DeriveKey states the source of TempKey in its mode.
+    fn derive_key_mode(&self) -> u8 {
+        DeriveKey::mode(self.source == Source::Input)
+    }
+}
+
+/// Digest of the MAC command. Depending on `mode`, the caller passes either
+/// the slot key or TempKey as `block1`, and either the challenge or TempKey
+/// as `block2`. Bytes of `otp` and the serial number are included as the
+/// mode tells.
+pub fn mac(
+    mode: u8,
+    key_id: u16,
+    block1: &[u8; 32],
+    block2: &[u8; 32],
+    otp: &[u8; 11],
+    serial: &Serial,
+) -> [u8; 32] {
+    let [lsb, msb] = key_id.to_le_bytes();
+    let sn = serial.as_ref();
+    let mut otp_bytes = [0x00; 11];
+    if mode & MAC_MODE_INCLUDE_OTP_88 != 0x00 {
+        otp_bytes.copy_from_slice(otp);
+    } else if mode & MAC_MODE_INCLUDE_OTP_64 != 0x00 {
+        otp_bytes[..8].copy_from_slice(&otp[..8]);
+    }
+    let mut sn_bytes = [0x00; 6];
+    if mode & MAC_MODE_INCLUDE_SN != 0x00 {
+        sn_bytes[..4].copy_from_slice(&sn[4..8]);
+        sn_bytes[4..].copy_from_slice(&sn[2..4]);
+    }
+    Sha256::new()
+        .chain(block1)
+        .chain(block2)
+        .chain([OpCode::Mac as u8, mode, lsb, msb])
+        .chain(otp_bytes)
+        .chain([sn[8]])
+        .chain(&sn_bytes[..4])
+        .chain([sn[0], sn[1]])
+        .chain(&sn_bytes[4..])
+        .finalize()
+        .into()
+}
+
+/// Check a response to a challenge from the MAC command of a device, given
+/// its serial number and a copy of the key. Modes taking TempKey in place of
+/// the key or challenge are up to the caller to compute with `mac`.
+pub fn verify_mac(
+    mode: u8,
+    key_id: u16,
+    key: &[u8; 32],
+    challenge: &[u8; 32],
+    otp: &[u8; 11],
+    serial: &Serial,
+    response: &[u8; 32],
+) -> bool {
+    let expected = mac(mode, key_id, key, challenge, otp, serial);
+    // Compare in constant time.
+    expected
+        .iter()
+        .zip(response)
+        .fold(0x00, |diff, (a, b)| diff | a ^ b)
+        == 0x00
+}
+
+/// Digest CheckMac compares the client response against. OtherData stands
+/// in for the opcode, mode and key ID that the MAC command hashes, so that a
+/// response from another device verifies. OTP bytes are included when mode
+/// bit 5 is set.
+pub fn check_mac(
+    mode: u8,
+    block1: &[u8; 32],
+    block2: &[u8; 32],
+    other_data: &[u8; 13],
+    otp: &[u8; 8],
+    serial: &Serial,
+) -> [u8; 32] {
+    let sn = serial.as_ref();
+    let otp = match mode & MAC_MODE_INCLUDE_OTP_64 {
+        0x00 => [0x00; 8],
+        _ => *otp,
+    };
+    Sha256::new()
+        .chain(block1)
+        .chain(block2)
+        .chain(&other_data[..4])
+        .chain(otp)
+        .chain(&other_data[4..7])
+        .chain([sn[8]])
+        .chain(&other_data[7..11])
+        .chain([sn[0], sn[1]])
+        .chain(&other_data[11..])
+        .finalize()
+        .into()
+}
+
+/// Decrypt the output of KDF or ECDH returned under the IO protection key.
+/// Each 32-byte block is XORed with SHA-256 over the key and its own 16
+/// bytes of the nonce the device returned alongside.
+pub fn io_decrypt(io_key: &[u8; 32], nonce: &[u8; 32], data: &mut [u8]) {
+    data.chunks_mut(32)
+        .zip(nonce.chunks(16))
+        .for_each(|(block, nonce)| {
+            let key = Sha256::new().chain(io_key).chain(nonce).finalize();
+            block
+                .iter_mut()
+                .zip(key.iter())
+                .for_each(|(byte, key)| *byte ^= key);
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::convert::TryFrom;
+
+    // Known answers are computed independently from the message layouts in
+    // the datasheet.
+    fn serial() -> Serial {
+        let mut config = [0x00; 32];
+        config[..4].copy_from_slice(&[0x01, 0x23, 0x6a, 0xb1]);
+        config[8..13].copy_from_slice(&[0x5c, 0x2f, 0x07, 0x3d, 0xee]);
+        Serial::try_from(&config[..]).unwrap()
+    }
+
+    #[test]
+    fn nonce() {
+        let rand_out = Block::try_from(&[0xa5; 32][..]).unwrap();
+        let temp_key = TempKey::random(&rand_out, &[0x5a; 20], true);
+        assert_eq!(
+            temp_key.value(),
+            &[
+                0xbe, 0xc2, 0x0a, 0x2a, 0x55, 0x10, 0x9e, 0xa0, 0x39, 0x85, 0x4d, 0xc9, 0xcc, 0x02,
+                0x70, 0xd5, 0x19, 0x71, 0x04, 0xea, 0x89, 0x56, 0x02, 0x07, 0x64, 0x6b, 0xfb, 0xf3,
+                0x43, 0x45, 0x40, 0xf6
+            ]
+        );
+        assert_eq!(temp_key.source(), Source::Random);
+        assert_ne!(TempKey::random(&rand_out, &[0x5a; 20], false), temp_key);
+        assert_eq!(TempKey::fixed(&[0x11; 32]).source(), Source::Input);
+    }
+
+    #[test]
+    fn gen_dig() {
+        let mut temp_key = TempKey::fixed(&[0x11; 32]);
+        temp_key.gen_dig(Zone::Data, 0x0006, &[0x22; 32], &serial());
+        assert_eq!(
+            temp_key.value(),
+            &[
+                0xbf, 0xed, 0x42, 0x6f, 0x47, 0x9e, 0xdd, 0x64, 0x0f, 0xba, 0x9b, 0xb1, 0xf4, 0x86,
+                0xb5, 0x93, 0x9a, 0x18, 0xbb, 0xab, 0x93, 0x24, 0x0c, 0x43, 0x2d, 0x86, 0x7d, 0x05,
+                0xaa, 0x41, 0x56, 0x5c
+            ]
+        );
+        let cipher = temp_key.xor(&[0x33; 32]);
+        assert_eq!(temp_key.xor(&cipher), [0x33; 32]);
+        assert_eq!(
+            temp_key.write_mac(0x82, 0x0140, &[0x33; 32], &serial()),
+            [
+                0x5a, 0x17, 0x06, 0x3c, 0x85, 0x21, 0xfc, 0x1b, 0x4f, 0x07, 0x95, 0x5c, 0x0d, 0xe4,
+                0xd7, 0xcc, 0xd5, 0xb1, 0x17, 0x94, 0x39, 0x4a, 0x74, 0xb2, 0x20, 0x65, 0xfb, 0x0c,
+                0x01, 0x17, 0xb9, 0x9e
+            ]
+        );
+        assert_eq!(
+            temp_key.derive_key(0x0007, &[0x44; 32], &serial()),
+            [
+                0x6e, 0x2d, 0xbb, 0x6c, 0xb3, 0x9e, 0x95, 0xc3, 0x91, 0xf2, 0x3f, 0x1f, 0x5b, 0xdf,
+                0xc7, 0xcb, 0x6a, 0x6f, 0xae, 0x5a, 0xa0, 0x33, 0xe4, 0xad, 0x1f, 0x58, 0x29, 0xe4,
+                0xcc, 0xa1, 0x70, 0x11
+            ]
+        );
+        assert_eq!(
+            temp_key.derive_key_mac(0x0007, &[0x44; 32], &serial()),
+            [
+                0x30, 0xfd, 0xea, 0x8b, 0x62, 0x97, 0x8c, 0x39, 0xfd, 0x30, 0xd1, 0x19, 0xae, 0x7f,
+                0x7d, 0x6f, 0xf0, 0x37, 0x9b, 0xe8, 0x68, 0xa3, 0x87, 0x53, 0x29, 0x87, 0xff, 0xbc,
+                0x6b, 0x48, 0x14, 0x5e
+            ]
+        );
+    }
+
+    #[test]
+    fn io_decrypt() {
+        let mut nonce = [0x00; 32];
+        nonce
+            .iter_mut()
+            .enumerate()
+            .for_each(|(i, byte)| *byte = i as u8);
+        let mut data = [0x00; 64];
+        super::io_decrypt(&[0x5a; 32], &nonce, &mut data);
+        assert_eq!(
+            data[..32],
+            [
+                0x07, 0xcb, 0x22, 0xff, 0xb4, 0x45, 0x36, 0xed, 0xa7, 0x34, 0x8b, 0x41, 0x61, 0xf0,
+                0x1e, 0xd7, 0xd6, 0x99, 0xe7, 0x93, 0x60, 0xae, 0x42, 0x16, 0x4c, 0xfe, 0x56, 0x66,
+                0x95, 0x3a, 0xa6, 0xb0
+            ]
+        );
+        assert_eq!(
+            data[32..],
+            [
+                0x46, 0x3b, 0x9a, 0x11, 0xc2, 0x5f, 0xf3, 0x2b, 0x1a, 0x99, 0x75, 0x6f, 0x74, 0x8c,
+                0xf4, 0x27, 0xdf, 0xb1, 0xb1, 0xef, 0xc8, 0x54, 0xfd, 0xba, 0xe9, 0x41, 0x45, 0x1a,
+                0x69, 0x43, 0x76, 0x0c
+            ]
+        );
+    }
+
+    #[test]
+    fn mac() {
+        let otp = [0x77; 11];
+        let digest = super::mac(0x50, 0x0003, &[0x55; 32], &[0x66; 32], &otp, &serial());
+        assert_eq!(
+            digest,
+            [
+                0x24, 0xb0, 0xa2, 0x80, 0x0e, 0x22, 0x1d, 0x2b, 0xa5, 0x81, 0xf7, 0xc8, 0xcc, 0xa1,
+                0x31, 0x1d, 0x76, 0xb1, 0xb3, 0x12, 0xee, 0x16, 0xa3, 0xd2, 0x18, 0x5f, 0x03, 0x50,
+                0xba, 0xe5, 0x23, 0x22
+            ]
+        );
+
+        // All 88 bits of OTP are included, whatever bit 5 says. The digest
+        // follows the message layout of atcah_mac in cryptoauthlib.
+        let digest = super::mac(0x30, 0x0003, &[0x55; 32], &[0x66; 32], &otp, &serial());
+        assert_eq!(
+            digest,
+            [
+                0xf8, 0xfd, 0xbd, 0x52, 0xe2, 0x96, 0x25, 0x4a, 0x99, 0x58, 0xb3, 0x6f, 0x5f, 0xab,
+                0x1b, 0xf4, 0xb1, 0x2e, 0x4b, 0x64, 0xae, 0x41, 0x55, 0xa2, 0x35, 0x22, 0x6b, 0xdd,
+                0xa9, 0x21, 0xac, 0x9d
+            ]
+        );
+
+        // CheckMac given the opcode, mode and key ID of MAC as OtherData
+        // arrives at the same digest.
+        let digest = super::mac(0x00, 0x0003, &[0x55; 32], &[0x66; 32], &otp, &serial());
+        let mut other_data = [0x00; 13];
+        other_data[..4].copy_from_slice(&[OpCode::Mac as u8, 0x00, 0x03, 0x00]);
+        let expected = check_mac(
+            0x00,
+            &[0x55; 32],
+            &[0x66; 32],
+            &other_data,
+            &[0x77; 8],
+            &serial(),
+        );
+        assert_eq!(digest, expected);
+    }
+}
@@ -1,16 +1,29 @@
 #![no_std]
 mod fmt;
 
+#[cfg(feature = "async")]
+pub mod asynch;
 mod client;
 mod clock_divider;
 mod command;
-mod datalink;
+pub mod config;
+pub mod datalink;
 pub mod error;
+pub mod host;
 pub mod memory;
 mod packet;
+#[cfg(any(test, feature = "simulator"))]
+pub mod simulator;
 pub mod tngtls;
 
-pub use client::{AtCaClient, Memory, Verifier, Verify};
-pub use command::{Block, Digest, PublicKey, Signature};
+pub use client::{
+    AtCaClient, Counter, Ecdh, EphemeralKey, Hmac, Kdf, KdfOutput, Memory, Nonce, Session,
+    ShaContext, Verifier, Verify,
+};
+pub use command::{
+    Block, CounterId, Digest, EcdhSource, KdfMessage, KdfSource, KdfTarget, NonceTarget, PublicKey,
+    Signature,
+};
+pub use datalink::{I2cConfig, Transport};
 pub use packet::CRC16;
 pub use signature;
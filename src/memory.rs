@@ -1,11 +1,12 @@
 use super::error::{Error, ErrorKind};
+use core::iter::from_fn;
 use core::ops::{Range, RangeInclusive};
 use core::slice::from_ref;
 /// Zone bit 7 set: Access 32 bytes, otherwise 4 bytes.
 const ZONE_READWRITE_32: u8 = 0x80;
 
 /// A unit of data exchange is either 4 or 32 bytes.
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum Size {
     Word = 0x04,
     Block = 0x20,
@@ -28,6 +29,15 @@ pub enum Zone {
 }
 
 impl Zone {
+    /// Size of the zone in bytes. Data zone spans all the 16 slots.
+    pub fn size(&self) -> usize {
+        match self {
+            Self::Config => 128,
+            Self::Otp => 64,
+            Self::Data => 8 * 36 + 416 + 7 * 72,
+        }
+    }
+
     // A helper method to translate a global index into block and offset.
     pub fn locate_index(index: usize) -> (u8, u8, u8) {
         let block = index / Size::Block.len();
@@ -37,10 +47,26 @@ impl Zone {
     }
 
     pub(crate) fn get_slot_addr(&self, slot: Slot, block: u8) -> Result<u16, Error> {
+        self.get_data_addr(slot, block, 0, Size::Block)
+    }
+
+    // Address of a word within a slot, accessed in `size` units. Accesses past
+    // the end of the slot are rejected, except that the last block of a
+    // 72-byte slot is read and written as a whole, as public keys and
+    // certificates are.
+    pub(crate) fn get_data_addr(
+        &self,
+        slot: Slot,
+        block: u8,
+        offset: u8,
+        size: Size,
+    ) -> Result<u16, Error> {
+        let position = block as usize * Size::Block.len() + offset as usize * Size::Word.len();
+        let fits = position + size.len() <= slot.size()
+            || size == Size::Block && slot.is_certificate() && position < slot.size();
         match self {
-            Self::Data if slot.is_private_key() && block == 0 => Ok((slot as u16) << 3),
-            Self::Data if slot.is_certificate() && block <= 2 => {
-                Ok((slot as u16) << 3 | (block as u16) << 8)
+            Self::Data if offset <= 0x07 && fits => {
+                Ok((block as u16) << 8 | (slot as u16) << 3 | offset as u16)
             }
             _ => Err(ErrorKind::BadParam.into()),
         }
@@ -50,6 +76,9 @@ impl Zone {
         if block.leading_zeros() < 3 || offset.leading_zeros() < 4 {
             return Err(ErrorKind::BadParam.into());
         }
+        if self.size() <= block as usize * Size::Block.len() {
+            return Err(ErrorKind::BadParam.into());
+        }
         let block = (block as u16) << 3;
         let offset = (offset & 0x07) as u16;
         let addr = block | offset;
@@ -68,6 +97,17 @@ impl Zone {
     }
 }
 
+/// Access to the OTP zone, as decoded by `ConfigZone::otp_mode`. ATECC608 has
+/// no consumption mode.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum OtpMode {
+    /// Data zone is unlocked. Writes are accepted once the config zone is
+    /// locked, reads are prohibited.
+    Writable,
+    /// Data zone is locked. Contents are fixed for good.
+    ReadOnly,
+}
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub enum Slot {
     /// PrivateKey0x contains 36 bytes, taking 2 block reads.
@@ -102,6 +142,15 @@ impl Slot {
         Self::Certificate09 <= *self
     }
 
+    /// Size of the slot in bytes.
+    pub fn size(&self) -> usize {
+        match self {
+            Self::Data08 => 416,
+            _ if self.is_private_key() => 36,
+            _ => 72,
+        }
+    }
+
     pub fn keys() -> KeysIter {
         KeysIter(0x00..=0x0f)
     }
@@ -135,6 +184,24 @@ impl Iterator for KeysIter {
     }
 }
 
+// Split a word aligned byte range into accesses, reading or writing a block
+// wherever a whole aligned block fits in.
+pub(crate) fn accesses(range: Range<usize>) -> impl Iterator<Item = (Size, usize)> {
+    let mut position = range.start;
+    from_fn(move || {
+        if range.end <= position {
+            return None;
+        }
+        let size = match position % Size::Block.len() {
+            0 if position + Size::Block.len() <= range.end => Size::Block,
+            _ => Size::Word,
+        };
+        let access = (size, position);
+        position += size.len();
+        Some(access)
+    })
+}
+
 pub(crate) struct CertificateRepr(RangeInclusive<usize>);
 
 impl CertificateRepr {
@@ -158,7 +225,7 @@ impl Iterator for CertificateRepr {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::client::Memory;
+    use crate::config::ConfigZone;
     use core::convert::identity;
     use core::iter::repeat;
     use heapless::Vec;
@@ -167,18 +234,12 @@ mod tests {
 
     #[test]
     fn locate_index() {
-        assert_eq!(
-            (0, 5, 0),
-            Zone::locate_index(Memory::<(), ()>::SLOT_CONFIG_INDEX)
-        );
+        assert_eq!((0, 5, 0), Zone::locate_index(ConfigZone::SLOT_CONFIG_INDEX));
         assert_eq!(
             (2, 6, 2),
-            Zone::locate_index(Memory::<(), ()>::CHIP_OPTIONS_INDEX)
-        );
-        assert_eq!(
-            (3, 0, 0),
-            Zone::locate_index(Memory::<(), ()>::KEY_CONFIG_INDEX)
+            Zone::locate_index(ConfigZone::CHIP_OPTIONS_INDEX)
         );
+        assert_eq!((3, 0, 0), Zone::locate_index(ConfigZone::KEY_CONFIG_INDEX));
     }
 
     #[test]
@@ -190,11 +251,50 @@ mod tests {
         }
     }
 
+    #[test]
+    fn get_data_addr() {
+        assert_eq!(
+            0x0108,
+            Data.get_data_addr(PrivateKey01, 1, 0, Size::Word).unwrap()
+        );
+        assert!(Data.get_data_addr(PrivateKey01, 1, 1, Size::Word).is_err());
+        // A block past the first runs over the end of a 36-byte slot.
+        assert!(Data.get_data_addr(PrivateKey01, 1, 0, Size::Block).is_err());
+        assert!(Data.get_slot_addr(PrivateKey01, 1).is_err());
+        assert_eq!(
+            0x0c47,
+            Data.get_data_addr(Data08, 12, 7, Size::Word).unwrap()
+        );
+        assert!(Data.get_slot_addr(Data08, 13).is_err());
+        assert!(Data.get_slot_addr(Certificate09, 3).is_err());
+        assert!(Data.get_data_addr(Certificate09, 2, 2, Size::Word).is_err());
+        assert!(Config.get_data_addr(Data08, 0, 0, Size::Word).is_err());
+    }
+
+    #[test]
+    fn accesses() {
+        let mut accesses = super::accesses(28..104);
+        assert_eq!(accesses.next(), Some((Size::Word, 28)));
+        assert_eq!(accesses.next(), Some((Size::Block, 32)));
+        assert_eq!(accesses.next(), Some((Size::Block, 64)));
+        assert_eq!(accesses.next(), Some((Size::Word, 96)));
+        assert_eq!(accesses.next(), Some((Size::Word, 100)));
+        assert_eq!(accesses.next(), None);
+        assert_eq!(super::accesses(0..36).count(), 2);
+        assert_eq!(
+            Slot::keys().map(|slot| slot.size()).sum::<usize>(),
+            Data.size()
+        );
+    }
+
     #[test]
     fn get_addr() {
         assert_eq!(0x0005, Config.get_addr(0, 5).unwrap());
         assert_eq!(0x0016, Config.get_addr(2, 6).unwrap());
         assert_eq!(0x0018, Config.get_addr(3, 0).unwrap());
+        assert_eq!(0x000f, Otp.get_addr(1, 7).unwrap());
+        assert!(Config.get_addr(4, 0).is_err());
+        assert!(Otp.get_addr(2, 0).is_err());
     }
 
     #[test]
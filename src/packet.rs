@@ -137,9 +137,15 @@ impl Packet {
         &self.opcode
     }
 
+    #[cfg(test)]
     pub(crate) fn buffer(self, buffer: &[u8]) -> &[u8] {
         buffer[self.range].as_ref()
     }
+
+    /// Length of the packet including the preceding word address.
+    pub(crate) fn len(&self) -> usize {
+        self.range.end
+    }
 }
 
 // Is it possible to classify the response into [] | [u8; WORD] | [u8; BLOCK]?
@@ -0,0 +1,1096 @@
+// Command processor of the simulated device. Each handler validates its
+// parameters, checks access policies found in the config zone and writes the
+// response PDU into the output buffer, returning its length.
+use super::sha::ShaContext;
+use super::{Simulator, CONFIG_SIZE, OTP_SIZE};
+use crate::command::{Counter, OpCode};
+use crate::config::{CountMatch, KeyType};
+use crate::error::Status;
+use crate::host::{self, Source, TempKey};
+use crate::packet::CRC16;
+use aes::cipher::{BlockDecrypt, BlockEncrypt, KeyInit};
+use aes::Aes128;
+use core::convert::{TryFrom, TryInto};
+use p256::ecdsa::signature::hazmat::{PrehashSigner, PrehashVerifier};
+use p256::ecdsa::{Signature, SigningKey, VerifyingKey};
+use p256::elliptic_curve::sec1::ToEncodedPoint;
+use p256::{PublicKey, SecretKey};
+use sha2::{Digest as _, Sha256};
+
+// KeyType parameter of Verify in external mode.
+const KEY_TYPE_P256: u16 = 0x04;
+// Private keys are stored with 4 bytes of padding ahead.
+const PRIVATE_KEY_RANGE: core::ops::Range<usize> = 4..36;
+// Key ID designating TempKey instead of a slot.
+const TEMP_KEY_ID: u16 = 0xffff;
+
+// Decoded command packet.
+struct Command<'a> {
+    opcode: u8,
+    mode: u8,
+    param2: u16,
+    data: &'a [u8],
+}
+
+impl Simulator {
+    pub(super) fn execute(&mut self, packet: &[u8], output: &mut [u8]) -> Result<usize, Status> {
+        let length = packet.len();
+        let command = Command {
+            opcode: packet[1],
+            mode: packet[2],
+            param2: u16::from_le_bytes([packet[3], packet[4]]),
+            data: &packet[5..length - 2],
+        };
+
+        // Commands running the SHA engine on their own wipe out the context
+        // of an ongoing calculation.
+        let keeps_sha = [
+            OpCode::Info,
+            OpCode::Read,
+            OpCode::Write,
+            OpCode::Lock,
+            OpCode::Counter,
+            OpCode::Sha,
+        ]
+        .iter()
+        .any(|opcode| *opcode as u8 == command.opcode);
+        if !keeps_sha {
+            self.sha = None;
+        }
+
+        match command.opcode {
+            x if x == OpCode::Info as u8 => self.info(&command, output),
+            x if x == OpCode::Read as u8 => self.read_zone(&command, output),
+            x if x == OpCode::Write as u8 => self.write_zone(&command, output),
+            x if x == OpCode::Lock as u8 => self.lock(&command, output),
+            x if x == OpCode::Random as u8 => self.random_command(&command, output),
+            x if x == OpCode::Nonce as u8 => self.nonce(&command, output),
+            x if x == OpCode::GenDig as u8 => self.gendig(&command, output),
+            x if x == OpCode::DeriveKey as u8 => self.derive_key(&command, output),
+            x if x == OpCode::Mac as u8 => self.mac(&command, output),
+            x if x == OpCode::CheckMac as u8 => self.checkmac(&command, output),
+            x if x == OpCode::GenKey as u8 => self.genkey(&command, output),
+            x if x == OpCode::PrivWrite as u8 => self.privwrite(&command, output),
+            x if x == OpCode::Sign as u8 => self.sign(&command, output),
+            x if x == OpCode::Verify as u8 => self.verify(&command, output),
+            x if x == OpCode::Counter as u8 => self.counter(&command, output),
+            x if x == OpCode::Sha as u8 => self.sha_command(&command, output),
+            x if x == OpCode::Aes as u8 => self.aes(&command, output),
+            x if x == OpCode::Kdf as u8 => self.kdf(&command, output),
+            x if x == OpCode::Ecdh as u8 => self.ecdh(&command, output),
+            _ => Err(Status::Parse),
+        }
+    }
+
+    fn info(&mut self, command: &Command, output: &mut [u8]) -> Result<usize, Status> {
+        match command.mode {
+            0x00 => respond(output, &self.config[4..8]),
+            _ => Err(Status::Parse),
+        }
+    }
+
+    fn read_zone(&mut self, command: &Command, output: &mut [u8]) -> Result<usize, Status> {
+        let size = access_size(command.mode);
+        match command.mode & 0x03 {
+            0x00 => {
+                let start = config_index(command.param2, size);
+                respond(output, &self.config[start..start + size])
+            }
+            0x01 => {
+                if !self.is_data_locked() {
+                    return Err(Status::Execution);
+                }
+                let start = otp_index(command.param2, size)?;
+                respond(output, &self.otp[start..start + size])
+            }
+            0x02 => {
+                let (slot, start) = data_index(command.param2, size);
+                if !self.is_data_locked() {
+                    return Err(Status::Execution);
+                }
+                let slot_config = self.slot_config(slot);
+                if self.key_config(slot).private() {
+                    return Err(Status::Execution);
+                }
+                // Secret slots are only read out encrypted with TempKey, which
+                // must come from GenDig over the read key.
+                let encrypted = slot_config.is_secret();
+                if encrypted
+                    && (!slot_config.encrypt_read()
+                        || size != 32
+                        || self.temp_key.is_none()
+                        || self.gen_dig != Some(slot_config.read_key() as usize))
+                {
+                    return Err(Status::Execution);
+                }
+                let data = self.slot_data(slot);
+                if data.len() <= start {
+                    return Err(Status::Parse);
+                }
+                // Block reads may run past the end of the slot. Surplus bytes
+                // read as zeros.
+                output[..size].iter_mut().for_each(|byte| *byte = 0x00);
+                let end = data.len().min(start + size);
+                output[..end - start].copy_from_slice(&data[start..end]);
+                if let Some(temp_key) = self.temp_key.filter(|_| encrypted) {
+                    output[..size]
+                        .iter_mut()
+                        .zip(temp_key.value().iter())
+                        .for_each(|(byte, key)| *byte ^= key);
+                }
+                Ok(size)
+            }
+            _ => Err(Status::Parse),
+        }
+    }
+
+    fn write_zone(&mut self, command: &Command, output: &mut [u8]) -> Result<usize, Status> {
+        let size = access_size(command.mode);
+        if command.mode & 0x40 != 0x00 {
+            return self.write_encrypted(command, output);
+        }
+        if command.data.len() != size {
+            return Err(Status::Parse);
+        }
+        match command.mode & 0x03 {
+            0x00 => {
+                if self.is_config_locked() {
+                    return Err(Status::Execution);
+                }
+                let start = config_index(command.param2, size);
+                let range = start..start + size;
+                // Serial number, revision and the like are read-only. So are
+                // UserExtra, Selector and lock bytes.
+                if range.start < 16 || (range.start < 88 && 84 < range.end) {
+                    return Err(Status::Execution);
+                }
+                self.config[range].copy_from_slice(command.data);
+                ok(output)
+            }
+            0x01 => {
+                if !self.is_config_locked() || self.is_data_locked() {
+                    return Err(Status::Execution);
+                }
+                let start = otp_index(command.param2, size)?;
+                self.otp[start..start + size].copy_from_slice(command.data);
+                ok(output)
+            }
+            0x02 => {
+                let (slot, start) = data_index(command.param2, size);
+                if !self.is_config_locked() {
+                    return Err(Status::Execution);
+                }
+                if self.is_data_locked() {
+                    let write_config = self.slot_config(slot).write_config();
+                    if self.is_slot_locked(slot)
+                        || self.key_config(slot).private()
+                        || write_config != 0x00
+                    {
+                        return Err(Status::Execution);
+                    }
+                }
+                let data = self.slot_data_mut(slot);
+                if data.len() <= start {
+                    return Err(Status::Parse);
+                }
+                // Surplus bytes of block writes past the end of the slot are
+                // discarded.
+                let end = data.len().min(start + size);
+                data[start..end].copy_from_slice(&command.data[..end - start]);
+                ok(output)
+            }
+            _ => Err(Status::Parse),
+        }
+    }
+
+    // Data encrypted with TempKey, followed by a MAC over the plain text.
+    // TempKey must come from GenDig over the write key of the slot.
+    fn write_encrypted(&mut self, command: &Command, output: &mut [u8]) -> Result<usize, Status> {
+        if command.mode & 0x83 != 0x82 || command.data.len() != 64 {
+            return Err(Status::Parse);
+        }
+        let (slot, start) = data_index(command.param2, 32);
+        let slot_config = self.slot_config(slot);
+        if !self.is_data_locked()
+            || self.is_slot_locked(slot)
+            || self.key_config(slot).private()
+            || slot_config.write_config() & 0x04 == 0x00
+        {
+            return Err(Status::Execution);
+        }
+        let temp_key = self.temp_key()?;
+        if self.gen_dig != Some(slot_config.write_key() as usize) {
+            return Err(Status::Execution);
+        }
+
+        let session_key = temp_key.value();
+        let mut data = [0x00; 32];
+        data.iter_mut()
+            .zip(command.data[..32].iter().zip(session_key))
+            .for_each(|(plain, (cipher, key))| *plain = cipher ^ key);
+        let [lsb, msb] = command.param2.to_le_bytes();
+        let mac = Sha256::new()
+            .chain(session_key)
+            .chain([OpCode::Write as u8, command.mode & !0x40, lsb, msb])
+            .chain(self.serial_bytes())
+            .chain([0x00; 25])
+            .chain(data)
+            .finalize();
+        if mac[..] != command.data[32..] {
+            return Err(Status::CheckmacVerifyFailed);
+        }
+
+        let slot_data = self.slot_data_mut(slot);
+        if slot_data.len() <= start {
+            return Err(Status::Parse);
+        }
+        let end = slot_data.len().min(start + 32);
+        slot_data[start..end].copy_from_slice(&data[..end - start]);
+        ok(output)
+    }
+
+    fn lock(&mut self, command: &Command, output: &mut [u8]) -> Result<usize, Status> {
+        let check_crc = command.mode & 0x80 == 0x00;
+        match command.mode & 0x03 {
+            0x00 => {
+                if self.is_config_locked() {
+                    return Err(Status::Execution);
+                }
+                if check_crc && CRC16.checksum(&self.config) != command.param2 {
+                    return Err(Status::Execution);
+                }
+                self.lock_config();
+                ok(output)
+            }
+            0x01 => {
+                if !self.is_config_locked() || self.is_data_locked() {
+                    return Err(Status::Execution);
+                }
+                if check_crc && self.data_crc() != command.param2 {
+                    return Err(Status::Execution);
+                }
+                self.lock_data();
+                ok(output)
+            }
+            0x02 => {
+                let slot = ((command.mode >> 2) & 0x0f) as usize;
+                if !self.is_data_locked()
+                    || self.is_slot_locked(slot)
+                    || !self.key_config(slot).lockable()
+                {
+                    return Err(Status::Execution);
+                }
+                if check_crc && CRC16.checksum(self.slot_data(slot)) != command.param2 {
+                    return Err(Status::Execution);
+                }
+                self.lock_slot(slot);
+                ok(output)
+            }
+            _ => Err(Status::Parse),
+        }
+    }
+
+    // CRC over the entire data zone followed by the OTP zone.
+    fn data_crc(&self) -> u16 {
+        let mut digest = CRC16.digest();
+        digest.update(&self.data);
+        digest.update(&self.otp);
+        digest.finalize()
+    }
+
+    fn random_command(&mut self, command: &Command, output: &mut [u8]) -> Result<usize, Status> {
+        if command.mode > 0x01 {
+            return Err(Status::Parse);
+        }
+        let random = self.random();
+        respond(output, &random)
+    }
+
+    fn counter(&mut self, command: &Command, output: &mut [u8]) -> Result<usize, Status> {
+        if command.param2 > 0x01 {
+            return Err(Status::Parse);
+        }
+        let index = command.param2 as usize;
+        match command.mode {
+            0x00 => {}
+            0x01 => {
+                let value = self.counters[index];
+                if value >= Counter::MAX_VALUE {
+                    return Err(Status::Execution);
+                }
+                // CountMatch stops Counter0 at the value stored in its key.
+                let count_match = self.count_match();
+                if index == 0 && count_match.enabled() {
+                    let slot = count_match.key() as usize;
+                    let stored = self.slot_data(slot)[..8].try_into();
+                    let limit = stored.ok().and_then(CountMatch::decode_value);
+                    if !matches!(limit, Some(limit) if value < limit) {
+                        return Err(Status::Execution);
+                    }
+                }
+                self.counters[index] = value + 1;
+            }
+            _ => return Err(Status::Parse),
+        }
+        respond(output, &self.counters[index].to_le_bytes())
+    }
+
+    fn nonce(&mut self, command: &Command, output: &mut [u8]) -> Result<usize, Status> {
+        match command.mode & 0x03 {
+            0x00 | 0x01 => {
+                if command.data.len() != 20 {
+                    return Err(Status::Parse);
+                }
+                let rand_out = self.random();
+                let digest = Sha256::new()
+                    .chain(rand_out)
+                    .chain(command.data)
+                    .chain([OpCode::Nonce as u8, command.mode, command.param2 as u8])
+                    .finalize();
+                self.load_temp_key(&digest, Source::Random);
+                respond(output, &rand_out)
+            }
+            0x03 => {
+                let length = if command.mode & 0x20 != 0x00 { 64 } else { 32 };
+                if command.data.len() != length {
+                    return Err(Status::Parse);
+                }
+                match command.mode & 0xc0 {
+                    0x00 => {
+                        self.load_temp_key(&command.data[..length], Source::Input);
+                    }
+                    0x40 => self.message_digest_buffer[..length].copy_from_slice(command.data),
+                    0x80 if length == 32 => self.alternate_key_buffer.copy_from_slice(command.data),
+                    _ => return Err(Status::Parse),
+                }
+                ok(output)
+            }
+            _ => Err(Status::Parse),
+        }
+    }
+
+    // Only keys in the data zone are modelled.
+    fn gendig(&mut self, command: &Command, output: &mut [u8]) -> Result<usize, Status> {
+        if command.mode != 0x02 || !command.data.is_empty() {
+            return Err(Status::Parse);
+        }
+        let slot = key_slot(command.param2)?;
+        let temp_key = self.temp_key()?;
+        if !self.is_data_locked() || self.key_config(slot).private() {
+            return Err(Status::Execution);
+        }
+        let [lsb, msb] = command.param2.to_le_bytes();
+        let digest = Sha256::new()
+            .chain(&self.slot_data(slot)[..32])
+            .chain([OpCode::GenDig as u8, command.mode, lsb, msb])
+            .chain(self.serial_bytes())
+            .chain([0x00; 25])
+            .chain(temp_key.value())
+            .finalize();
+        self.load_temp_key(&digest, temp_key.source());
+        self.gen_dig = Some(slot);
+        ok(output)
+    }
+
+    // WriteConfig of the target enables DeriveKey with bit 1, takes the parent
+    // from WriteKey rather than the target itself with bit 0, and requires an
+    // authorizing MAC with bit 3.
+    fn derive_key(&mut self, command: &Command, output: &mut [u8]) -> Result<usize, Status> {
+        if command.mode & !0x04 != 0x00 {
+            return Err(Status::Parse);
+        }
+        let slot = key_slot(command.param2)?;
+        let slot_config = self.slot_config(slot);
+        let write_config = slot_config.write_config();
+        let mac_length = if write_config & 0x08 != 0x00 { 32 } else { 0 };
+        if command.data.len() != mac_length {
+            return Err(Status::Parse);
+        }
+        let temp_key = self.temp_key()?;
+        let input = temp_key.source() == Source::Input;
+        if !self.is_data_locked()
+            || self.is_slot_locked(slot)
+            || self.key_config(slot).key_type() == KeyType::P256
+            || write_config & 0x02 == 0x00
+            || input != (command.mode & 0x04 != 0x00)
+        {
+            return Err(Status::Execution);
+        }
+
+        let parent_slot = if write_config & 0x01 != 0x00 {
+            slot_config.write_key() as usize
+        } else {
+            slot
+        };
+        let mut parent = [0x00; 32];
+        parent.copy_from_slice(&self.slot_data(parent_slot)[..32]);
+        let [lsb, msb] = command.param2.to_le_bytes();
+        let header = [OpCode::DeriveKey as u8, command.mode, lsb, msb];
+        if mac_length != 0 {
+            let mac = Sha256::new()
+                .chain(parent)
+                .chain(header)
+                .chain(self.serial_bytes())
+                .finalize();
+            if mac[..] != *command.data {
+                return Err(Status::CheckmacVerifyFailed);
+            }
+        }
+        let key = Sha256::new()
+            .chain(parent)
+            .chain(header)
+            .chain(self.serial_bytes())
+            .chain([0x00; 25])
+            .chain(temp_key.value())
+            .finalize();
+        self.slot_data_mut(slot)[..32].copy_from_slice(&key);
+        ok(output)
+    }
+
+    // Keys in the data zone and TempKey, hashed along with optional bytes of
+    // the OTP zone and the serial number.
+    fn mac(&mut self, command: &Command, output: &mut [u8]) -> Result<usize, Status> {
+        let challenge_length = if command.mode & 0x01 != 0x00 { 0 } else { 32 };
+        if command.mode & 0x88 != 0x00 || command.data.len() != challenge_length {
+            return Err(Status::Parse);
+        }
+        let slot = key_slot(command.param2)?;
+        if !self.is_data_locked() || self.key_config(slot).private() {
+            return Err(Status::Execution);
+        }
+        let block1 = self.mac_operand(command.mode & 0x02 != 0x00, &self.slot_data(slot)[..32])?;
+        let block2 = self.mac_operand(command.mode & 0x01 != 0x00, command.data)?;
+        if command.mode & 0x03 != 0x00 && command.mode & 0x04 != 0x00 {
+            let input = self.temp_key()?.source() == Source::Input;
+            if input != (command.mode & 0x01 != 0x00) {
+                return Err(Status::Execution);
+            }
+        }
+
+        let mut otp = [0x00; 11];
+        // Bit 5 is ignored while bit 4 is set.
+        if command.mode & 0x10 != 0x00 {
+            otp.copy_from_slice(&self.otp[..11]);
+        } else if command.mode & 0x20 != 0x00 {
+            otp[..8].copy_from_slice(&self.otp[..8]);
+        }
+        let mut serial = [0x00; 6];
+        if command.mode & 0x40 != 0x00 {
+            serial[..4].copy_from_slice(&self.config[8..12]);
+            serial[4..].copy_from_slice(&self.config[2..4]);
+        }
+        let [lsb, msb] = command.param2.to_le_bytes();
+        let [sn8, sn0, sn1] = self.serial_bytes();
+        let digest = Sha256::new()
+            .chain(block1)
+            .chain(block2)
+            .chain([OpCode::Mac as u8, command.mode, lsb, msb])
+            .chain(otp)
+            .chain([sn8])
+            .chain(&serial[..4])
+            .chain([sn0, sn1])
+            .chain(&serial[4..])
+            .finalize();
+        respond(output, &digest)
+    }
+
+    // Data is ClientChal, ClientResp and 13 bytes of OtherData.
+    fn checkmac(&mut self, command: &Command, output: &mut [u8]) -> Result<usize, Status> {
+        if command.mode & 0xdc != 0x00 || command.data.len() != 77 {
+            return Err(Status::Parse);
+        }
+        let slot = key_slot(command.param2)?;
+        if !self.is_data_locked() || self.key_config(slot).private() {
+            return Err(Status::Execution);
+        }
+        let (challenge, data) = command.data.split_at(32);
+        let (response, other_data) = data.split_at(32);
+        let block1 = self.mac_operand(command.mode & 0x02 != 0x00, &self.slot_data(slot)[..32])?;
+        let block2 = self.mac_operand(command.mode & 0x01 != 0x00, challenge)?;
+        let otp = match command.mode & 0x20 {
+            0x00 => [0x00; 8],
+            _ => self.otp[..8].try_into().unwrap_or_else(|_| unreachable!()),
+        };
+        let [sn8, sn0, sn1] = self.serial_bytes();
+        let digest = Sha256::new()
+            .chain(block1)
+            .chain(block2)
+            .chain(&other_data[..4])
+            .chain(otp)
+            .chain(&other_data[4..7])
+            .chain([sn8])
+            .chain(&other_data[7..11])
+            .chain([sn0, sn1])
+            .chain(&other_data[11..])
+            .finalize();
+        if digest[..] != *response {
+            return Err(Status::CheckmacVerifyFailed);
+        }
+        ok(output)
+    }
+
+    // Either TempKey or the given bytes.
+    fn mac_operand(&self, temp_key: bool, value: &[u8]) -> Result<[u8; 32], Status> {
+        match temp_key {
+            true => self.temp_key().map(|temp_key| *temp_key.value()),
+            false => value.try_into().map_err(|_| Status::Parse),
+        }
+    }
+
+    // TempKey, unless invalid.
+    fn temp_key(&self) -> Result<TempKey, Status> {
+        self.temp_key.ok_or(Status::Execution)
+    }
+
+    // Commands writing their result into TempKey keep its source flag, which
+    // reads random while TempKey is invalid.
+    fn temp_key_source(&self) -> Source {
+        self.temp_key
+            .map_or(Source::Random, |temp_key| temp_key.source())
+    }
+
+    fn genkey(&mut self, command: &Command, output: &mut [u8]) -> Result<usize, Status> {
+        if !self.is_config_locked() {
+            return Err(Status::Execution);
+        }
+        let create = match command.mode {
+            0x04 => true,
+            0x00 => false,
+            _ => return Err(Status::Parse),
+        };
+
+        if command.param2 == TEMP_KEY_ID {
+            if !create {
+                return Err(Status::Parse);
+            }
+            let private_key = self.generate_private_key();
+            self.load_temp_key(&private_key, Source::Random);
+            return respond(output, &public_key(&private_key)?);
+        }
+
+        let slot = key_slot(command.param2)?;
+        let key_config = self.key_config(slot);
+        if !key_config.private() || key_config.key_type() != KeyType::P256 {
+            return Err(Status::Execution);
+        }
+        if create {
+            if self.is_data_locked() {
+                // WriteConfig bit 1 enables GenKey on a locked data zone.
+                let write_config = self.slot_config(slot).write_config();
+                if self.is_slot_locked(slot) || write_config & 0x02 == 0x00 {
+                    return Err(Status::Execution);
+                }
+            }
+            let private_key = self.generate_private_key();
+            self.slot_data_mut(slot)[PRIVATE_KEY_RANGE].copy_from_slice(&private_key);
+        }
+        let public_key = public_key(&self.slot_data(slot)[PRIVATE_KEY_RANGE])?;
+        respond(output, &public_key)
+    }
+
+    fn generate_private_key(&mut self) -> [u8; 32] {
+        loop {
+            let candidate = self.random();
+            if SecretKey::from_slice(&candidate).is_ok() {
+                return candidate;
+            }
+        }
+    }
+
+    fn privwrite(&mut self, command: &Command, output: &mut [u8]) -> Result<usize, Status> {
+        if command.data.len() != 36 + 32 {
+            return Err(Status::Parse);
+        }
+        if !self.is_config_locked() || self.is_data_locked() || command.mode & 0x40 != 0x00 {
+            // Authorized, encrypted writes are not modelled.
+            return Err(Status::Execution);
+        }
+        let slot = key_slot(command.param2)?;
+        let key_config = self.key_config(slot);
+        if !key_config.private() || key_config.key_type() != KeyType::P256 {
+            return Err(Status::Execution);
+        }
+        self.slot_data_mut(slot)[..36].copy_from_slice(&command.data[..36]);
+        ok(output)
+    }
+
+    // Message to be signed or verified. Either the message digest buffer or
+    // TempKey holds it.
+    fn message(&self, mode: u8) -> Result<[u8; 32], Status> {
+        let mut message = [0x00; 32];
+        if mode & 0x20 != 0x00 {
+            message.copy_from_slice(&self.message_digest_buffer[..32]);
+        } else {
+            message.copy_from_slice(self.temp_key()?.value());
+        }
+        Ok(message)
+    }
+
+    fn sign(&mut self, command: &Command, output: &mut [u8]) -> Result<usize, Status> {
+        // Only external messages can be signed.
+        if command.mode & 0x80 == 0x00 {
+            return Err(Status::Parse);
+        }
+        let slot = key_slot(command.param2)?;
+        let key_config = self.key_config(slot);
+        if !key_config.private()
+            || key_config.key_type() != KeyType::P256
+            || self.slot_config(slot).read_key() & 0x01 == 0x00
+        {
+            return Err(Status::Execution);
+        }
+        let message = self.message(command.mode)?;
+        let signing_key = SigningKey::from_slice(&self.slot_data(slot)[PRIVATE_KEY_RANGE])
+            .map_err(|_| Status::Ecc)?;
+        let signature: Signature = signing_key
+            .sign_prehash(&message)
+            .map_err(|_| Status::Ecc)?;
+        self.temp_key = None;
+        respond(output, &signature.to_bytes())
+    }
+
+    fn verify(&mut self, command: &Command, output: &mut [u8]) -> Result<usize, Status> {
+        let mut public_key = [0x00; 64];
+        match command.mode & 0x07 {
+            // External
+            0x02 => {
+                if command.param2 != KEY_TYPE_P256 || command.data.len() != 128 {
+                    return Err(Status::Parse);
+                }
+                public_key.copy_from_slice(&command.data[64..]);
+            }
+            // Stored
+            0x00 => {
+                if command.data.len() != 64 {
+                    return Err(Status::Parse);
+                }
+                let slot = key_slot(command.param2)?;
+                let key_config = self.key_config(slot);
+                if key_config.private() || key_config.key_type() != KeyType::P256 {
+                    return Err(Status::Execution);
+                }
+                public_key = stored_public_key(self.slot_data(slot));
+            }
+            _ => return Err(Status::Parse),
+        }
+        let message = self.message(command.mode)?;
+        let verifying_key =
+            VerifyingKey::from_sec1_bytes(&uncompressed(&public_key)).map_err(|_| Status::Ecc)?;
+        let signature =
+            Signature::from_slice(&command.data[..64]).map_err(|_| Status::CheckmacVerifyFailed)?;
+        verifying_key
+            .verify_prehash(&message, &signature)
+            .map_err(|_| Status::CheckmacVerifyFailed)?;
+        ok(output)
+    }
+
+    fn sha_command(&mut self, command: &Command, output: &mut [u8]) -> Result<usize, Status> {
+        match command.mode & 0x07 {
+            // Start
+            0x00 => {
+                self.sha = Some(ShaContext::new());
+                ok(output)
+            }
+            // Update
+            0x01 => {
+                if command.data.len() != 64 || command.param2 != 64 {
+                    return Err(Status::Parse);
+                }
+                self.sha
+                    .as_mut()
+                    .ok_or(Status::Execution)?
+                    .update(command.data);
+                ok(output)
+            }
+            // End, for both SHA-256 and HMAC/SHA-256. ATECC508 distinguished
+            // the latter with mode 0x05.
+            0x02 | 0x05 => {
+                if command.data.len() > 64 || command.param2 as usize != command.data.len() {
+                    return Err(Status::Parse);
+                }
+                let context = self.sha.take().ok_or(Status::Execution)?;
+                let digest = context.finalize(command.data);
+                match command.mode & 0xc0 {
+                    0x00 => {
+                        self.load_temp_key(&digest, Source::Input);
+                    }
+                    0x40 => self.message_digest_buffer[..32].copy_from_slice(&digest),
+                    0xc0 => {}
+                    _ => return Err(Status::Parse),
+                }
+                respond(output, &digest)
+            }
+            // Public key in a slot
+            0x03 => {
+                let slot = key_slot(command.param2)?;
+                let key_config = self.key_config(slot);
+                if key_config.private() || key_config.key_type() != KeyType::P256 {
+                    return Err(Status::Execution);
+                }
+                let public_key = stored_public_key(self.slot_data(slot));
+                self.sha
+                    .as_mut()
+                    .ok_or(Status::Execution)?
+                    .update(&public_key);
+                ok(output)
+            }
+            // HMAC start
+            0x04 => {
+                let slot = key_slot(command.param2)?;
+                let key = &self.slot_data(slot)[..32];
+                self.sha = Some(ShaContext::hmac(key));
+                ok(output)
+            }
+            // Read context
+            0x06 => {
+                let context = self.sha.as_ref().ok_or(Status::Execution)?;
+                respond(output, &context.save().ok_or(Status::Execution)?)
+            }
+            // Write context
+            0x07 => {
+                if command.param2 as usize != command.data.len() {
+                    return Err(Status::Parse);
+                }
+                self.sha = Some(ShaContext::restore(command.data).ok_or(Status::Parse)?);
+                ok(output)
+            }
+            _ => Err(Status::Parse),
+        }
+    }
+
+    fn aes(&mut self, command: &Command, output: &mut [u8]) -> Result<usize, Status> {
+        if command.data.len() != 16 {
+            return Err(Status::Parse);
+        }
+        if self.config[13] & 0x01 == 0x00 {
+            return Err(Status::Execution);
+        }
+        let key_block = ((command.mode >> 6) & 0x03) as usize * 16;
+        let mut key = [0x00; 16];
+        if command.param2 == TEMP_KEY_ID {
+            let temp_key = self.temp_key()?;
+            if key_block >= 64 {
+                return Err(Status::Execution);
+            }
+            key.copy_from_slice(&temp_key.bytes()[key_block..key_block + 16]);
+        } else {
+            let slot = key_slot(command.param2)?;
+            let data = self.slot_data(slot);
+            if self.key_config(slot).key_type() != KeyType::Aes || data.len() < key_block + 16 {
+                return Err(Status::Execution);
+            }
+            key.copy_from_slice(&data[key_block..key_block + 16]);
+        }
+
+        let cipher = Aes128::new_from_slice(&key).map_err(|_| Status::Execution)?;
+        let mut block: aes::Block = <[u8; 16]>::try_from(command.data)
+            .map_err(|_| Status::Parse)?
+            .into();
+        match command.mode & 0x07 {
+            0x00 => cipher.encrypt_block(&mut block),
+            0x01 => cipher.decrypt_block(&mut block),
+            _ => return Err(Status::Parse),
+        }
+        respond(output, &block)
+    }
+
+    // Mode selects the source in bits 0-1, the target in bits 2-4 and the
+    // algorithm in bits 5-6. Param2 holds the source slot in its lower byte
+    // and the target slot in its upper byte.
+    fn kdf(&mut self, command: &Command, output: &mut [u8]) -> Result<usize, Status> {
+        if command.data.len() < 4 {
+            return Err(Status::Parse);
+        }
+        let (details, message) = command.data.split_at(4);
+        let details = u32::from_le_bytes(details.try_into().map_err(|_| Status::Parse)?);
+        let algorithm = command.mode & 0x60;
+        let message_length = match algorithm {
+            0x20 => 16,
+            // HKDF reading its message from TempKey
+            0x40 if details & 0x03 == 0x01 => 0,
+            _ => (details >> 24) as usize,
+        };
+        if message.len() != message_length {
+            return Err(Status::Parse);
+        }
+        let [source_slot, target_slot] = command.param2.to_le_bytes();
+
+        // HKDF keyed by zeros ignores the source.
+        let zero_key = algorithm == 0x40 && details & 0x04 != 0x00;
+        let temp_key = self
+            .temp_key
+            .map_or([0x00; 64], |temp_key| *temp_key.bytes());
+        let mut source = [0x00; 64];
+        match command.mode & 0x03 {
+            0x00 | 0x01 if self.temp_key.is_none() && !zero_key => return Err(Status::Execution),
+            0x00 => source.copy_from_slice(&temp_key),
+            0x01 => source[..32].copy_from_slice(&temp_key[32..]),
+            0x02 => {
+                let slot = key_slot(source_slot.into())?;
+                if !self.is_data_locked() {
+                    return Err(Status::Execution);
+                }
+                let data = self.slot_data(slot);
+                let length = data.len().min(64);
+                source[..length].copy_from_slice(&data[..length]);
+            }
+            _ => source[..32].copy_from_slice(&self.alternate_key_buffer),
+        }
+
+        let mut result = [0x00; 64];
+        let length = match algorithm {
+            0x00 => {
+                let key_length = (details & 0x03) as usize * 16 + 16;
+                let length = if details & 0x0100 != 0x00 { 64 } else { 32 };
+                prf(&source[..key_length], message, &mut result[..length]);
+                length
+            }
+            0x20 => {
+                let key_index = (details & 0x03) as usize * 16;
+                let cipher = Aes128::new_from_slice(&source[key_index..key_index + 16])
+                    .map_err(|_| Status::Execution)?;
+                let mut block: aes::Block = <[u8; 16]>::try_from(message)
+                    .map_err(|_| Status::Parse)?
+                    .into();
+                cipher.encrypt_block(&mut block);
+                result[..16].copy_from_slice(&block);
+                16
+            }
+            0x40 => {
+                let key = if zero_key {
+                    &[0x00; 32][..]
+                } else {
+                    &source[..32]
+                };
+                let message = match details & 0x03 {
+                    0x01 => &temp_key[..32],
+                    0x02 => message,
+                    _ => return Err(Status::Parse),
+                };
+                result[..32].copy_from_slice(&hmac(key, &[message]));
+                32
+            }
+            _ => return Err(Status::Parse),
+        };
+        let result = &result[..length];
+
+        match (command.mode >> 2) & 0x07 {
+            0x00 => self.load_temp_key(result, self.temp_key_source()),
+            0x01 if length <= 32 => {
+                // The lower half of TempKey is left as is.
+                if let Some(current) = self.temp_key {
+                    let mut value = temp_key;
+                    value[32..32 + length].copy_from_slice(result);
+                    self.temp_key = Some(TempKey::new(&value, current.source()));
+                }
+            }
+            0x02 => {
+                let slot = key_slot(target_slot.into())?;
+                if !self.is_data_locked()
+                    || self.is_slot_locked(slot)
+                    || self.key_config(slot).key_type() == KeyType::P256
+                    || self.slot_data(slot).len() < length
+                {
+                    return Err(Status::Execution);
+                }
+                self.slot_data_mut(slot)[..length].copy_from_slice(result);
+            }
+            0x03 if length <= 32 => self.alternate_key_buffer[..length].copy_from_slice(result),
+            0x04 => return respond(output, result),
+            0x05 => {
+                let io_key = self.io_protection_key().ok_or(Status::Execution)?;
+                let nonce = self.random();
+                let mut encrypted = [0x00; 96];
+                encrypted[..length].copy_from_slice(result);
+                encrypted[length..length + 32].copy_from_slice(&nonce);
+                // Encryption is the same XOR as decryption.
+                host::io_decrypt(&io_key, &nonce, &mut encrypted[..length]);
+                return respond(output, &encrypted[..length + 32]);
+            }
+            _ => return Err(Status::Execution),
+        }
+        ok(output)
+    }
+
+    // ChipOptions enables the IO protection key with bit 1, and names its
+    // slot in bits 12-15.
+    fn io_protection_key(&self) -> Option<[u8; 32]> {
+        let chip_options = self.chip_options();
+        if chip_options & 0x0002 == 0x00 {
+            return None;
+        }
+        let mut key = [0x00; 32];
+        key.copy_from_slice(&self.slot_data((chip_options >> 12) as usize)[..32]);
+        Some(key)
+    }
+
+    fn ecdh(&mut self, command: &Command, output: &mut [u8]) -> Result<usize, Status> {
+        if command.mode & !0x0f != 0x00 || command.data.len() != 64 {
+            return Err(Status::Parse);
+        }
+        let mut private_key = [0x00; 32];
+        let mut read_key = 0x00;
+        let slot = if command.mode & 0x01 == 0x00 {
+            let slot = key_slot(command.param2)?;
+            let key_config = self.key_config(slot);
+            read_key = self.slot_config(slot).read_key();
+            // ReadKey bit 2 permits ECDH on a private key.
+            if !key_config.private()
+                || key_config.key_type() != KeyType::P256
+                || read_key & 0x04 == 0
+            {
+                return Err(Status::Execution);
+            }
+            private_key.copy_from_slice(&self.slot_data(slot)[PRIVATE_KEY_RANGE]);
+            slot
+        } else {
+            private_key.copy_from_slice(self.temp_key()?.value());
+            // With the key in TempKey, param2 names the target slot instead.
+            command.param2 as usize
+        };
+        let shared_secret = self.shared_secret(&private_key, command.data)?;
+
+        match command.mode & 0x0c {
+            // ReadKey bit 3 redirects the secret into the slot N|1.
+            0x00 if command.mode & 0x01 == 0x00 && read_key & 0x08 != 0x00 => {
+                self.slot_data_mut(slot | 0x01)[..32].copy_from_slice(&shared_secret)
+            }
+            0x00 if command.mode & 0x01 == 0x00 => return respond(output, &shared_secret),
+            0x04 => {
+                let target = if command.mode & 0x01 == 0x00 {
+                    slot | 0x01
+                } else {
+                    key_slot(command.param2)?
+                };
+                if self.is_slot_locked(target)
+                    || self.key_config(target).key_type() == KeyType::P256
+                {
+                    return Err(Status::Execution);
+                }
+                self.slot_data_mut(target)[..32].copy_from_slice(&shared_secret);
+            }
+            0x08 => self.load_temp_key(&shared_secret, self.temp_key_source()),
+            0x0c if command.mode & 0x02 == 0x00 => return respond(output, &shared_secret),
+            0x0c => {
+                let io_key = self.io_protection_key().ok_or(Status::Execution)?;
+                let nonce = self.random();
+                let mut encrypted = [0x00; 64];
+                encrypted[..32].copy_from_slice(&shared_secret);
+                encrypted[32..].copy_from_slice(&nonce);
+                host::io_decrypt(&io_key, &nonce, &mut encrypted[..32]);
+                return respond(output, &encrypted);
+            }
+            _ => return Err(Status::Parse),
+        }
+        ok(output)
+    }
+
+    fn shared_secret(&self, private_key: &[u8], public_key: &[u8]) -> Result<[u8; 32], Status> {
+        let secret_key = SecretKey::from_slice(private_key).map_err(|_| Status::Ecc)?;
+        let public_key =
+            PublicKey::from_sec1_bytes(&uncompressed(public_key)).map_err(|_| Status::Ecc)?;
+        let shared =
+            p256::ecdh::diffie_hellman(secret_key.to_nonzero_scalar(), public_key.as_affine());
+        let mut secret = [0x00; 32];
+        secret.copy_from_slice(shared.raw_secret_bytes());
+        Ok(secret)
+    }
+}
+
+// HMAC/SHA-256 over the concatenation of `parts`. Keys are at most 64 bytes
+// long.
+fn hmac(key: &[u8], parts: &[&[u8]]) -> [u8; 32] {
+    let mut block = [0x00; 64];
+    block[..key.len()].copy_from_slice(key);
+    let mut inner = Sha256::new().chain(block.map(|byte| byte ^ 0x36));
+    for part in parts {
+        inner.update(part);
+    }
+    Sha256::new()
+        .chain(block.map(|byte| byte ^ 0x5c))
+        .chain(inner.finalize())
+        .finalize()
+        .into()
+}
+
+// P_SHA256 of TLS 1.2, whose seed is the label followed by the seed proper.
+fn prf(secret: &[u8], seed: &[u8], output: &mut [u8]) {
+    let mut a = hmac(secret, &[seed]);
+    for chunk in output.chunks_mut(32) {
+        let block = hmac(secret, &[&a, seed]);
+        chunk.copy_from_slice(&block[..chunk.len()]);
+        a = hmac(secret, &[&a]);
+    }
+}
+
+fn ok(output: &mut [u8]) -> Result<usize, Status> {
+    output[0] = 0x00;
+    Ok(1)
+}
+
+fn respond(output: &mut [u8], pdu: &[u8]) -> Result<usize, Status> {
+    output[..pdu.len()].copy_from_slice(pdu);
+    Ok(pdu.len())
+}
+
+fn access_size(mode: u8) -> usize {
+    if mode & 0x80 != 0x00 {
+        32
+    } else {
+        4
+    }
+}
+
+fn config_index(param2: u16, size: usize) -> usize {
+    let block = ((param2 >> 3) & 0x03) as usize;
+    let offset = (param2 & 0x07) as usize;
+    let index = block * 32 + if size == 4 { offset * 4 } else { 0 };
+    debug_assert!(index + size <= CONFIG_SIZE);
+    index
+}
+
+fn otp_index(param2: u16, size: usize) -> Result<usize, Status> {
+    let block = ((param2 >> 3) & 0x03) as usize;
+    let offset = (param2 & 0x07) as usize;
+    let index = block * 32 + if size == 4 { offset * 4 } else { 0 };
+    if index + size > OTP_SIZE {
+        return Err(Status::Parse);
+    }
+    Ok(index)
+}
+
+// Slot number and byte offset within the slot.
+fn data_index(param2: u16, size: usize) -> (usize, usize) {
+    let slot = ((param2 >> 3) & 0x0f) as usize;
+    let block = (param2 >> 8) as usize;
+    let offset = (param2 & 0x07) as usize;
+    (slot, block * 32 + if size == 4 { offset * 4 } else { 0 })
+}
+
+fn key_slot(param2: u16) -> Result<usize, Status> {
+    if param2 > 0x0f {
+        return Err(Status::Parse);
+    }
+    Ok(param2 as usize)
+}
+
+fn public_key(private_key: &[u8]) -> Result<[u8; 64], Status> {
+    let secret_key = SecretKey::from_slice(private_key).map_err(|_| Status::Ecc)?;
+    let point = secret_key.public_key().to_encoded_point(false);
+    point.as_bytes()[1..].try_into().map_err(|_| Status::Ecc)
+}
+
+// Public keys are stored in slots with 4 bytes of padding ahead of each of X
+// and Y.
+fn stored_public_key(data: &[u8]) -> [u8; 64] {
+    let mut public_key = [0x00; 64];
+    public_key[..32].copy_from_slice(&data[4..36]);
+    public_key[32..].copy_from_slice(&data[40..72]);
+    public_key
+}
+
+fn uncompressed(public_key: &[u8]) -> [u8; 65] {
+    let mut sec1 = [0x04; 65];
+    sec1[1..].copy_from_slice(public_key);
+    sec1
+}
@@ -0,0 +1,495 @@
+// Software model of ATECC608 sitting behind an I2C bus, or a UART wired
+// single-wire. It speaks the same datalink protocol as the real device, i.e.
+// wake token, word addresses or flags and CRC16 protected frames, and executes
+// commands against in-memory config, OTP and data zones. Intended for
+// exercising `AtCaClient` without silicon.
+//
+// Randomness is derived from a deterministic generator so that test runs are
+// reproducible. Execution is instantaneous; no timing is modelled.
+mod command;
+mod sha;
+mod swi;
+
+use crate::command::CounterId;
+use crate::config::{ConfigZone, CountMatch, KeyConfig, SlotConfig};
+use crate::datalink::{Transaction, ADDRESS};
+use crate::error::Status;
+use crate::host::{Source, TempKey};
+use crate::memory::Slot;
+use crate::packet::CRC16;
+use core::convert::{TryFrom, TryInto};
+use core::ops::Range;
+use embedded_hal::delay::DelayNs;
+use embedded_hal::i2c::{self, ErrorKind, NoAcknowledgeSource, Operation};
+use sha::ShaContext;
+use sha2::{Digest as _, Sha256};
+
+pub use swi::Uart;
+
+pub(crate) const CONFIG_SIZE: usize = 128;
+pub(crate) const OTP_SIZE: usize = 64;
+const DATA_SIZE: usize = 8 * 36 + 416 + 7 * 72;
+const IO_BUFFER_SIZE: usize = 192;
+
+const WAKE_RESPONSE: [u8; 4] = [0x04, 0x11, 0x33, 0x43];
+const REVISION: [u8; 4] = [0x00, 0x00, 0x60, 0x02];
+const SERIAL_NUMBER: [u8; 9] = [0x01, 0x23, 0x4a, 0x2f, 0x71, 0x08, 0x9c, 0x55, 0xee];
+
+const UNLOCKED: u8 = 0x55;
+const LOCKED: u8 = 0x00;
+
+/// A delay provider doing nothing. The simulator completes every command
+/// immediately, hence there is no point in waiting.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoDelay;
+
+impl DelayNs for NoDelay {
+    fn delay_ns(&mut self, _ns: u32) {}
+}
+
+#[cfg(feature = "async")]
+impl embedded_hal_async::delay::DelayNs for NoDelay {
+    async fn delay_ns(&mut self, _ns: u32) {}
+}
+
+/// The only failure the simulated bus reports. Either the address does not
+/// match or the device is asleep.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Nack;
+
+impl i2c::Error for Nack {
+    fn kind(&self) -> ErrorKind {
+        ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Power {
+    Sleep,
+    Idle,
+    Awake,
+}
+
+// Deterministic random number generator. SHA-256 over a seed and a counter.
+#[derive(Clone, Debug)]
+struct Drbg {
+    seed: [u8; 32],
+    counter: u64,
+}
+
+impl Drbg {
+    fn generate(&mut self) -> [u8; 32] {
+        self.counter += 1;
+        Sha256::new()
+            .chain(self.seed)
+            .chain(self.counter.to_le_bytes())
+            .finalize()
+            .into()
+    }
+}
+
+// Buffer holding the last response. Reads advance the cursor, the Reset word
+// address rewinds it.
+#[derive(Clone, Debug)]
+struct Io {
+    buffer: [u8; IO_BUFFER_SIZE],
+    length: usize,
+    cursor: usize,
+}
+
+impl Io {
+    fn load(&mut self, bytes: &[u8]) {
+        self.buffer[..bytes.len()].copy_from_slice(bytes);
+        self.length = bytes.len();
+        self.cursor = 0;
+    }
+
+    fn respond(&mut self, result: Result<&[u8], Status>) {
+        let status;
+        let pdu = match result {
+            Ok(pdu) => pdu,
+            Err(error) => {
+                status = [error as u8];
+                &status
+            }
+        };
+        let length = pdu.len() + 3;
+        self.buffer[0] = length as u8;
+        self.buffer[1..length - 2].copy_from_slice(pdu);
+        let crc = CRC16.checksum(&self.buffer[..length - 2]);
+        self.buffer[length - 2..length].copy_from_slice(&crc.to_le_bytes());
+        self.length = length;
+        self.cursor = 0;
+    }
+
+    fn read(&mut self, buffer: &mut [u8]) {
+        for byte in buffer.iter_mut() {
+            *byte = if self.cursor < self.length {
+                self.buffer[self.cursor]
+            } else {
+                0xff
+            };
+            self.cursor += 1;
+        }
+    }
+}
+
+/// Simulated ATECC608 implementing `embedded_hal::i2c::I2c`.
+#[derive(Clone, Debug)]
+pub struct Simulator {
+    address: u8,
+    power: Power,
+    io: Io,
+    pub(crate) config: [u8; CONFIG_SIZE],
+    pub(crate) otp: [u8; OTP_SIZE],
+    pub(crate) data: [u8; DATA_SIZE],
+    // TempKey is invalid until a command loads it.
+    pub(crate) temp_key: Option<TempKey>,
+    // Slot whose key GenDig combined into TempKey, if any.
+    pub(crate) gen_dig: Option<usize>,
+    pub(crate) message_digest_buffer: [u8; 64],
+    pub(crate) alternate_key_buffer: [u8; 32],
+    pub(crate) sha: Option<ShaContext>,
+    // Counter values, taken from the config zone as it is locked.
+    pub(crate) counters: [u32; 2],
+    drbg: Drbg,
+    // Number of polls a command keeps the device busy for, and how many of
+    // them are left for the command in progress.
+    busy_polls: usize,
+    busy: usize,
+    wakes: usize,
+}
+
+impl Default for Simulator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Simulator {
+    /// A device in its factory state, with config and data zones unlocked.
+    pub fn new() -> Self {
+        let mut config = [0x00; CONFIG_SIZE];
+        config[0..4].copy_from_slice(&SERIAL_NUMBER[0..4]);
+        config[4..8].copy_from_slice(&REVISION);
+        config[8..13].copy_from_slice(&SERIAL_NUMBER[4..9]);
+        // AES_Enable
+        config[13] = 0x01;
+        // I2C_Enable
+        config[14] = 0x01;
+        // I2C_Address
+        config[ConfigZone::I2C_ADDRESS_INDEX] = ADDRESS << 1;
+        // Counter0 and Counter1 hold their initial value.
+        for counter in config[52..68].chunks_mut(8) {
+            counter.copy_from_slice(&ConfigZone::COUNTER_RESET);
+        }
+        config[ConfigZone::LOCK_VALUE_INDEX] = UNLOCKED;
+        config[ConfigZone::LOCK_CONFIG_INDEX] = UNLOCKED;
+        config[ConfigZone::SLOT_LOCKED_INDEX] = 0xff;
+        config[ConfigZone::SLOT_LOCKED_INDEX + 1] = 0xff;
+
+        Self {
+            address: ADDRESS,
+            power: Power::Sleep,
+            io: Io {
+                buffer: [0x00; IO_BUFFER_SIZE],
+                length: 0,
+                cursor: 0,
+            },
+            config,
+            otp: [0x00; OTP_SIZE],
+            data: [0x00; DATA_SIZE],
+            temp_key: None,
+            gen_dig: None,
+            message_digest_buffer: [0x00; 64],
+            alternate_key_buffer: [0x00; 32],
+            sha: None,
+            counters: [0; 2],
+            drbg: Drbg {
+                seed: [0x00; 32],
+                counter: 0,
+            },
+            busy_polls: 0,
+            busy: 0,
+            wakes: 0,
+        }
+    }
+
+    /// Answer on a 7-bit I2C address other than the default one, as a part
+    /// shipped with a pre-programmed I2C_Address would.
+    pub fn with_address(mut self, address: u8) -> Self {
+        self.address = address;
+        self.config[ConfigZone::I2C_ADDRESS_INDEX] = address << 1;
+        self
+    }
+
+    /// Reseed the random number generator. Devices with distinct seeds create
+    /// distinct keys.
+    pub fn with_seed(mut self, seed: [u8; 32]) -> Self {
+        self.drbg = Drbg { seed, counter: 0 };
+        self
+    }
+
+    /// Keep the device busy, i.e. not acknowledging its address, for the
+    /// given number of transfers after each command.
+    pub fn with_busy_polls(mut self, busy_polls: usize) -> Self {
+        self.busy_polls = busy_polls;
+        self
+    }
+
+    /// Replace the whole config zone, including the lock bytes.
+    pub fn with_config(mut self, config: [u8; CONFIG_SIZE]) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn config_zone(&self) -> &[u8; CONFIG_SIZE] {
+        &self.config
+    }
+
+    pub fn otp_zone(&self) -> &[u8; OTP_SIZE] {
+        &self.otp
+    }
+
+    /// Raw contents of a slot in the data zone.
+    pub fn slot(&self, slot: Slot) -> &[u8] {
+        &self.data[slot_range(slot as usize)]
+    }
+
+    /// 7-bit I2C address the device currently answers on.
+    pub fn address(&self) -> u8 {
+        self.address
+    }
+
+    pub fn is_awake(&self) -> bool {
+        self.power == Power::Awake
+    }
+
+    pub fn is_asleep(&self) -> bool {
+        self.power == Power::Sleep
+    }
+
+    /// Number of wake tokens received so far.
+    pub fn wakes(&self) -> usize {
+        self.wakes
+    }
+
+    pub(crate) fn is_config_locked(&self) -> bool {
+        self.config[ConfigZone::LOCK_CONFIG_INDEX] != UNLOCKED
+    }
+
+    pub(crate) fn is_data_locked(&self) -> bool {
+        self.config[ConfigZone::LOCK_VALUE_INDEX] != UNLOCKED
+    }
+
+    // Counters start from their initial values in the configuration zone.
+    pub(crate) fn lock_config(&mut self) {
+        self.config[ConfigZone::LOCK_CONFIG_INDEX] = LOCKED;
+        let config = ConfigZone::try_from(&self.config[..]).ok();
+        for (counter, counter_id) in self
+            .counters
+            .iter_mut()
+            .zip([CounterId::Counter0, CounterId::Counter1])
+        {
+            *counter = config
+                .and_then(|config| config.counter(counter_id))
+                .unwrap_or(0);
+        }
+    }
+
+    pub(crate) fn lock_data(&mut self) {
+        self.config[ConfigZone::LOCK_VALUE_INDEX] = LOCKED;
+    }
+
+    pub(crate) fn is_slot_locked(&self, slot: usize) -> bool {
+        let slot_locked = u16::from_le_bytes([
+            self.config[ConfigZone::SLOT_LOCKED_INDEX],
+            self.config[ConfigZone::SLOT_LOCKED_INDEX + 1],
+        ]);
+        slot_locked & (0x01 << slot) == 0x00
+    }
+
+    pub(crate) fn lock_slot(&mut self, slot: usize) {
+        self.config[ConfigZone::SLOT_LOCKED_INDEX + slot / 8] &= !(0x01 << (slot % 8));
+    }
+
+    pub(crate) fn slot_config(&self, slot: usize) -> SlotConfig {
+        let index = ConfigZone::SLOT_CONFIG_INDEX + slot * 2;
+        SlotConfig::from_le_bytes([self.config[index], self.config[index + 1]])
+    }
+
+    pub(crate) fn chip_options(&self) -> u16 {
+        u16::from_le_bytes([
+            self.config[ConfigZone::CHIP_OPTIONS_INDEX],
+            self.config[ConfigZone::CHIP_OPTIONS_INDEX + 1],
+        ])
+    }
+
+    pub(crate) fn count_match(&self) -> CountMatch {
+        self.config[ConfigZone::COUNT_MATCH_INDEX].into()
+    }
+
+    pub(crate) fn key_config(&self, slot: usize) -> KeyConfig {
+        let index = ConfigZone::KEY_CONFIG_INDEX + slot * 2;
+        KeyConfig::from_le_bytes([self.config[index], self.config[index + 1]])
+    }
+
+    pub(crate) fn slot_data(&self, slot: usize) -> &[u8] {
+        &self.data[slot_range(slot)]
+    }
+
+    pub(crate) fn slot_data_mut(&mut self, slot: usize) -> &mut [u8] {
+        &mut self.data[slot_range(slot)]
+    }
+
+    /// SN[8], SN[0] and SN[1], which authenticated commands mix into digests.
+    pub(crate) fn serial_bytes(&self) -> [u8; 3] {
+        [self.config[12], self.config[0], self.config[1]]
+    }
+
+    pub(crate) fn random(&mut self) -> [u8; 32] {
+        if !self.is_config_locked() {
+            // Prior to locking the config zone, the RNG yields a fixed pattern.
+            let mut pattern = [0x00; 32];
+            pattern
+                .chunks_mut(4)
+                .for_each(|chunk| chunk.copy_from_slice(&[0xff, 0xff, 0x00, 0x00]));
+            return pattern;
+        }
+        self.drbg.generate()
+    }
+
+    // Loading TempKey clears the GenDig state.
+    pub(crate) fn load_temp_key(&mut self, value: &[u8], source: Source) {
+        self.temp_key = Some(TempKey::new(value, source));
+        self.gen_dig = None;
+    }
+
+    // Volatile states are lost on sleep. Idle retains them.
+    fn sleep(&mut self) {
+        self.power = Power::Sleep;
+        self.temp_key = None;
+        self.gen_dig = None;
+        self.message_digest_buffer = [0x00; 64];
+        self.alternate_key_buffer = [0x00; 32];
+        self.sha = None;
+    }
+
+    // Any activity on the bus is a wake token, whichever address it is sent
+    // to. The device is not listening yet, hence no acknowledgement.
+    fn wake(&mut self) -> Nack {
+        self.power = Power::Awake;
+        self.wakes += 1;
+        self.busy = 0;
+        self.io.load(&WAKE_RESPONSE);
+        // I2C_Address comes into effect once the config zone is locked.
+        if self.is_config_locked() {
+            self.address = self.config[ConfigZone::I2C_ADDRESS_INDEX] >> 1;
+        }
+        Nack
+    }
+
+    fn handle_write(&mut self, bytes: &[u8]) -> Result<(), Nack> {
+        let (word_address, packet) = match bytes.split_first() {
+            Some(split) => split,
+            None => return Ok(()),
+        };
+        match *word_address {
+            x if x == Transaction::Reset as u8 => self.io.cursor = 0,
+            x if x == Transaction::Sleep as u8 => self.sleep(),
+            x if x == Transaction::Idle as u8 => self.power = Power::Idle,
+            x if x == Transaction::Command as u8 => {
+                let mut output = [0x00; IO_BUFFER_SIZE];
+                let result = self
+                    .receive(packet)
+                    .and_then(|()| self.execute(packet, &mut output))
+                    .map(|length| &output[..length]);
+                self.io.respond(result);
+                self.busy = self.busy_polls;
+            }
+            _ => return Err(Nack),
+        }
+        Ok(())
+    }
+
+    fn handle_read(&mut self, buffer: &mut [u8]) -> Result<(), Nack> {
+        if self.power != Power::Awake {
+            return Err(Nack);
+        }
+        self.io.read(buffer);
+        Ok(())
+    }
+
+    // Validate framing and CRC of a command packet.
+    fn receive(&self, packet: &[u8]) -> Result<(), Status> {
+        let length = *packet.first().ok_or(Status::Parse)? as usize;
+        if length < 7 || packet.len() != length {
+            return Err(Status::Parse);
+        }
+        let (payload, crc) = packet.split_at(length - 2);
+        let crc = crc
+            .try_into()
+            .map(u16::from_le_bytes)
+            .unwrap_or_else(|_| unreachable!());
+        if crc != CRC16.checksum(payload) {
+            return Err(Status::Crc);
+        }
+        Ok(())
+    }
+}
+
+impl i2c::ErrorType for Simulator {
+    type Error = Nack;
+}
+
+impl i2c::I2c for Simulator {
+    fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        if self.power != Power::Awake {
+            return Err(self.wake());
+        }
+        if address != self.address {
+            return Err(Nack);
+        }
+        if self.busy > 0 {
+            self.busy -= 1;
+            return Err(Nack);
+        }
+        operations
+            .iter_mut()
+            .try_for_each(|operation| match operation {
+                Operation::Write(bytes) => self.handle_write(bytes),
+                Operation::Read(buffer) => self.handle_read(buffer),
+            })
+    }
+}
+
+#[cfg(feature = "async")]
+impl embedded_hal_async::i2c::I2c for Simulator {
+    async fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        i2c::I2c::transaction(self, address, operations)
+    }
+}
+
+/// Byte range of a slot within the data zone. Slots 0-7 hold 36 bytes, slot 8
+/// holds 416 bytes and slots 9-15 hold 72 bytes.
+pub(crate) fn slot_range(slot: usize) -> Range<usize> {
+    match slot {
+        0..=7 => slot * 36..slot * 36 + 36,
+        8 => 288..288 + 416,
+        _ => {
+            let start = 704 + (slot - 9) * 72;
+            start..start + 72
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,126 @@
+// SHA-256 engine of the simulated device. The state is kept in its raw form,
+// i.e. eight working variables plus a block counter, because the device only
+// ever consumes whole 64-byte blocks until the final one.
+use core::convert::TryInto;
+use sha2::compress256;
+
+const INITIAL_STATE: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+/// Size of the context read out and written back by the host.
+pub(crate) const CONTEXT_SIZE: usize = 40;
+const IPAD: u8 = 0x36;
+const OPAD: u8 = 0x5c;
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct ShaContext {
+    state: [u32; 8],
+    blocks: u64,
+    // Key of an ongoing HMAC calculation.
+    hmac_key: Option<[u8; 32]>,
+}
+
+impl ShaContext {
+    pub(crate) fn new() -> Self {
+        Self {
+            state: INITIAL_STATE,
+            blocks: 0,
+            hmac_key: None,
+        }
+    }
+
+    pub(crate) fn hmac(key: &[u8]) -> Self {
+        let mut hmac_key = [0x00; 32];
+        hmac_key.copy_from_slice(key);
+        let mut context = Self::new();
+        context.update(&pad(&hmac_key, IPAD));
+        context.hmac_key = Some(hmac_key);
+        context
+    }
+
+    /// Context as read out by the host: the state words in big endian,
+    /// followed by the block counter in little endian. HMAC keys are not
+    /// exposed, so HMAC calculations have no context to save.
+    pub(crate) fn save(&self) -> Option<[u8; CONTEXT_SIZE]> {
+        if self.hmac_key.is_some() {
+            return None;
+        }
+        let mut bytes = [0x00; CONTEXT_SIZE];
+        let (state, blocks) = bytes.split_at_mut(32);
+        state
+            .chunks_mut(4)
+            .zip(self.state.iter())
+            .for_each(|(dst, word)| dst.copy_from_slice(&word.to_be_bytes()));
+        blocks.copy_from_slice(&self.blocks.to_le_bytes());
+        Some(bytes)
+    }
+
+    pub(crate) fn restore(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != CONTEXT_SIZE {
+            return None;
+        }
+        let (state_bytes, blocks) = bytes.split_at(32);
+        let mut context = Self::new();
+        context
+            .state
+            .iter_mut()
+            .zip(state_bytes.chunks(4))
+            .for_each(|(word, src)| {
+                *word = u32::from_be_bytes(src.try_into().unwrap_or_else(|_| unreachable!()))
+            });
+        context.blocks = u64::from_le_bytes(blocks.try_into().ok()?);
+        Some(context)
+    }
+
+    /// Input must be exactly 64 bytes.
+    pub(crate) fn update(&mut self, block: &[u8]) {
+        compress(&mut self.state, block);
+        self.blocks += 1;
+    }
+
+    /// Input must not exceed 64 bytes.
+    pub(crate) fn finalize(mut self, remainder: &[u8]) -> [u8; 32] {
+        let digest = self.finish(remainder);
+        match self.hmac_key {
+            None => digest,
+            Some(key) => {
+                let mut outer = Self::new();
+                outer.update(&pad(&key, OPAD));
+                outer.finish(&digest)
+            }
+        }
+    }
+
+    fn finish(&mut self, remainder: &[u8]) -> [u8; 32] {
+        let bit_length = (self.blocks * 64 + remainder.len() as u64) * 8;
+        let mut last = [0x00; 128];
+        last[..remainder.len()].copy_from_slice(remainder);
+        last[remainder.len()] = 0x80;
+        let length = if remainder.len() < 56 { 64 } else { 128 };
+        last[length - 8..length].copy_from_slice(&bit_length.to_be_bytes());
+        last[..length]
+            .chunks(64)
+            .for_each(|block| compress(&mut self.state, block));
+
+        let mut digest = [0x00; 32];
+        digest
+            .chunks_mut(4)
+            .zip(self.state.iter())
+            .for_each(|(dst, word)| dst.copy_from_slice(&word.to_be_bytes()));
+        digest
+    }
+}
+
+fn compress(state: &mut [u32; 8], block: &[u8]) {
+    let block: [u8; 64] = block.try_into().unwrap_or_else(|_| unreachable!());
+    compress256(state, &[block.into()]);
+}
+
+fn pad(key: &[u8; 32], value: u8) -> [u8; 64] {
+    let mut block = [value; 64];
+    block
+        .iter_mut()
+        .zip(key.iter())
+        .for_each(|(dst, key)| *dst ^= key);
+    block
+}
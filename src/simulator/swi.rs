@@ -0,0 +1,154 @@
+// UART end of a simulated device wired single-wire. Frames are decoded into
+// bits and bits into flagged transfers, which drive the same state machine as
+// I2C transfers do.
+use super::{Simulator, IO_BUFFER_SIZE};
+use crate::datalink::swi::{decode, encode, Flag, BAUD_RATE, WAKE_BAUD_RATE};
+use crate::datalink::{BaudRate, Transaction};
+use core::convert::{Infallible, TryInto};
+use embedded_io::{ErrorType, Read, ReadReady, Write};
+use heapless::Vec;
+
+/// Simulated ATECC608 behind a UART, implementing `embedded_io` traits.
+#[derive(Clone, Debug)]
+pub struct Uart {
+    simulator: Simulator,
+    baud_rate: u32,
+    // Frames of the byte being received.
+    frames: Vec<u8, 8>,
+    // Bytes of the transfer being received.
+    incoming: Vec<u8, IO_BUFFER_SIZE>,
+    // Bytes being transmitted, and the position of the next frame.
+    outgoing: Vec<u8, IO_BUFFER_SIZE>,
+    cursor: usize,
+}
+
+impl Uart {
+    pub fn new(simulator: Simulator) -> Self {
+        Self {
+            simulator,
+            baud_rate: BAUD_RATE,
+            frames: Vec::new(),
+            incoming: Vec::new(),
+            outgoing: Vec::new(),
+            cursor: 0,
+        }
+    }
+
+    pub fn simulator(&self) -> &Simulator {
+        &self.simulator
+    }
+
+    fn receive_frame(&mut self, frame: u8) {
+        if self.baud_rate != BAUD_RATE {
+            // Only a null byte sent slow enough holds the line low long
+            // enough to be a wake token.
+            if frame == 0x00 && self.baud_rate <= WAKE_BAUD_RATE && !self.simulator.is_awake() {
+                self.simulator.wake();
+            }
+            return;
+        }
+        if !self.simulator.is_awake() {
+            return;
+        }
+        self.frames.push(frame).ok();
+        if self.frames.is_full() {
+            let frames = self.frames.as_slice().try_into().unwrap_or([0x00; 8]);
+            self.frames.clear();
+            self.receive_byte(decode(&frames));
+        }
+    }
+
+    fn receive_byte(&mut self, byte: u8) {
+        if self.incoming.push(byte).is_err() {
+            self.incoming.clear();
+            return;
+        }
+        let complete = match self.incoming[0] {
+            x if x == Flag::Command as u8 => {
+                self.incoming.len() > 1 && self.incoming.len() > self.incoming[1] as usize
+            }
+            _ => true,
+        };
+        if !complete {
+            return;
+        }
+
+        self.outgoing.clear();
+        self.cursor = 0;
+        let flag = self.incoming[0];
+        match flag {
+            x if x == Flag::Command as u8 => {
+                self.incoming[0] = Transaction::Command as u8;
+                self.simulator.handle_write(&self.incoming).ok();
+            }
+            x if x == Flag::Transmit as u8 => self.transmit(),
+            x if x == Flag::Idle as u8 => {
+                self.simulator.handle_write(&[Transaction::Idle as u8]).ok();
+            }
+            x if x == Flag::Sleep as u8 => {
+                self.simulator
+                    .handle_write(&[Transaction::Sleep as u8])
+                    .ok();
+            }
+            _ => {}
+        }
+        self.incoming.clear();
+    }
+
+    // Busy devices do not respond.
+    fn transmit(&mut self) {
+        if self.simulator.busy > 0 {
+            self.simulator.busy -= 1;
+            return;
+        }
+        let io = &self.simulator.io;
+        self.outgoing
+            .extend_from_slice(&io.buffer[..io.length])
+            .unwrap_or_else(|()| unreachable!());
+    }
+}
+
+impl ErrorType for Uart {
+    type Error = Infallible;
+}
+
+impl Write for Uart {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        buf.iter().for_each(|frame| self.receive_frame(*frame));
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+// Nothing to read yet reads as the end of stream.
+impl Read for Uart {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        let mut count = 0;
+        for frame in buf.iter_mut() {
+            let byte = match self.outgoing.get(self.cursor / 8) {
+                Some(byte) => *byte,
+                None => break,
+            };
+            *frame = encode(byte)[self.cursor % 8];
+            self.cursor += 1;
+            count += 1;
+        }
+        Ok(count)
+    }
+}
+
+impl ReadReady for Uart {
+    fn read_ready(&mut self) -> Result<bool, Self::Error> {
+        Ok(self.cursor < self.outgoing.len() * 8)
+    }
+}
+
+impl BaudRate for Uart {
+    fn set_baud_rate(&mut self, baud_rate: u32) -> Result<(), Self::Error> {
+        self.baud_rate = baud_rate;
+        Ok(())
+    }
+}
@@ -0,0 +1,156 @@
+use super::*;
+use crate::host;
+use crate::{KdfMessage, KdfSource, KdfTarget, NonceTarget};
+
+#[test]
+fn aes() {
+    let mut sim = Simulator::new();
+    // KeyType AES for the slot, which can be written in clear.
+    let index = ConfigZone::KEY_CONFIG_INDEX + AES_SLOT as usize * 2;
+    sim.config[index] = 0x06 << 2;
+    let mut atca = AtCaClient::new(&mut sim, NoDelay);
+    atca.memory().lock(Zone::Config).unwrap();
+
+    // FIPS-197, Appendix C.1
+    let key = [
+        0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e,
+        0x0f,
+    ];
+    let plaintext = [
+        0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb, 0xcc, 0xdd, 0xee,
+        0xff,
+    ];
+    let expected = [
+        0x69, 0xc4, 0xe0, 0xd8, 0x6a, 0x7b, 0x04, 0x30, 0xd8, 0xcd, 0xb7, 0x80, 0x70, 0xb4, 0xc5,
+        0x5a,
+    ];
+    atca.memory().write_aes_key(AES_SLOT, key).unwrap();
+    let ciphertext = &mut [0x00; 16];
+    atca.aes(AES_SLOT).encrypt(&plaintext, ciphertext).unwrap();
+    assert_eq!(ciphertext, &expected);
+    let decrypted = &mut [0x00; 16];
+    atca.aes(AES_SLOT).decrypt(ciphertext, decrypted).unwrap();
+    assert_eq!(decrypted, &plaintext);
+}
+
+#[test]
+fn kdf() {
+    let io_key = [0x5a; 32];
+    let mut sim = Simulator::new();
+    // IO protection key enabled in slot 6.
+    sim.config[ConfigZone::CHIP_OPTIONS_INDEX..ConfigZone::CHIP_OPTIONS_INDEX + 2]
+        .copy_from_slice(&[0x02, 0x60]);
+    let secret_config = SlotConfig::from(0x0000).with_is_secret(true);
+    symmetric(
+        &mut sim,
+        &[
+            (Slot::PrivateKey05, secret_config, &[]),
+            (Slot::PrivateKey06, secret_config, &io_key),
+            (Slot::Data08, secret_config, &[0x0b; 48]),
+        ],
+    );
+    let mut atca = AtCaClient::new(&mut sim, NoDelay);
+
+    // TLS 1.2 master secret from a premaster secret kept in a slot.
+    let mut message = [0x01; 77];
+    message[..13].copy_from_slice(b"master secret");
+    let output = atca
+        .kdf()
+        .prf(
+            KdfSource::Slot(Slot::Data08),
+            48,
+            KdfTarget::Output,
+            64,
+            &message,
+        )
+        .unwrap();
+    assert_eq!(
+        output.data(),
+        &[
+            0xc8, 0x40, 0xdb, 0x11, 0x94, 0xc9, 0x47, 0x33, 0xc8, 0x8e, 0x29, 0x11, 0x9f, 0x5f,
+            0x47, 0x89, 0xfb, 0x64, 0xe4, 0xcb, 0xeb, 0xf8, 0xad, 0xc2, 0xfa, 0x84, 0xda, 0x40,
+            0xca, 0xba, 0xfd, 0x9e, 0xcd, 0xc7, 0x0e, 0x26, 0xc1, 0xdb, 0x34, 0x78, 0xac, 0x90,
+            0xb7, 0xe8, 0x41, 0x62, 0xd9, 0xf2, 0x61, 0x6b, 0x9c, 0xeb, 0xab, 0x64, 0x6f, 0x44,
+            0x4a, 0xa2, 0x6a, 0x51, 0xeb, 0x72, 0xad, 0xeb
+        ][..]
+    );
+    assert!(output.nonce().is_none());
+    let encrypted = atca
+        .kdf()
+        .prf(
+            KdfSource::Slot(Slot::Data08),
+            48,
+            KdfTarget::OutputEncrypted,
+            64,
+            &message,
+        )
+        .unwrap();
+    let mut data = [0x00; 64];
+    data.copy_from_slice(encrypted.data());
+    assert_ne!(&data[..], output.data());
+    host::io_decrypt(&io_key, &encrypted.nonce().unwrap().into(), &mut data);
+    assert_eq!(&data[..], output.data());
+    assert!(atca
+        .kdf()
+        .prf(
+            KdfSource::Slot(Slot::Data08),
+            24,
+            KdfTarget::Output,
+            64,
+            &message
+        )
+        .is_err());
+
+    // HKDF, RFC 5869 test case 3. PRK does not leave the device.
+    let output = atca
+        .kdf()
+        .hkdf_extract(
+            None,
+            KdfMessage::Input(&[0x0b; 22]),
+            KdfTarget::Slot(Slot::PrivateKey05),
+        )
+        .unwrap();
+    assert!(output.data().is_empty());
+    let output = atca
+        .kdf()
+        .hkdf_expand(KdfSource::Slot(Slot::PrivateKey05), &[], KdfTarget::Output)
+        .unwrap();
+    assert_eq!(
+        output.data(),
+        &[
+            0x8d, 0xa4, 0xe7, 0x75, 0xa5, 0x63, 0xc1, 0x8f, 0x71, 0x5f, 0x80, 0x2a, 0x06, 0x3c,
+            0x5a, 0x31, 0xb8, 0xa1, 0x1f, 0x5c, 0x5e, 0xe1, 0x87, 0x9e, 0xc3, 0x45, 0x4e, 0x5f,
+            0x3c, 0x73, 0x8d, 0x2d
+        ][..]
+    );
+
+    // AES-128, FIPS-197 example vector, keyed by the alternate key buffer.
+    let mut key = [0x00; 32];
+    key.iter_mut()
+        .take(16)
+        .enumerate()
+        .for_each(|(i, byte)| *byte = i as u8);
+    atca.nonce()
+        .load(NonceTarget::AlternateKeyBuffer, &key)
+        .unwrap();
+    let plaintext = [
+        0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb, 0xcc, 0xdd, 0xee,
+        0xff,
+    ];
+    let output = atca
+        .kdf()
+        .aes(
+            KdfSource::AlternateKeyBuffer,
+            0,
+            KdfTarget::Output,
+            &plaintext,
+        )
+        .unwrap();
+    assert_eq!(
+        output.data(),
+        &[
+            0x69, 0xc4, 0xe0, 0xd8, 0x6a, 0x7b, 0x04, 0x30, 0xd8, 0xcd, 0xb7, 0x80, 0x70, 0xb4,
+            0xc5, 0x5a
+        ][..]
+    );
+}
@@ -0,0 +1,118 @@
+use super::*;
+use crate::tngtls::{SIGNER_PUBLIC_KEY, USER_PRIVATE_KEY1};
+use embedded_hal::i2c::I2c;
+use signature::{Signer, Verifier};
+
+#[test]
+fn asynchronous() {
+    use crate::asynch::AtCaClient;
+    use embassy_futures::block_on;
+
+    let mut sim = Simulator::new();
+    tng(&mut sim);
+    let mut atca = AtCaClient::new(&mut sim, NoDelay);
+    block_on(async {
+        let revision = atca.info().await.unwrap();
+        assert_eq!(revision.as_ref(), REVISION);
+        let digest = atca.sha(None).digest(b"abc").await.unwrap();
+        assert_eq!(digest.as_ref(), &Sha256::digest(b"abc")[..]);
+        let message = [0x5a; 150];
+        let mut sha = atca.sha(None);
+        sha.init().await.unwrap();
+        for chunk in message.chunks(10) {
+            sha.update(chunk).await.unwrap();
+        }
+        let digest = sha.finalize().await.unwrap();
+        assert_eq!(digest.as_ref(), &Sha256::digest(&message)[..]);
+        drop(sha);
+        atca.create_private_key(USER_PRIVATE_KEY1).await.unwrap();
+        let signature = atca.sign(USER_PRIVATE_KEY1).sign(b"abc").await.unwrap();
+        let mut verify = atca.verify(USER_PRIVATE_KEY1);
+        assert!(verify.verify(b"abc", &signature).await.is_ok());
+    });
+}
+
+// Logs every byte either flavour puts on or takes off the bus.
+struct Recorder {
+    sim: Simulator,
+    log: heapless::Vec<u8, 2048>,
+}
+
+impl i2c::ErrorType for Recorder {
+    type Error = Nack;
+}
+
+impl I2c for Recorder {
+    fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        let result = self.sim.transaction(address, operations);
+        self.log.push(address).unwrap();
+        for operation in operations.iter() {
+            match operation {
+                Operation::Write(bytes) => self.log.extend_from_slice(bytes).unwrap(),
+                Operation::Read(buffer) => self.log.extend_from_slice(buffer).unwrap(),
+            }
+        }
+        self.log.push(result.is_ok() as u8).unwrap();
+        result
+    }
+}
+
+impl embedded_hal_async::i2c::I2c for Recorder {
+    async fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        I2c::transaction(self, address, operations)
+    }
+}
+
+#[test]
+fn flavours_agree() {
+    use embassy_futures::block_on;
+
+    let mut sims = [Simulator::new(), Simulator::new()];
+    sims.iter_mut().for_each(tng);
+    let [sim, asim] = sims;
+    let mut blocking = Recorder {
+        sim,
+        log: heapless::Vec::new(),
+    };
+    let mut asynch = Recorder {
+        sim: asim,
+        log: heapless::Vec::new(),
+    };
+
+    let mut atca = AtCaClient::new(&mut blocking, NoDelay);
+    let public_key = atca.create_private_key(USER_PRIVATE_KEY1).unwrap();
+    let signature = atca.signer(USER_PRIVATE_KEY1).try_sign(b"abc").unwrap();
+    let verifier = atca.verifier(USER_PRIVATE_KEY1);
+    assert!(verifier.verify(b"abc", &signature).is_ok());
+    atca.memory()
+        .write_pubkey(SIGNER_PUBLIC_KEY, public_key)
+        .unwrap();
+    // Data zone cannot be read before locking.
+    assert!(atca.memory().pubkey(SIGNER_PUBLIC_KEY).is_err());
+    atca.sleep().unwrap();
+
+    let mut atca = crate::asynch::AtCaClient::new(&mut asynch, NoDelay);
+    block_on(async {
+        let public_key = atca.create_private_key(USER_PRIVATE_KEY1).await.unwrap();
+        let signature = atca.sign(USER_PRIVATE_KEY1).sign(b"abc").await.unwrap();
+        let mut verify = atca.verify(USER_PRIVATE_KEY1);
+        assert!(verify.verify(b"abc", &signature).await.is_ok());
+        atca.memory()
+            .write_pubkey(SIGNER_PUBLIC_KEY, public_key)
+            .await
+            .unwrap();
+        assert!(atca.memory().pubkey(SIGNER_PUBLIC_KEY).await.is_err());
+        atca.sleep().await.unwrap();
+    });
+
+    assert_eq!(asynch.sim.wakes(), blocking.sim.wakes());
+    assert_eq!(asynch.log, blocking.log);
+}
@@ -0,0 +1,239 @@
+use super::*;
+use crate::datalink::{Swi, SwiConfig};
+use crate::error;
+use crate::tngtls::{I2C_ADDRESS, USER_PRIVATE_KEY1};
+use crate::I2cConfig;
+use embedded_hal::i2c::I2c;
+use signature::{Signer, Verifier};
+
+#[test]
+fn wake_and_idle() {
+    let mut sim = Simulator::new();
+    let mut atca = AtCaClient::new(&mut sim, NoDelay);
+    let revision = atca.info().unwrap();
+    assert_eq!(revision.as_ref(), REVISION);
+    drop(atca);
+    // Device is put into idle state after each command.
+    assert!(!sim.is_awake());
+}
+
+#[test]
+fn address_mismatch() {
+    let mut sim = Simulator::new().with_address(0x35);
+    assert!(AtCaClient::new(&mut sim, NoDelay).info().is_err());
+}
+
+#[test]
+fn configurable_address() {
+    let mut sim = Simulator::new().with_address(I2C_ADDRESS);
+    let config = I2cConfig::default()
+        .address(I2C_ADDRESS)
+        .retry(1)
+        .wake_delay_us(800);
+    let mut atca = AtCaClient::with_i2c_config(&mut sim, NoDelay, config);
+    assert_eq!(atca.memory().i2c_address().unwrap(), I2C_ADDRESS);
+}
+
+#[test]
+fn reprogram_address() {
+    let mut sim = Simulator::new();
+    let mut atca = AtCaClient::new(&mut sim, NoDelay);
+    atca.memory().set_i2c_address(0x36).unwrap();
+    assert_eq!(atca.memory().i2c_address().unwrap(), 0x36);
+    assert!(atca.memory().set_i2c_address(0x80).is_err());
+    // Not in effect until the config zone gets locked.
+    assert!(atca.info().is_ok());
+    atca.memory().lock(Zone::Config).unwrap();
+    assert!(atca.info().is_err());
+    assert_eq!(sim.address(), 0x36);
+
+    let config = I2cConfig::default().address(0x36);
+    let mut atca = AtCaClient::with_i2c_config(&mut sim, NoDelay, config);
+    assert!(atca.info().is_ok());
+    // Locked config zone is not writable any more.
+    assert!(atca.memory().set_i2c_address(ADDRESS).is_err());
+}
+
+#[test]
+fn poll_for_completion() {
+    let mut sim = Simulator::new().with_busy_polls(4);
+    let mut atca = AtCaClient::new(&mut sim, NoDelay);
+    assert_eq!(atca.info().unwrap().as_ref(), REVISION);
+
+    // Give up earlier than the device completes.
+    let config = I2cConfig::default().poll_interval_us(1000).timeout_ms(3);
+    let mut atca = AtCaClient::with_i2c_config(&mut sim, NoDelay, config);
+    let error = atca.info().unwrap_err();
+    assert_eq!(error.kind(), Some(error::ErrorKind::Timeout));
+}
+
+#[test]
+fn session() {
+    let mut sim = Simulator::new();
+    tng(&mut sim);
+    let wakes = sim.wakes();
+    let mut atca = AtCaClient::new(&mut sim, NoDelay);
+    let mut session = atca.session();
+    session.random().unwrap();
+    session.info().unwrap();
+    session.sha(None).digest(b"abc").unwrap();
+    session.idle().unwrap();
+    assert!(!sim.is_awake() && !sim.is_asleep());
+    assert_eq!(sim.wakes(), wakes + 1);
+
+    // Multi-step operations wake the device once.
+    let mut atca = AtCaClient::new(&mut sim, NoDelay);
+    atca.create_private_key(USER_PRIVATE_KEY1).unwrap();
+    let wakes = sim.wakes();
+    let mut atca = AtCaClient::new(&mut sim, NoDelay);
+    let signature = atca.signer(USER_PRIVATE_KEY1).try_sign(b"abc").unwrap();
+    assert!(atca
+        .verifier(USER_PRIVATE_KEY1)
+        .verify(b"abc", &signature)
+        .is_ok());
+    assert_eq!(sim.wakes(), wakes + 2);
+
+    let mut atca = AtCaClient::new(&mut sim, NoDelay);
+    atca.session().sleep().unwrap();
+    assert!(sim.is_asleep());
+    // A nested session does not end the outer one.
+    let wakes = sim.wakes();
+    let mut atca = AtCaClient::new(&mut sim, NoDelay);
+    let mut session = atca.session();
+    session.info().unwrap();
+    session.session().sleep().unwrap();
+    session.info().unwrap();
+    session.idle().unwrap();
+    assert!(!sim.is_awake() && !sim.is_asleep());
+    assert_eq!(sim.wakes(), wakes + 1);
+    let mut atca = AtCaClient::new(&mut sim, NoDelay);
+    atca.info().unwrap();
+    atca.sleep().unwrap();
+    assert!(sim.is_asleep());
+}
+
+#[test]
+fn watchdog() {
+    let mut sim = Simulator::new().with_busy_polls(1);
+    // Every command keeps the device busy for 800 ms.
+    let config = I2cConfig::default()
+        .poll_interval_us(400_000)
+        .timeout_ms(1000);
+    let mut atca = AtCaClient::with_i2c_config(&mut sim, NoDelay, config);
+    let mut session = atca.session();
+    session.info().unwrap();
+    // The watchdog could expire during the next command.
+    session.info().unwrap();
+    drop(session);
+    assert_eq!(sim.wakes(), 2);
+}
+
+// Two devices on a single bus. Wake tokens reach both of them, and a
+// transfer is acknowledged if either device does.
+struct Bus([Simulator; 2]);
+
+impl i2c::ErrorType for Bus {
+    type Error = Nack;
+}
+
+impl I2c for Bus {
+    fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        let results = [
+            self.0[0].transaction(address, operations),
+            self.0[1].transaction(address, operations),
+        ];
+        results
+            .iter()
+            .find(|result| result.is_ok())
+            .copied()
+            .unwrap_or(Err(Nack))
+    }
+}
+
+#[test]
+fn shared_bus() {
+    use core::cell::RefCell;
+    use critical_section::Mutex;
+    use embedded_hal_bus::i2c::{CriticalSectionDevice, RefCellDevice};
+
+    let bus = Bus([
+        Simulator::new().with_seed([0x01; 32]),
+        Simulator::new()
+            .with_seed([0x02; 32])
+            .with_address(I2C_ADDRESS),
+    ]);
+    let tngtls = I2cConfig::default().address(I2C_ADDRESS);
+
+    let bus = RefCell::new(bus);
+    let mut atca1 = AtCaClient::new(RefCellDevice::new(&bus), NoDelay);
+    let mut atca2 = AtCaClient::with_i2c_config(RefCellDevice::new(&bus), NoDelay, tngtls);
+    atca1.tng().unwrap();
+    atca2.tng().unwrap();
+    let random1 = atca1.random().unwrap();
+    let random2 = atca2.random().unwrap();
+    assert_ne!(random1.as_ref(), random2.as_ref());
+    drop((atca1, atca2));
+
+    let bus = Mutex::new(bus);
+    let mut atca1 = AtCaClient::new(CriticalSectionDevice::new(&bus), NoDelay);
+    let mut atca2 = AtCaClient::with_i2c_config(CriticalSectionDevice::new(&bus), NoDelay, tngtls);
+    assert_eq!(atca1.memory().i2c_address().unwrap(), ADDRESS);
+    assert_eq!(atca2.memory().i2c_address().unwrap(), I2C_ADDRESS);
+}
+
+#[test]
+fn single_wire() {
+    let mut uart = Uart::new(Simulator::new());
+    let mut atca = AtCaClient::swi(&mut uart, NoDelay);
+    assert_eq!(atca.info().unwrap().as_ref(), REVISION);
+    atca.tng().unwrap();
+    atca.create_private_key(USER_PRIVATE_KEY1).unwrap();
+    let signature = atca.signer(USER_PRIVATE_KEY1).try_sign(b"abc").unwrap();
+    assert!(atca
+        .verifier(USER_PRIVATE_KEY1)
+        .verify(b"abc", &signature)
+        .is_ok());
+    let wakes = uart.simulator().wakes();
+
+    let mut atca = AtCaClient::swi(&mut uart, NoDelay);
+    let mut session = atca.session();
+    session.random().unwrap();
+    session.info().unwrap();
+    session.idle().unwrap();
+    assert!(!uart.simulator().is_awake());
+    assert_eq!(uart.simulator().wakes(), wakes + 1);
+
+    AtCaClient::swi(&mut uart, NoDelay).sleep().unwrap();
+    assert!(uart.simulator().is_asleep());
+}
+
+#[test]
+fn single_wire_timeout() {
+    let mut uart = Uart::new(Simulator::new().with_busy_polls(4));
+    let config = SwiConfig::default().timeout_ms(3);
+    let mut atca = AtCaClient::with_transport(Swi::new(&mut uart, NoDelay, config));
+    let error = atca.info().unwrap_err();
+    assert_eq!(error.kind(), Some(error::ErrorKind::Timeout));
+    let mut atca = AtCaClient::swi(&mut uart, NoDelay);
+    assert_eq!(atca.info().unwrap().as_ref(), REVISION);
+}
+
+#[test]
+fn crc_error() {
+    let mut sim = Simulator::new();
+    // Wake up the device.
+    sim.write(ADDRESS, &[0x00]).ok();
+    let buffer = &mut [0x00; 4];
+    sim.read(ADDRESS, buffer).unwrap();
+    assert_eq!(buffer, &WAKE_RESPONSE);
+
+    // Info command with a broken CRC.
+    sim.write(ADDRESS, &[0x03, 0x07, 0x30, 0x00, 0x00, 0x00, 0xde, 0xad])
+        .unwrap();
+    sim.read(ADDRESS, buffer).unwrap();
+    assert_eq!(buffer[..2], [0x04, Status::Crc as u8]);
+}
@@ -0,0 +1,152 @@
+use super::*;
+use crate::error;
+use crate::tngtls::{IO_PROTECTION_KEY, USER_PRIVATE_KEY1, USER_PRIVATE_KEY2};
+use crate::{Digest, EcdhSource};
+use core::convert::TryFrom;
+use p256::ecdsa::signature::hazmat::PrehashVerifier;
+use p256::ecdsa::{Signature, VerifyingKey};
+use signature::{Signer, Verifier};
+
+#[test]
+fn sign_and_verify() {
+    let mut sim = Simulator::new();
+    tng(&mut sim);
+    let mut atca = AtCaClient::new(&mut sim, NoDelay);
+    let public_key = atca.create_private_key(USER_PRIVATE_KEY1).unwrap();
+    assert_eq!(
+        public_key.as_ref(),
+        atca.generate_pubkey(USER_PRIVATE_KEY1).unwrap().as_ref()
+    );
+
+    let msg = b"Hello, world!";
+    let signature = atca.signer(USER_PRIVATE_KEY1).try_sign(msg).unwrap();
+    {
+        let verifier = atca.verifier(USER_PRIVATE_KEY1);
+        assert!(verifier.verify(msg, &signature).is_ok());
+        assert!(verifier.verify(b"Goodbye", &signature).is_err());
+    }
+
+    // Cross-check the signature on the host.
+    let verifying_key =
+        VerifyingKey::from_sec1_bytes(&command_uncompressed(public_key.as_ref())).unwrap();
+    let signature = Signature::from_slice(signature.as_ref()).unwrap();
+    let digest = Sha256::digest(msg);
+    assert!(verifying_key.verify_prehash(&digest, &signature).is_ok());
+
+    // Signing with a key in a slot that prohibits external signatures.
+    let digest = Digest::try_from(&digest[..]).unwrap();
+    let mut atca = AtCaClient::new(&mut sim, NoDelay);
+    assert!(atca.sign(Slot::PrivateKey06).sign_digest(&digest).is_err());
+}
+
+#[test]
+fn diffie_hellman() {
+    let mut sim = Simulator::new();
+    tng(&mut sim);
+    let mut atca = AtCaClient::new(&mut sim, NoDelay);
+    let public_key1 = atca.create_private_key(USER_PRIVATE_KEY1).unwrap();
+    let public_key2 = atca.create_private_key(USER_PRIVATE_KEY2).unwrap();
+    assert_ne!(public_key1.as_ref(), public_key2.as_ref());
+
+    let secret1 = atca.diffie_hellman(USER_PRIVATE_KEY1, public_key2).unwrap();
+    let secret2 = atca.diffie_hellman(USER_PRIVATE_KEY2, public_key1).unwrap();
+    assert_eq!(secret1.as_ref(), secret2.as_ref());
+}
+
+#[test]
+fn ecdh_modes() {
+    let io_key = [0x5a; 32];
+    let mut sim = Simulator::new();
+    tng(&mut sim);
+    // IO protection key enabled in slot 6.
+    sim.config[ConfigZone::CHIP_OPTIONS_INDEX..ConfigZone::CHIP_OPTIONS_INDEX + 2]
+        .copy_from_slice(&[0x02, 0x60]);
+    sim.slot_data_mut(IO_PROTECTION_KEY as usize)[..32].copy_from_slice(&io_key);
+    let mut atca = AtCaClient::new(&mut sim, NoDelay);
+    let public_key1 = atca.create_private_key(USER_PRIVATE_KEY1).unwrap();
+    let public_key2 = atca.create_private_key(USER_PRIVATE_KEY2).unwrap();
+    let expected = atca.diffie_hellman(USER_PRIVATE_KEY2, public_key1).unwrap();
+
+    let mut ecdh = atca.ecdh(EcdhSource::Slot(USER_PRIVATE_KEY1));
+    let secret = ecdh.diffie_hellman(&public_key2).unwrap();
+    assert_eq!(secret.as_ref(), expected.as_ref());
+    let secret = ecdh
+        .diffie_hellman_encrypted(&public_key2, &io_key.into())
+        .unwrap();
+    assert_eq!(secret.as_ref(), expected.as_ref());
+    // Slot N|1 holds another private key, and other slots are rejected.
+    assert!(ecdh.into_slot(&public_key2, Slot::PrivateKey03).is_err());
+    assert_eq!(
+        ecdh.into_slot(&public_key2, Slot::Data08)
+            .unwrap_err()
+            .kind(),
+        Some(error::ErrorKind::BadParam)
+    );
+    ecdh.into_temp_key(&public_key2).unwrap();
+    drop(atca);
+    assert_eq!(sim.temp_key.unwrap().value(), expected.as_ref());
+
+    // The same private key, this time from TempKey.
+    let private_key = sim.slot_data(USER_PRIVATE_KEY1 as usize)[4..36].to_vec();
+    sim.load_temp_key(&private_key, Source::Random);
+    let mut atca = AtCaClient::new(&mut sim, NoDelay);
+    atca.ecdh(EcdhSource::TempKey)
+        .into_slot(&public_key2, Slot::Data08)
+        .unwrap();
+    drop(atca);
+    assert_eq!(
+        &sim.slot_data(Slot::Data08 as usize)[..32],
+        expected.as_ref()
+    );
+}
+
+#[test]
+fn ephemeral_diffie_hellman() {
+    let mut sim = Simulator::new();
+    tng(&mut sim);
+    let mut atca = AtCaClient::new(&mut sim, NoDelay);
+    let public_key = atca.create_private_key(USER_PRIVATE_KEY1).unwrap();
+
+    let ephemeral = atca.ephemeral_keypair().unwrap();
+    let ephemeral_public_key = *ephemeral.public_key();
+    let secret = ephemeral.diffie_hellman(&public_key).unwrap();
+    // The private key does not outlive its single use.
+    assert!(atca
+        .ecdh(EcdhSource::TempKey)
+        .diffie_hellman(&public_key)
+        .is_err());
+    let expected = atca
+        .diffie_hellman(USER_PRIVATE_KEY1, ephemeral_public_key)
+        .unwrap();
+    assert_eq!(secret.as_ref(), expected.as_ref());
+
+    // A new key every time.
+    let ephemeral = atca.ephemeral_keypair().unwrap();
+    assert_ne!(
+        ephemeral.public_key().as_ref(),
+        ephemeral_public_key.as_ref()
+    );
+    drop(ephemeral);
+    assert!(atca
+        .ecdh(EcdhSource::TempKey)
+        .diffie_hellman(&public_key)
+        .is_err());
+
+    // Within an outer session, TempKey is overwritten instead.
+    let mut session = atca.session();
+    let ephemeral = session.ephemeral_keypair().unwrap();
+    ephemeral.diffie_hellman(&public_key).unwrap();
+    assert!(session
+        .ecdh(EcdhSource::TempKey)
+        .diffie_hellman(&public_key)
+        .is_err());
+    drop(session);
+    drop(atca);
+    assert!(!sim.is_asleep());
+}
+
+fn command_uncompressed(public_key: &[u8]) -> [u8; 65] {
+    let mut sec1 = [0x04; 65];
+    sec1[1..].copy_from_slice(public_key);
+    sec1
+}
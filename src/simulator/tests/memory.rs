@@ -0,0 +1,410 @@
+use super::*;
+use crate::command::Block;
+use crate::config::Field;
+use crate::error;
+use crate::memory::{OtpMode, Size};
+use crate::tngtls::{I2C_ADDRESS, SIGNER_PUBLIC_KEY, USER_PRIVATE_KEY1};
+use crate::CounterId;
+use core::convert::TryFrom;
+
+#[test]
+fn serial_number() {
+    let mut sim = Simulator::new();
+    let serial = AtCaClient::new(&mut sim, NoDelay)
+        .memory()
+        .serial_number()
+        .unwrap();
+    assert_eq!(serial.as_ref(), SERIAL_NUMBER);
+}
+
+#[test]
+fn lock_config_zone() {
+    let mut sim = Simulator::new();
+    let mut atca = AtCaClient::new(&mut sim, NoDelay);
+    assert!(!atca.memory().is_locked(Zone::Config).unwrap());
+    // Random number generator is not functional yet.
+    assert_eq!(
+        atca.random().unwrap().as_ref()[..4],
+        [0xff, 0xff, 0x00, 0x00]
+    );
+
+    let crc = CRC16.checksum(&[0x00; CONFIG_SIZE]);
+    assert!(atca.memory().lock_crc(Zone::Config, crc).is_err());
+    atca.tng().unwrap();
+    assert!(atca.memory().is_locked(Zone::Config).unwrap());
+    assert!(!atca.memory().is_locked(Zone::Data).unwrap());
+    let permission = atca.memory().permission(USER_PRIVATE_KEY1).unwrap();
+    assert!(permission.is_secret());
+    assert_eq!(permission.write_config(), 0x02);
+    let key_config = atca.memory().key_type(USER_PRIVATE_KEY1).unwrap();
+    assert!(key_config.private());
+    assert_eq!(key_config.key_type(), KeyType::P256);
+    assert_ne!(
+        atca.random().unwrap().as_ref()[..4],
+        [0xff, 0xff, 0x00, 0x00]
+    );
+
+    // Config zone is no longer writable.
+    let result = atca
+        .memory()
+        .write_config(Size::Word, 0, 5, [0x00, 0x00, 0x00, 0x00]);
+    assert!(result.is_err());
+}
+
+#[test]
+fn lock_verified() {
+    let mut sim = Simulator::new();
+    let mut atca = AtCaClient::new(&mut sim, NoDelay);
+    let mut expected = atca.memory().read_config_zone().unwrap();
+    expected.chip_options = 0x0100;
+    let error = atca.memory().lock_config_verified(&expected).unwrap_err();
+    assert_eq!(error.kind(), Some(error::ErrorKind::ZoneMismatch));
+    assert!(!atca.memory().is_locked(Zone::Config).unwrap());
+
+    atca.memory().write_config_zone(&expected).unwrap();
+    atca.memory().lock_config_verified(&expected).unwrap();
+    assert!(atca.memory().is_locked(Zone::Config).unwrap());
+
+    let mut data = [0x00; DATA_SIZE];
+    let otp = [0x00; OTP_SIZE];
+    assert!(atca.memory().lock_data_verified(&data, &otp[..32]).is_err());
+    data[slot_range(Slot::Data08 as usize)][0] = 0x01;
+    let error = atca.memory().lock_data_verified(&data, &otp).unwrap_err();
+    assert_eq!(error.status(), Some(error::Status::Execution));
+    assert!(!atca.memory().is_locked(Zone::Data).unwrap());
+
+    atca.memory()
+        .lock_data_verified(&[0x00; DATA_SIZE], &otp)
+        .unwrap();
+    assert!(atca.memory().is_locked(Zone::Data).unwrap());
+}
+
+#[test]
+fn otp_zone() {
+    let mut sim = Simulator::new();
+    let mut atca = AtCaClient::new(&mut sim, NoDelay);
+    let mode = atca.memory().otp_mode().unwrap();
+    assert_eq!(mode, OtpMode::Writable);
+    let error = atca.memory().read_otp(mode, Size::Word, 0, 0).unwrap_err();
+    assert_eq!(error.kind(), Some(error::ErrorKind::NotLocked));
+    // Not writable prior to locking the config zone.
+    assert!(atca
+        .memory()
+        .write_otp(mode, Size::Word, 0, 0, b"ATCA")
+        .is_err());
+
+    atca.tng().unwrap();
+    let mode = atca.memory().read_config_zone().unwrap().otp_mode();
+    assert_eq!(mode, OtpMode::Writable);
+    let metadata = [0xa5; 32];
+    atca.memory()
+        .write_otp(mode, Size::Block, 1, 0, metadata)
+        .unwrap();
+    atca.memory()
+        .write_otp(mode, Size::Word, 0, 2, b"ATCA")
+        .unwrap();
+    assert!(atca
+        .memory()
+        .write_otp(mode, Size::Word, 2, 0, b"ATCA")
+        .is_err());
+
+    let mut otp = [0x00; OTP_SIZE];
+    otp[8..12].copy_from_slice(b"ATCA");
+    otp[32..].copy_from_slice(&metadata);
+    atca.memory()
+        .lock_data_verified(&[0x00; DATA_SIZE], &otp)
+        .unwrap();
+    let mode = atca.memory().otp_mode().unwrap();
+    assert_eq!(mode, OtpMode::ReadOnly);
+    assert_eq!(
+        atca.memory()
+            .read_otp(mode, Size::Word, 0, 2)
+            .unwrap()
+            .as_ref(),
+        b"ATCA"
+    );
+    assert_eq!(
+        atca.memory()
+            .read_otp(mode, Size::Block, 1, 0)
+            .unwrap()
+            .as_ref(),
+        metadata
+    );
+    let error = atca
+        .memory()
+        .write_otp(mode, Size::Word, 0, 0, b"ATCA")
+        .unwrap_err();
+    assert_eq!(error.kind(), Some(error::ErrorKind::DataZoneLocked));
+    assert_eq!(sim.otp_zone(), &otp);
+}
+
+#[test]
+fn config_zone() {
+    let mut sim = Simulator::new();
+    let mut atca = AtCaClient::new(&mut sim, NoDelay);
+    let factory = atca.memory().read_config_zone().unwrap();
+    assert_eq!(factory.serial_number, SERIAL_NUMBER);
+    assert_eq!(factory.revision, REVISION);
+
+    let mut config = factory;
+    config.serial_number = [0x00; 9];
+    config.i2c_address = I2C_ADDRESS << 1;
+    config.slot_config[AES_SLOT as usize] = SlotConfig::from(0x0f0f);
+    config.chip_options = 0x0100;
+    config.key_config[Slot::Certificate0f as usize] = KeyConfig::from(0x001c);
+    config.lock_config = 0x00;
+    atca.memory().write_config_zone(&config).unwrap();
+
+    // Read-only bytes are left intact.
+    let written = atca.memory().read_config_zone().unwrap();
+    let mut diff = factory.diff(&written);
+    assert_eq!(diff.next(), Some(Field::I2cAddress));
+    assert_eq!(diff.next(), Some(Field::SlotConfig(AES_SLOT)));
+    assert_eq!(diff.next(), Some(Field::ChipOptions));
+    assert_eq!(diff.next(), Some(Field::KeyConfig(Slot::Certificate0f)));
+    assert_eq!(diff.next(), None);
+    assert_eq!(&written.to_bytes()[..], &sim.config_zone()[..]);
+}
+
+#[test]
+fn data_zone() {
+    let mut sim = Simulator::new();
+    tng(&mut sim);
+    let mut atca = AtCaClient::new(&mut sim, NoDelay);
+    let public_key = atca.create_private_key(USER_PRIVATE_KEY1).unwrap();
+    atca.memory()
+        .write_pubkey(SIGNER_PUBLIC_KEY, public_key)
+        .unwrap();
+    // Data zone cannot be read before locking.
+    assert!(atca.memory().pubkey(SIGNER_PUBLIC_KEY).is_err());
+
+    atca.memory().lock(Zone::Data).unwrap();
+    let stored = atca.memory().pubkey(SIGNER_PUBLIC_KEY).unwrap();
+    assert_eq!(public_key.as_ref(), stored.as_ref());
+    // Signer public key cannot be overwritten any more.
+    assert!(atca
+        .memory()
+        .write_pubkey(SIGNER_PUBLIC_KEY, public_key)
+        .is_err());
+    // Nor can private keys be read.
+    assert!(atca.memory().pubkey(Slot::Certificate09).is_err());
+}
+
+#[test]
+fn pubkey_digest() {
+    let mut sim = Simulator::new();
+    tng(&mut sim);
+    let mut atca = AtCaClient::new(&mut sim, NoDelay);
+    let public_key = atca.create_private_key(USER_PRIVATE_KEY1).unwrap();
+    atca.memory()
+        .write_pubkey(SIGNER_PUBLIC_KEY, public_key)
+        .unwrap();
+    atca.memory().lock(Zone::Data).unwrap();
+
+    let digest = atca.memory().pubkey_digest(SIGNER_PUBLIC_KEY).unwrap();
+    assert_eq!(digest.as_ref(), &Sha256::digest(public_key.as_ref())[..]);
+
+    // The key may follow whole blocks of other data.
+    let prefix = [0xa5; 64];
+    let expected = Sha256::new()
+        .chain(prefix)
+        .chain(public_key.as_ref())
+        .chain(b"tail")
+        .finalize();
+    let mut sha = atca.sha(None);
+    sha.init().unwrap();
+    sha.update(prefix).unwrap();
+    sha.public_key(SIGNER_PUBLIC_KEY).unwrap();
+    sha.update(b"tail").unwrap();
+    assert_eq!(sha.finalize().unwrap().as_ref(), &expected[..]);
+
+    sha.init().unwrap();
+    sha.update(&prefix[..10]).unwrap();
+    assert!(sha.public_key(SIGNER_PUBLIC_KEY).is_err());
+    drop(sha);
+    // Private keys cannot be hashed.
+    assert!(atca.memory().pubkey_digest(USER_PRIVATE_KEY1).is_err());
+}
+
+#[test]
+fn counter() {
+    let mut sim = Simulator::new();
+    let count_match = CountMatch::default()
+        .with_enabled(true)
+        .with_key(Slot::Data08);
+    sim.config[ConfigZone::COUNT_MATCH_INDEX] = count_match.into();
+    sim.slot_data_mut(Slot::Data08 as usize)[..8]
+        .copy_from_slice(&CountMatch::encode_value(32).unwrap());
+    let mut atca = AtCaClient::new(&mut sim, NoDelay);
+    let mut config = atca.memory().read_config_zone().unwrap();
+    config.set_counter(CounterId::Counter0, 30).unwrap();
+    atca.memory().write_config_zone(&config).unwrap();
+    atca.memory().lock_config_verified(&config).unwrap();
+    atca.memory().lock(Zone::Data).unwrap();
+
+    let config = atca.memory().read_config_zone().unwrap();
+    assert_eq!(config.counter(CounterId::Counter0), Some(30));
+    assert_eq!(config.counter(CounterId::Counter1), Some(0));
+    assert_eq!(atca.counter(CounterId::Counter1).read().unwrap(), 0);
+    assert_eq!(atca.counter(CounterId::Counter1).increment().unwrap(), 1);
+    assert_eq!(atca.counter(CounterId::Counter1).increment().unwrap(), 2);
+    assert_eq!(atca.counter(CounterId::Counter1).read().unwrap(), 2);
+
+    // Counter0 stops at the match value.
+    assert_eq!(atca.memory().count_match().unwrap(), count_match);
+    assert_eq!(atca.memory().count_match_value().unwrap(), Some(32));
+    assert_eq!(atca.counter(CounterId::Counter0).increment().unwrap(), 31);
+    assert_eq!(atca.counter(CounterId::Counter0).increment().unwrap(), 32);
+    assert!(atca.counter(CounterId::Counter0).increment().is_err());
+    assert_eq!(atca.counter(CounterId::Counter0).read().unwrap(), 32);
+    assert_eq!(atca.counter(CounterId::Counter1).read().unwrap(), 2);
+}
+
+#[test]
+fn slot_access() {
+    let mut sim = Simulator::new();
+    tng(&mut sim);
+    let mut atca = AtCaClient::new(&mut sim, NoDelay);
+    let mut data = [0x00; 416];
+    data.iter_mut()
+        .enumerate()
+        .for_each(|(i, byte)| *byte = i as u8);
+    atca.memory().write_slot(Slot::Data08, 0, &data).unwrap();
+    atca.memory()
+        .write_slot(Slot::Certificate0d, 4, &data[..68])
+        .unwrap();
+    let error = atca
+        .memory()
+        .write_slot(Slot::Certificate0d, 8, &data[..68])
+        .unwrap_err();
+    assert_eq!(error.kind(), Some(error::ErrorKind::InvalidSize));
+    assert_eq!(sim.slot(Slot::Data08), &data[..]);
+    assert_eq!(&sim.slot(Slot::Certificate0d)[4..], &data[..68]);
+
+    let mut atca = AtCaClient::new(&mut sim, NoDelay);
+    atca.memory().lock(Zone::Data).unwrap();
+    let mut buffer = [0x00; 416];
+    atca.memory()
+        .read_slot(Slot::Data08, 0, &mut buffer)
+        .unwrap();
+    assert_eq!(buffer, data);
+    let mut buffer = [0x00; 53];
+    atca.memory()
+        .read_slot(Slot::Data08, 350, &mut buffer)
+        .unwrap();
+    assert_eq!(buffer, data[350..403]);
+    let error = atca
+        .memory()
+        .read_slot(Slot::Data08, 400, &mut buffer)
+        .unwrap_err();
+    assert_eq!(error.kind(), Some(error::ErrorKind::InvalidSize));
+
+    // Partially covered words are merged with their current contents.
+    atca.memory()
+        .write_slot(Slot::Data08, 30, &[0xff; 37])
+        .unwrap();
+    let mut buffer = [0x00; 416];
+    atca.memory()
+        .read_slot(Slot::Data08, 0, &mut buffer)
+        .unwrap();
+    data[30..67].iter_mut().for_each(|byte| *byte = 0xff);
+    assert_eq!(buffer, data);
+}
+
+#[test]
+fn encrypted_write() {
+    let io_key = [0x5a; 32];
+    let mut sim = Simulator::new();
+    let io_config = SlotConfig::from(0x0000)
+        .with_is_secret(true)
+        .with_write_config(0x08);
+    let data_config = SlotConfig::from(0x0000)
+        .with_write_key(Slot::PrivateKey06 as u8)
+        .with_write_config(0x04);
+    symmetric(
+        &mut sim,
+        &[
+            (Slot::PrivateKey06, io_config, &io_key),
+            (Slot::Data08, data_config, &[]),
+        ],
+    );
+    let mut atca = AtCaClient::new(&mut sim, NoDelay);
+
+    let data = Block::try_from(&[0xc3; 32][..]).unwrap();
+    let key = Block::try_from(&io_key[..]).unwrap();
+    // Clear text writes are refused once the data zone is locked.
+    assert!(atca
+        .memory()
+        .write_slot(Slot::Data08, 32, &[0xc3; 32])
+        .is_err());
+    atca.memory()
+        .write_slot_encrypted(
+            Slot::Data08,
+            1,
+            &data,
+            Slot::PrivateKey06,
+            &key,
+            &[0x11; 20],
+        )
+        .unwrap();
+    let wrong_key = Block::try_from(&[0xa5; 32][..]).unwrap();
+    let error = atca
+        .memory()
+        .write_slot_encrypted(
+            Slot::Data08,
+            2,
+            &data,
+            Slot::PrivateKey06,
+            &wrong_key,
+            &[0x12; 20],
+        )
+        .unwrap_err();
+    assert_eq!(error.status(), Some(error::Status::CheckmacVerifyFailed));
+    assert_eq!(&sim.slot(Slot::Data08)[32..64], data.as_ref());
+    assert_eq!(&sim.slot(Slot::Data08)[64..96], &[0x00; 32]);
+}
+
+#[test]
+fn encrypted_read() {
+    let io_key = [0x5a; 32];
+    let secret = [0x3c; 32];
+    let mut sim = Simulator::new();
+    let io_config = SlotConfig::from(0x0000).with_is_secret(true);
+    let secret_config = SlotConfig::from(0x0000)
+        .with_read_key(Slot::PrivateKey06 as u8)
+        .with_encrypt_read(true)
+        .with_is_secret(true);
+    symmetric(
+        &mut sim,
+        &[
+            (Slot::PrivateKey06, io_config, &io_key),
+            (Slot::PrivateKey07, secret_config, &secret),
+        ],
+    );
+    let mut atca = AtCaClient::new(&mut sim, NoDelay);
+
+    let key = Block::try_from(&io_key[..]).unwrap();
+    let mut buffer = [0x00; 32];
+    // Secret slots are not readable in clear text.
+    assert!(atca
+        .memory()
+        .read_slot(Slot::PrivateKey07, 0, &mut buffer)
+        .is_err());
+    let block = atca
+        .memory()
+        .read_slot_encrypted(Slot::PrivateKey07, 0, Slot::PrivateKey06, &key, &[0x21; 20])
+        .unwrap();
+    assert_eq!(block.as_ref(), secret);
+    // GenDig over another key than ReadKey is refused.
+    let error = atca
+        .memory()
+        .read_slot_encrypted(Slot::PrivateKey07, 0, Slot::Data08, &key, &[0x22; 20])
+        .unwrap_err();
+    assert_eq!(error.status(), Some(error::Status::Execution));
+    // The second block would run past the end of the 36-byte slot.
+    let error = atca
+        .memory()
+        .read_slot_encrypted(Slot::PrivateKey07, 1, Slot::PrivateKey06, &key, &[0x23; 20])
+        .unwrap_err();
+    assert_eq!(error.kind(), Some(error::ErrorKind::BadParam));
+}
@@ -0,0 +1,36 @@
+// Tests run the client against the simulator, grouped by feature.
+mod aes;
+#[cfg(feature = "async")]
+mod asynch;
+mod datalink;
+mod ecc;
+mod memory;
+mod sha;
+mod temp_key;
+
+use super::*;
+use crate::config::KeyType;
+use crate::memory::Zone;
+use crate::AtCaClient;
+
+const AES_SLOT: Slot = Slot::PrivateKey05;
+
+fn tng(sim: &mut Simulator) {
+    AtCaClient::new(sim, NoDelay).tng().unwrap();
+}
+
+// Lock the zones with symmetric keys or data in the given slots.
+fn symmetric(sim: &mut Simulator, slots: &[(Slot, SlotConfig, &[u8])]) {
+    let mut atca = AtCaClient::new(sim, NoDelay);
+    let mut config = atca.memory().read_config_zone().unwrap();
+    for (slot, slot_config, _) in slots {
+        config.slot_config[*slot as usize] = *slot_config;
+        config.key_config[*slot as usize] = KeyConfig::from(0x0000).with_key_type(KeyType::Sha);
+    }
+    atca.memory().write_config_zone(&config).unwrap();
+    atca.memory().lock_config_verified(&config).unwrap();
+    for (slot, _, data) in slots {
+        atca.memory().write_slot(*slot, 0, data).unwrap();
+    }
+    atca.memory().lock(Zone::Data).unwrap();
+}
@@ -0,0 +1,127 @@
+use super::*;
+
+#[test]
+fn sha() {
+    let mut sim = Simulator::new();
+    let mut atca = AtCaClient::new(&mut sim, NoDelay);
+    for message in [&b"abc"[..], &[0xbc; 0x40 + 0x3f][..], &[][..]].iter() {
+        let digest = atca.sha(None).digest(message).unwrap();
+        assert_eq!(digest.as_ref(), &Sha256::digest(message)[..]);
+    }
+}
+
+#[test]
+fn sha_chunks() {
+    use crate::tngtls::Hasher;
+    use digest::{FixedOutput, Update};
+
+    // Xorshift, good enough to pick lengths.
+    let mut state = 0x2545_f491_u32;
+    let mut random = move |bound: usize| {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        state as usize % bound
+    };
+    let mut message = [0x00; 300];
+    message
+        .iter_mut()
+        .for_each(|byte| *byte = random(0x100) as u8);
+
+    let mut sim = Simulator::new();
+    let mut atca = AtCaClient::new(&mut sim, NoDelay);
+    for _ in 0..20 {
+        let length = random(message.len() + 1);
+        let expected = Sha256::digest(&message[..length]);
+        let mut sha = atca.sha(None);
+        sha.init().unwrap();
+        let mut rest = &message[..length];
+        while !rest.is_empty() {
+            let (chunk, tail) = rest.split_at(random(rest.len().min(130)) + 1);
+            sha.update(chunk).unwrap();
+            rest = tail;
+        }
+        assert_eq!(sha.finalize().unwrap().as_ref(), &expected[..]);
+        drop(sha);
+
+        let mut hasher = Hasher::from(atca.sha(None));
+        let mut rest = &message[..length];
+        while !rest.is_empty() {
+            let (chunk, tail) = rest.split_at(random(rest.len().min(130)) + 1);
+            hasher.update(chunk);
+            rest = tail;
+        }
+        assert_eq!(hasher.finalize_fixed_reset(), expected);
+        hasher.update(&message[..length]);
+        assert_eq!(hasher.finalize_fixed(), expected);
+    }
+}
+
+#[test]
+fn sha_context() {
+    let message = [0x3c; 150];
+    let expected = Sha256::digest(&message[..]);
+    let mut sim = Simulator::new();
+    let mut atca = AtCaClient::new(&mut sim, NoDelay);
+
+    let mut sha = atca.sha(None);
+    sha.init().unwrap();
+    sha.update(&message[..100]).unwrap();
+    let context = sha.save_context().unwrap();
+    drop(sha);
+
+    // Any other command wipes the calculation out.
+    atca.random().unwrap();
+    let mut sha = atca.sha(None);
+    sha.update(&message[100..]).unwrap();
+    assert!(sha.finalize().is_err());
+    drop(sha);
+
+    let mut sha = atca.sha(None);
+    sha.restore_context(&context).unwrap();
+    sha.update(&message[100..]).unwrap();
+    assert_eq!(sha.finalize().unwrap().as_ref(), &expected[..]);
+}
+
+#[test]
+fn hmac() {
+    let key = [0x5a; 32];
+    let mut sim = Simulator::new();
+    let key_config = SlotConfig::from(0x0000).with_is_secret(true);
+    symmetric(&mut sim, &[(Slot::PrivateKey06, key_config, &key)]);
+    let message = [0xa5; 150];
+    let inner = Sha256::new()
+        .chain([0x5a ^ 0x36; 32])
+        .chain([0x36; 32])
+        .chain(message)
+        .finalize();
+    let expected = Sha256::new()
+        .chain([0x5a ^ 0x5c; 32])
+        .chain([0x5c; 32])
+        .chain(inner)
+        .finalize();
+
+    let mut atca = AtCaClient::new(&mut sim, NoDelay);
+    let mut hmac = atca.hmac(Slot::PrivateKey06);
+    // Chunks not aligned to the block size are carried over.
+    hmac.update(&message[..10]).unwrap();
+    hmac.chain(&message[10..70])
+        .unwrap()
+        .update(&message[70..])
+        .unwrap();
+    assert_eq!(hmac.finalize().unwrap().as_ref(), &expected[..]);
+    assert_eq!(hmac.digest(&message).unwrap().as_ref(), &expected[..]);
+    hmac.chain(message)
+        .unwrap()
+        .finalize_into_temp_key()
+        .unwrap();
+    drop(hmac);
+    assert_eq!(sim.temp_key.unwrap().value(), &expected[..]);
+
+    // As a RustCrypto MAC.
+    let mut atca = AtCaClient::new(&mut sim, NoDelay);
+    let mut hmac = atca.hmac(Slot::PrivateKey06);
+    mac::Mac::update(&mut hmac, &message[..100]);
+    mac::Mac::update(&mut hmac, &message[100..]);
+    mac::Mac::verify_slice(hmac, &expected).unwrap();
+}
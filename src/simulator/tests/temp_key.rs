@@ -0,0 +1,168 @@
+use super::*;
+use crate::command::Block;
+use crate::error;
+use crate::host::{self, TempKey};
+use crate::NonceTarget;
+use core::convert::TryFrom;
+
+#[test]
+fn nonce() {
+    let mut sim = Simulator::new();
+    tng(&mut sim);
+    let mut atca = AtCaClient::new(&mut sim, NoDelay);
+    let num_in = [0x42; 20];
+    let rand_out = atca.nonce().random(&num_in, false).unwrap();
+    let temp_key = TempKey::random(&rand_out, &num_in, false);
+    atca.nonce()
+        .load(NonceTarget::MessageDigestBuffer, &[0x11; 64])
+        .unwrap();
+    atca.nonce()
+        .load(NonceTarget::AlternateKeyBuffer, &[0x22; 32])
+        .unwrap();
+    let error = atca
+        .nonce()
+        .load(NonceTarget::AlternateKeyBuffer, &[0x22; 64])
+        .unwrap_err();
+    assert_eq!(error.kind(), Some(error::ErrorKind::BadParam));
+    assert!(atca
+        .nonce()
+        .load(NonceTarget::TempKey, &[0x33; 20])
+        .is_err());
+    assert_eq!(sim.temp_key.unwrap().value(), temp_key.value());
+    assert_eq!(sim.message_digest_buffer, [0x11; 64]);
+    assert_eq!(sim.alternate_key_buffer, [0x22; 32]);
+
+    let mut atca = AtCaClient::new(&mut sim, NoDelay);
+    atca.nonce()
+        .load(NonceTarget::TempKey, &[0x33; 64])
+        .unwrap();
+    assert_eq!(sim.temp_key.unwrap().bytes(), &[0x33; 64]);
+    assert_eq!(sim.temp_key.unwrap().source(), Source::Input);
+}
+
+#[test]
+fn derive_key() {
+    let master_key = [0x5a; 32];
+    let rolling_key = [0x3c; 32];
+    let mut sim = Simulator::new();
+    let master_config = SlotConfig::from(0x0000).with_is_secret(true);
+    let created_config = SlotConfig::from(0x0000)
+        .with_is_secret(true)
+        .with_write_key(Slot::PrivateKey05 as u8)
+        .with_write_config(0x0b);
+    let rolled_config = SlotConfig::from(0x0000)
+        .with_is_secret(true)
+        .with_write_config(0x02);
+    symmetric(
+        &mut sim,
+        &[
+            (Slot::PrivateKey05, master_config, &master_key),
+            (Slot::PrivateKey06, created_config, &[]),
+            (Slot::PrivateKey07, rolled_config, &rolling_key),
+        ],
+    );
+    let mut atca = AtCaClient::new(&mut sim, NoDelay);
+    let serial_number = atca.memory().serial_number().unwrap();
+
+    // Create a key diversified from the master key and a fixed TempKey.
+    let temp_key = TempKey::fixed(&[0x11; 32]);
+    atca.nonce()
+        .load(NonceTarget::TempKey, temp_key.value())
+        .unwrap();
+    let target = Slot::PrivateKey06;
+    let mac = temp_key.derive_key_mac(target as u16, &master_key, &serial_number);
+    let error = atca
+        .derive_key(target, temp_key.source(), None)
+        .unwrap_err();
+    assert_eq!(error.status(), Some(error::Status::Parse));
+    let wrong_mac = Block::from([0xa5; 32]);
+    let error = atca
+        .derive_key(target, temp_key.source(), Some(&wrong_mac))
+        .unwrap_err();
+    assert_eq!(error.status(), Some(error::Status::CheckmacVerifyFailed));
+    // The mode has to reflect how TempKey was loaded.
+    let mac = Block::from(mac);
+    assert!(atca.derive_key(target, Source::Random, Some(&mac)).is_err());
+    atca.derive_key(target, temp_key.source(), Some(&mac))
+        .unwrap();
+
+    // Roll a key forward with a random nonce.
+    let num_in = [0x42; 20];
+    let rand_out = atca.nonce().random(&num_in, true).unwrap();
+    let rolled = TempKey::random(&rand_out, &num_in, true);
+    atca.derive_key(Slot::PrivateKey07, rolled.source(), None)
+        .unwrap();
+
+    let created = temp_key.derive_key(target as u16, &master_key, &serial_number);
+    assert_eq!(&sim.slot(target)[..32], &created);
+    let rolled = rolled.derive_key(Slot::PrivateKey07 as u16, &rolling_key, &serial_number);
+    assert_eq!(&sim.slot(Slot::PrivateKey07)[..32], &rolled);
+}
+
+#[test]
+fn mac() {
+    let key = [0x5a; 32];
+    let mut sim = Simulator::new();
+    let key_config = SlotConfig::from(0x0000).with_is_secret(true);
+    symmetric(&mut sim, &[(Slot::PrivateKey06, key_config, &key)]);
+    let serial_number = AtCaClient::new(&mut sim, NoDelay)
+        .memory()
+        .serial_number()
+        .unwrap();
+    let otp = [0x00; 11];
+    let mode = host::MAC_MODE_INCLUDE_SN;
+    let challenge = Block::from([0x3c; 32]);
+    let mut atca = AtCaClient::new(&mut sim, NoDelay);
+    let response = atca
+        .mac(Slot::PrivateKey06, Some(&challenge), mode)
+        .unwrap();
+    let response = Block::try_from(response.as_ref()).unwrap();
+    assert!(host::verify_mac(
+        mode,
+        Slot::PrivateKey06 as u16,
+        &key,
+        &challenge.into(),
+        &otp,
+        &serial_number,
+        &response.into()
+    ));
+    assert!(!host::verify_mac(
+        mode,
+        Slot::PrivateKey06 as u16,
+        &[0xa5; 32],
+        &challenge.into(),
+        &otp,
+        &serial_number,
+        &response.into()
+    ));
+    // TempKey is not loaded.
+    let error = atca
+        .mac(Slot::PrivateKey06, None, host::MAC_MODE_BLOCK2_TEMPKEY)
+        .unwrap_err();
+    assert_eq!(error.status(), Some(error::Status::Execution));
+    assert!(atca.mac(Slot::PrivateKey06, None, mode).is_err());
+
+    // The device checks a response computed elsewhere with the same key.
+    // OtherData carries the parameters of MAC and the serial number bytes
+    // CheckMac does not fill in.
+    let mut other_data = [0x00; 13];
+    other_data[..4].copy_from_slice(&[0x08, mode, Slot::PrivateKey06 as u8, 0x00]);
+    other_data[7..11].copy_from_slice(&serial_number.as_ref()[4..8]);
+    other_data[11..].copy_from_slice(&serial_number.as_ref()[2..4]);
+    let response = host::mac(
+        mode,
+        Slot::PrivateKey06 as u16,
+        &key,
+        &challenge.into(),
+        &otp,
+        &serial_number,
+    );
+    let response = Block::from(response);
+    assert!(atca
+        .check_mac(Slot::PrivateKey06, &challenge, &response, &other_data)
+        .unwrap());
+    let response = Block::from([0x00; 32]);
+    assert!(!atca
+        .check_mac(Slot::PrivateKey06, &challenge, &response, &other_data)
+        .unwrap());
+}
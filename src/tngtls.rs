@@ -6,16 +6,19 @@
 // Signer public key from signer certificate. 6. ECDH/KDF key slot capable of
 // being used with AES keys and commands. 7. X.509 Compressed Certificate
 // Storage.
-use super::client::{AtCaClient, Memory, Sha};
+use super::client::{AtCaClient, Sha};
+use super::config::ConfigZone;
+use super::datalink::Transport;
 use super::error::Error;
 use super::memory::{Size, Slot, Zone};
 use core::convert::TryFrom;
 use digest::{FixedOutputDirty, Reset, Update};
-use embedded_hal::delay::DelayNs;
-use embedded_hal::i2c;
 use generic_array::typenum::U32;
 use generic_array::GenericArray;
 
+/// I2C address of Trust&GO parts, 0x6A in datasheet notation.
+pub const I2C_ADDRESS: u8 = 0x6a >> 1;
+
 pub const AUTH_PRIVATE_KEY: Slot = Slot::PrivateKey00;
 pub const SIGN_PRIVATE_KEY: Slot = Slot::PrivateKey01;
 pub const USER_PRIVATE_KEY1: Slot = Slot::PrivateKey02;
@@ -27,48 +30,76 @@ pub const DEVICE_CERTIFICATE: Slot = Slot::Certificate0a;
 pub const SIGNER_PUBLIC_KEY: Slot = Slot::Certificate0b;
 pub const SIGNER_CERTIFICATE: Slot = Slot::Certificate0c;
 
-pub struct Hasher<'a, PHY, D>(Sha<'a, PHY, D>);
-impl<'a, PHY, D> From<Sha<'a, PHY, D>> for Hasher<'a, PHY, D> {
-    fn from(sha: Sha<'a, PHY, D>) -> Self {
-        Self(sha)
+// SHA-256 on the device behind the traits of `digest`. Device errors cause a
+// panic, as the traits are infallible. The calculation starts with the first
+// update and starts over after finalization.
+pub struct Hasher<'a, T>
+where
+    T: Transport,
+{
+    sha: Sha<'a, T>,
+    started: bool,
+}
+
+impl<'a, T> From<Sha<'a, T>> for Hasher<'a, T>
+where
+    T: Transport,
+{
+    fn from(sha: Sha<'a, T>) -> Self {
+        let started = false;
+        Self { sha, started }
+    }
+}
+
+impl<'a, T> Hasher<'a, T>
+where
+    T: Transport,
+{
+    fn start(&mut self) {
+        if !self.started {
+            self.sha.init().expect("init operation failed");
+            self.started = true;
+        }
     }
 }
 
-impl<'a, PHY, D> Update for Hasher<'a, PHY, D>
+impl<'a, T> Update for Hasher<'a, T>
 where
-    PHY: i2c::I2c,
-    D: DelayNs,
+    T: Transport,
 {
     fn update(&mut self, data: impl AsRef<[u8]>) {
-        self.0.update(data).expect("update operation failed");
+        self.start();
+        self.sha.update(data).expect("update operation failed");
     }
 }
 
-impl<'a, PHY, D> FixedOutputDirty for Hasher<'a, PHY, D>
+impl<'a, T> FixedOutputDirty for Hasher<'a, T>
 where
-    PHY: i2c::I2c,
-    D: DelayNs,
+    T: Transport,
 {
     type OutputSize = U32;
     fn finalize_into_dirty(&mut self, out: &mut GenericArray<u8, Self::OutputSize>) {
-        let digest = self.0.finalize().expect("finalize operation failed");
+        self.start();
+        self.started = false;
+        let digest = self.sha.finalize().expect("finalize operation failed");
         out.as_mut_slice().copy_from_slice(digest.as_ref());
     }
 }
 
-impl<'a, PHY, D> Reset for Hasher<'a, PHY, D>
+impl<'a, T> Reset for Hasher<'a, T>
 where
-    PHY: i2c::I2c,
-    D: DelayNs,
+    T: Transport,
 {
-    fn reset(&mut self) {}
+    fn reset(&mut self) {
+        self.started = false;
+    }
 }
 
-pub struct TrustAndGo<'a, PHY, D> {
-    atca: &'a mut AtCaClient<PHY, D>,
+pub struct TrustAndGo<'a, T> {
+    atca: &'a mut AtCaClient<T>,
 }
 
-impl<'a, PHY, D> TrustAndGo<'a, PHY, D> {
+impl<'a, T> TrustAndGo<'a, T> {
     // Miscellaneous device states.
     const TNG_TLS_SLOT_CONFIG_DATA: [u8; Size::Block as usize] = [
         // Index 20..=51, block = 0, offset = 5
@@ -109,10 +140,9 @@ impl<'a, PHY, D> TrustAndGo<'a, PHY, D> {
 }
 
 // Methods for preparing device state. Configuraion, random nonce and key creation and so on.
-impl<'a, PHY, D> TrustAndGo<'a, PHY, D>
+impl<'a, T> TrustAndGo<'a, T>
 where
-    PHY: i2c::I2c,
-    D: DelayNs,
+    T: Transport,
 {
     // Slot config
     pub fn configure_permissions(&mut self) -> Result<(), Error> {
@@ -120,7 +150,7 @@ where
             .chunks(Size::Word.len())
             .enumerate()
             .try_for_each(|(i, word)| {
-                let index = Memory::<PHY, D>::SLOT_CONFIG_INDEX + i * Size::Word.len();
+                let index = ConfigZone::SLOT_CONFIG_INDEX + i * Size::Word.len();
                 let (block, offset, _) = Zone::locate_index(index);
                 self.atca
                     .memory()
@@ -131,7 +161,7 @@ where
 
     // Chip options
     pub fn configure_chip_options(&mut self) -> Result<(), Error> {
-        let (block, offset, _) = Zone::locate_index(Memory::<PHY, D>::CHIP_OPTIONS_INDEX);
+        let (block, offset, _) = Zone::locate_index(ConfigZone::CHIP_OPTIONS_INDEX);
         self.atca
             .memory()
             .write_config(Size::Word, block, offset, &Self::TNG_TLS_CHIP_OPTIONS)
@@ -139,7 +169,7 @@ where
 
     // Key config
     pub fn configure_key_types(&mut self) -> Result<(), Error> {
-        let (block, offset, _) = Zone::locate_index(Memory::<PHY, D>::KEY_CONFIG_INDEX);
+        let (block, offset, _) = Zone::locate_index(ConfigZone::KEY_CONFIG_INDEX);
         self.atca
             .memory()
             .write_config(Size::Block, block, offset, &Self::TNG_TLS_KEY_CONFIG_DATA)
@@ -147,13 +177,12 @@ where
 }
 
 // On creation of TNG object, enforce stateful configuration.
-impl<'a, PHY, D> TryFrom<&'a mut AtCaClient<PHY, D>> for TrustAndGo<'a, PHY, D>
+impl<'a, T> TryFrom<&'a mut AtCaClient<T>> for TrustAndGo<'a, T>
 where
-    PHY: i2c::I2c,
-    D: DelayNs,
+    T: Transport,
 {
     type Error = Error;
-    fn try_from(atca: &'a mut AtCaClient<PHY, D>) -> Result<Self, Self::Error> {
+    fn try_from(atca: &'a mut AtCaClient<T>) -> Result<Self, Self::Error> {
         let mut tng = Self { atca };
         // Check if configuration zone is locked.
         if !tng.atca.memory().is_locked(Zone::Config)? {
@@ -179,18 +208,16 @@ where
 mod tests {
     use super::*;
     use crate::command::OpCode;
+    use crate::config::{KeyConfig, KeyType, SlotConfig};
     use core::convert::TryInto;
     use core::ops::Deref;
     use heapless::Vec;
     use OpCode::*;
-    const KEY_TYPE_P256: u16 = 0x04; // P256 NIST ECC key
-    const KEY_TYPE_AES: u16 = 0x06; // AES-128 Key
-    const KEY_TYPE_SHA: u16 = 0x07; // SHA key or other data
 
     struct Provision {
         key_id: Slot,
-        permission: u16,
-        key_config: u16,
+        permission: SlotConfig,
+        key_config: KeyConfig,
     }
 
     impl Provision {
@@ -204,32 +231,8 @@ mod tests {
             }
         }
 
-        fn is_private(&self) -> bool {
-            self.key_config & 0x01 != 0x00
-        }
-
-        fn key_type(&self) -> u16 {
-            (self.key_config >> 2) & 0x07
-        }
-
-        fn read_key(&self) -> u16 {
-            self.permission & 0x0f
-        }
-
-        fn encrypt_read(&self) -> bool {
-            (self.permission >> 6) & 0x01 != 0x00
-        }
-
-        fn is_secret(&self) -> bool {
-            (self.permission >> 7) & 0x01 != 0x00
-        }
-
-        fn write_config(&self) -> u16 {
-            (self.permission >> 12) & 0x0f
-        }
-
         fn read_permission(&self) -> &str {
-            match (self.is_secret(), self.encrypt_read()) {
+            match (self.permission.is_secret(), self.permission.encrypt_read()) {
                 (false, false) => "Clear text",
                 (true, false) => "Never",
                 (true, true) => "Encrypted",
@@ -238,7 +241,7 @@ mod tests {
         }
 
         fn write_permission(&self) -> &str {
-            match self.write_config() {
+            match self.permission.write_config() {
                 0x00 => "Clear text",
                 0x01 => "PubInvalid",
                 x if (x >> 1) == 0x01 => "Never",
@@ -250,19 +253,22 @@ mod tests {
 
         // Random nonce
         fn require_nonce(&self) -> bool {
-            (self.key_config >> 6) & 0x01 != 0x00
+            self.key_config.req_random()
         }
 
         // Commands that returns its output to the slot.
         fn creation_commands(&self) -> Vec<OpCode, 5> {
             let mut commands = Vec::<OpCode, 5>::new();
-            if self.key_id.is_private_key() && self.key_type() == KEY_TYPE_P256 && self.is_secret()
+            let write_config = self.permission.write_config();
+            if self.key_id.is_private_key()
+                && self.key_config.key_type() == KeyType::P256
+                && self.permission.is_secret()
             {
-                if (self.write_config() >> 1) & 0x01 == 0x01 {
+                if (write_config >> 1) & 0x01 == 0x01 {
                     commands.push(GenKey).unwrap();
                     commands.push(DeriveKey).unwrap();
                 }
-                if (self.write_config() >> 2) & 0x01 == 0x01 {
+                if (write_config >> 2) & 0x01 == 0x01 {
                     commands.push(PrivWrite).unwrap();
                 }
             }
@@ -274,10 +280,10 @@ mod tests {
         fn operation_commands(&self) -> &[OpCode] {
             let mut commands = Vec::<OpCode, 5>::new();
             if self.key_id.is_private_key() {
-                if (self.read_key() >> 2) & 0x01 == 0x01 {
+                if (self.permission.read_key() >> 2) & 0x01 == 0x01 {
                     commands.push(Ecdh).unwrap();
                 }
-                if self.is_secret() && self.key_type() == KEY_TYPE_P256 {
+                if self.permission.is_secret() && self.key_config.key_type() == KeyType::P256 {
                     commands.push(Sign).unwrap();
                 }
                 unimplemented!()
@@ -287,23 +293,23 @@ mod tests {
         }
     }
 
-    fn permission(key_id: Slot) -> u16 {
-        let data = &TrustAndGo::<(), ()>::TNG_TLS_SLOT_CONFIG_DATA;
+    fn permission(key_id: Slot) -> SlotConfig {
+        let data = &TrustAndGo::<()>::TNG_TLS_SLOT_CONFIG_DATA;
         let index = key_id as usize * 2;
         let range = index..index + 2;
         data[range]
             .try_into()
-            .map(u16::from_le_bytes)
+            .map(SlotConfig::from_le_bytes)
             .unwrap_or_else(|_| unreachable!())
     }
 
-    fn key_config(key_id: Slot) -> u16 {
-        let data = &TrustAndGo::<(), ()>::TNG_TLS_KEY_CONFIG_DATA;
+    fn key_config(key_id: Slot) -> KeyConfig {
+        let data = &TrustAndGo::<()>::TNG_TLS_KEY_CONFIG_DATA;
         let index = key_id as usize * 2;
         let range = index..index + 2;
         data[range]
             .try_into()
-            .map(u16::from_le_bytes)
+            .map(KeyConfig::from_le_bytes)
             .unwrap_or_else(|_| unreachable!())
     }
 
@@ -313,59 +319,59 @@ mod tests {
     #[test]
     fn provision() {
         let auth_priv = Provision::new(AUTH_PRIVATE_KEY);
-        assert_eq!(true, auth_priv.is_private());
-        assert_eq!(KEY_TYPE_P256, auth_priv.key_type());
+        assert_eq!(true, auth_priv.key_config.private());
+        assert_eq!(KeyType::P256, auth_priv.key_config.key_type());
         assert_eq!(true, auth_priv.require_nonce());
-        assert_eq!(0x05, auth_priv.read_key());
+        assert_eq!(0x05, auth_priv.permission.read_key());
         assert_eq!("Clear text", auth_priv.write_permission());
         assert_eq!(0, auth_priv.creation_commands().len());
 
         let sign_priv = Provision::new(SIGN_PRIVATE_KEY);
-        assert_eq!(true, sign_priv.is_private());
-        assert_eq!(KEY_TYPE_P256, sign_priv.key_type());
+        assert_eq!(true, sign_priv.key_config.private());
+        assert_eq!(KeyType::P256, sign_priv.key_config.key_type());
         assert_eq!(true, sign_priv.require_nonce());
-        assert_eq!(0x02, sign_priv.read_key());
+        assert_eq!(0x02, sign_priv.permission.read_key());
         assert_eq!("Clear text", sign_priv.write_permission());
         assert_eq!(0, sign_priv.creation_commands().len());
 
         for key_id in [USER_PRIVATE_KEY1, USER_PRIVATE_KEY2, USER_PRIVATE_KEY3].iter() {
             let user_priv = Provision::new(*key_id);
-            assert_eq!(true, user_priv.is_private());
-            assert_eq!(KEY_TYPE_P256, user_priv.key_type());
+            assert_eq!(true, user_priv.key_config.private());
+            assert_eq!(KeyType::P256, user_priv.key_config.key_type());
             assert_eq!(true, user_priv.require_nonce());
-            assert_eq!(0x05, user_priv.read_key());
+            assert_eq!(0x05, user_priv.permission.read_key());
             assert_eq!("Never", user_priv.write_permission());
             assert_eq!(&[GenKey, DeriveKey], user_priv.creation_commands().deref());
         }
 
         let io_protect = Provision::new(IO_PROTECTION_KEY);
-        assert_eq!(KEY_TYPE_SHA, io_protect.key_type());
+        assert_eq!(KeyType::Sha, io_protect.key_config.key_type());
         assert_eq!("Clear text", io_protect.write_permission());
         assert_eq!(true, io_protect.require_nonce());
         assert_eq!(0, io_protect.creation_commands().len());
 
         let aes_key = Provision::new(AES_KEY);
-        assert_eq!(KEY_TYPE_AES, aes_key.key_type());
+        assert_eq!(KeyType::Aes, aes_key.key_config.key_type());
         assert_eq!("Never", aes_key.read_permission());
         assert_eq!("Clear text", aes_key.write_permission());
-        assert_eq!(0x00, aes_key.write_config());
+        assert_eq!(0x00, aes_key.permission.write_config());
 
         let device_cert = Provision::new(DEVICE_CERTIFICATE);
-        assert_eq!(KEY_TYPE_SHA, device_cert.key_type());
+        assert_eq!(KeyType::Sha, device_cert.key_config.key_type());
         assert_eq!("Clear text", device_cert.read_permission());
         assert_eq!("Never", device_cert.write_permission());
-        assert_eq!(0x08, device_cert.write_config());
+        assert_eq!(0x08, device_cert.permission.write_config());
 
         let signer_pub = Provision::new(SIGNER_PUBLIC_KEY);
-        assert_eq!(KEY_TYPE_P256, signer_pub.key_type());
+        assert_eq!(KeyType::P256, signer_pub.key_config.key_type());
         assert_eq!("Clear text", signer_pub.read_permission());
         assert_eq!("Never", signer_pub.write_permission());
-        assert_eq!(0x08, signer_pub.write_config());
+        assert_eq!(0x08, signer_pub.permission.write_config());
 
         let signer_cert = Provision::new(SIGNER_CERTIFICATE);
-        assert_eq!(KEY_TYPE_SHA, signer_cert.key_type());
+        assert_eq!(KeyType::Sha, signer_cert.key_config.key_type());
         assert_eq!("Clear text", signer_cert.read_permission());
         assert_eq!("Never", signer_cert.write_permission());
-        assert_eq!(0x08, signer_cert.write_config());
+        assert_eq!(0x08, signer_cert.permission.write_config());
     }
 }